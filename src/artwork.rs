@@ -0,0 +1,199 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    time::UNIX_EPOCH,
+};
+
+use image::{DynamicImage, ImageFormat, ImageResult};
+use symphonia::core::meta::{StandardVisualKey, Visual};
+use tracing::warn;
+
+use crate::{config::Config, errors::ArtworkError};
+
+/// The maximum width and height of cached thumbnails, in pixels.
+pub const THUMBNAIL_SIZE: u32 = 256;
+
+/// File names (compared case-insensitively) checked for album art in a song's directory, in order of preference.
+pub const FOLDER_ART_NAMES: &[&str] = &[
+    "cover.jpg",
+    "cover.jpeg",
+    "cover.png",
+    "folder.jpg",
+    "folder.jpeg",
+    "folder.png",
+    "front.jpg",
+    "front.png",
+];
+
+/// Gets the default thumbnail cache directory (`artwork` in [`Config::default_path`]).
+pub fn cache_dir() -> PathBuf {
+    let mut path = Config::default_path();
+    path.push("artwork");
+    path
+}
+
+/// Pick the front cover from `visuals`, or the first visual if none is marked as the front cover.
+pub fn front_cover(visuals: &[Visual]) -> Option<&Visual> {
+    visuals
+        .iter()
+        .find(|visual| visual.usage == Some(StandardVisualKey::FrontCover))
+        .or(visuals.first())
+}
+
+/// Find the album art file in `dir`, see [`FOLDER_ART_NAMES`].
+pub fn folder_art(dir: &Path) -> Option<PathBuf> {
+    let mut found: Vec<(usize, PathBuf)> = dir
+        .read_dir()
+        .ok()?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?.to_lowercase();
+            let rank = FOLDER_ART_NAMES.iter().position(|art| *art == name)?;
+            Some((rank, path))
+        })
+        .collect();
+    found.sort_by_key(|(rank, _)| *rank);
+    found.into_iter().next().map(|(_, path)| path)
+}
+
+/// Get the thumbnail of embedded album art, creating it in `cache_dir` if it isn't cached yet.
+///
+/// Thumbnails are keyed by the image data, so songs from the same album share one.
+pub fn embedded_thumbnail(data: &[u8], cache_dir: &Path) -> Result<PathBuf, ArtworkError> {
+    thumbnail(cache_dir, stable_hash(&[data]), || {
+        image::load_from_memory(data)
+    })
+}
+
+/// Get the thumbnail of the image file at `path`, creating it in `cache_dir` if it isn't cached yet.
+///
+/// Thumbnails are keyed by the path, size and modification time, so a changed image gets a new thumbnail.
+pub fn file_thumbnail(path: &Path, cache_dir: &Path) -> Result<PathBuf, ArtworkError> {
+    let metadata = path.metadata()?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let key = stable_hash(&[
+        path.as_os_str().as_encoded_bytes(),
+        &metadata.len().to_le_bytes(),
+        &modified.as_nanos().to_le_bytes(),
+    ]);
+    thumbnail(cache_dir, key, || image::open(path))
+}
+
+/// Get the thumbnail for a song at `song_path` in `cache_dir`, from its embedded art if it has any,
+/// or else from the [`folder_art`] next to it.
+///
+/// Errors are logged and treated as the song having no art.
+pub fn song_thumbnail(
+    embedded: Option<&[u8]>,
+    song_path: &Path,
+    cache_dir: &Path,
+) -> Option<PathBuf> {
+    let result = match embedded {
+        Some(data) => embedded_thumbnail(data, cache_dir),
+        None => file_thumbnail(&folder_art(song_path.parent()?)?, cache_dir),
+    };
+    result
+        .inspect_err(|e| {
+            warn!(
+                "Error reading album art for '{}': {}",
+                song_path.display(),
+                e
+            )
+        })
+        .ok()
+}
+
+/// A 64-bit FNV-1a hash of `parts`, which unlike [`std::hash::DefaultHasher`] is the same on every run and Rust version,
+/// so cached thumbnails are found again.
+fn stable_hash(parts: &[&[u8]]) -> u64 {
+    parts
+        .iter()
+        .flat_map(|part| part.iter())
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+fn thumbnail(
+    cache_dir: &Path,
+    key: u64,
+    load: impl FnOnce() -> ImageResult<DynamicImage>,
+) -> Result<PathBuf, ArtworkError> {
+    let path = cache_dir.join(format!("{key:016x}.jpg"));
+    if path.is_file() {
+        return Ok(path);
+    }
+    let image = load()?;
+    fs::create_dir_all(cache_dir)?;
+    // Songs of an album are read on several threads and share their art, so the thumbnail is written to a file
+    // of its own and then moved into place, and a half written thumbnail is never found
+    static TEMP_ID: AtomicU64 = AtomicU64::new(0);
+    let temp_path = cache_dir.join(format!(
+        "{key:016x}.{}-{}.tmp",
+        process::id(),
+        TEMP_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+    let saved = image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .to_rgb8()
+        .save_with_format(&temp_path, ImageFormat::Jpeg)
+        .map_err(ArtworkError::from)
+        .and_then(|()| Ok(fs::rename(&temp_path, &path)?));
+    if saved.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    saved.map(|()| path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::RgbImage;
+
+    use super::*;
+
+    #[test]
+    fn test_embedded_thumbnail() {
+        let cache_dir = std::env::temp_dir().join("amuseing-test-artwork");
+        let _ = fs::remove_dir_all(&cache_dir);
+        let mut data = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(1024, 512))
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+
+        let path = embedded_thumbnail(&data, &cache_dir).unwrap();
+        let thumbnail = image::open(&path).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (256, 128));
+        // The second call finds the cached thumbnail
+        assert_eq!(embedded_thumbnail(&data, &cache_dir).unwrap(), path);
+        fs::remove_dir_all(&cache_dir).unwrap();
+    }
+
+    #[test]
+    fn test_stable_hash() {
+        // Known FNV-1a values, so thumbnail names never change
+        assert_eq!(stable_hash(&[]), 0xcbf29ce484222325);
+        assert_eq!(stable_hash(&[b"a"]), 0xaf63dc4c8601ec8c);
+        assert_eq!(stable_hash(&[b"foo", b"bar"]), stable_hash(&[b"foobar"]));
+    }
+
+    #[test]
+    fn test_folder_art() {
+        let dir = std::env::temp_dir().join("amuseing-test-folder-art");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        assert_eq!(folder_art(&dir), None);
+        fs::write(dir.join("Folder.png"), []).unwrap();
+        fs::write(dir.join("song.mp3"), []).unwrap();
+        assert_eq!(folder_art(&dir), Some(dir.join("Folder.png")));
+        fs::write(dir.join("cover.jpg"), []).unwrap();
+        assert_eq!(folder_art(&dir), Some(dir.join("cover.jpg")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,461 @@
+//! Chapters of audiobooks and long mixes, from ID3v2 CHAP and CTOC frames, or from Nero chapters (`chpl`) and
+//! QuickTime chapter tracks in MP4 files.
+
+use std::{
+    fs,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ChaptersError;
+
+/// A chapter of a song, which goes on until the next chapter starts.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chapter {
+    pub title: String,
+    pub start: Duration,
+}
+
+/// Read the chapters of the song at `path`, sorted by their start, an empty list if it has none.
+pub fn read(path: &Path) -> Result<Vec<Chapter>, ChaptersError> {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "mp3" | "aac" => Ok(id3::no_tag_ok(id3::Tag::read_from_path(path))?
+            .map(|tag| from_id3(&tag))
+            .unwrap_or_default()),
+        "m4a" | "m4b" | "mp4" => from_mp4(path),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// The index of the chapter at `position`, None before the first chapter or if there are no chapters.
+pub fn chapter_at(chapters: &[Chapter], position: Duration) -> Option<usize> {
+    chapters
+        .partition_point(|chapter| chapter.start <= position)
+        .checked_sub(1)
+}
+
+/// The chapters in an ID3v2 tag.
+///
+/// The top level table of contents (CTOC) decides which chapters are used, if there is one.
+/// Chapters without a title (a TIT2 frame in the CHAP frame) are numbered.
+fn from_id3(tag: &id3::Tag) -> Vec<Chapter> {
+    let top_level = tag.tables_of_contents().find(|toc| toc.top_level);
+    let mut frames: Vec<&id3::frame::Chapter> = match top_level {
+        Some(toc) => toc
+            .elements
+            .iter()
+            .filter_map(|id| tag.chapters().find(|chapter| chapter.element_id == *id))
+            .collect(),
+        None => Vec::new(),
+    };
+    if frames.is_empty() {
+        frames = tag.chapters().collect();
+    }
+    let chapters = frames
+        .into_iter()
+        .map(|chapter| {
+            let title = chapter
+                .frames
+                .iter()
+                .find(|frame| frame.id() == "TIT2")
+                .and_then(|frame| frame.content().text())
+                .map(|title| title.trim().to_string());
+            (title, Duration::from_millis(chapter.start_time as u64))
+        })
+        .collect();
+    numbered(chapters)
+}
+
+/// The chapters of the MP4 file at `path`, the Nero chapters in `moov/udta/chpl` or else a QuickTime chapter track.
+///
+/// Only the `moov` atom and the chapter track's titles are read, so the audio data of long files isn't.
+fn from_mp4(path: &Path) -> Result<Vec<Chapter>, ChaptersError> {
+    let mut file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut pos = 0;
+    while pos + 8 <= len {
+        file.seek(SeekFrom::Start(pos))?;
+        let mut header = [0; 16];
+        file.read_exact(&mut header[..8])?;
+        let (header_len, size) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            // The atom goes on until the end of the file
+            0 => (8, len - pos),
+            1 => {
+                file.read_exact(&mut header[8..])?;
+                (16, u64::from_be_bytes(header[8..].try_into().unwrap()))
+            }
+            size => (8, size as u64),
+        };
+        if size < header_len || size > len - pos {
+            break;
+        }
+        if &header[4..8] == b"moov" {
+            let mut moov = vec![0; (size - header_len) as usize];
+            file.read_exact(&mut moov)?;
+            if let Some(chapters) = atom_path(&moov, &[b"udta", b"chpl"]).and_then(parse_chpl) {
+                return Ok(chapters);
+            }
+            return from_chapter_track(&mut file, &moov);
+        }
+        pos += size;
+    }
+    Ok(Vec::new())
+}
+
+/// The kinds and bodies of the atoms in `data`, up to the first one that's cut off.
+fn atoms(data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let (header_len, size) = match be_u32(data, pos)? {
+            0 => (8, data.len() - pos),
+            1 => (16, usize::try_from(be_u64(data, pos + 8)?).ok()?),
+            size => (8, size as usize),
+        };
+        if size < header_len || size > data.len() - pos {
+            return None;
+        }
+        let atom = (&data[pos + 4..pos + 8], &data[pos + header_len..pos + size]);
+        pos += size;
+        Some(atom)
+    })
+}
+
+/// The body of the first atom of `kind` in `data`.
+fn child_atom<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    atoms(data)
+        .find(|(atom_kind, _)| atom_kind == kind)
+        .map(|(_, body)| body)
+}
+
+/// The body of the atom at `path` in `data`, e.g. `[b"udta", b"chpl"]`.
+fn atom_path<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    path.iter()
+        .try_fold(data, |data, kind| child_atom(data, kind))
+}
+
+fn be_u32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(pos..pos + 4)?.try_into().unwrap(),
+    ))
+}
+
+fn be_u64(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        data.get(pos..pos + 8)?.try_into().unwrap(),
+    ))
+}
+
+/// At most this many chapters are read from a chapter track, so a broken file can't make a huge list.
+const MAX_TRACK_CHAPTERS: usize = 10_000;
+
+/// The chapters in the QuickTime chapter track of an MP4 file, the text track that a track points to in `tref/chap`.
+///
+/// The chapters' titles are the track's text samples, which are read from `file`.
+fn from_chapter_track(file: &mut fs::File, moov: &[u8]) -> Result<Vec<Chapter>, ChaptersError> {
+    let traks: Vec<&[u8]> = atoms(moov)
+        .filter(|(kind, _)| kind == b"trak")
+        .map(|(_, body)| body)
+        .collect();
+    let chapter_trak = traks
+        .iter()
+        .find_map(|trak| be_u32(atom_path(trak, &[b"tref", b"chap"])?, 0))
+        .and_then(|id| traks.iter().find(|trak| track_id(trak) == Some(id)));
+    let Some(samples) = chapter_trak.and_then(|trak| text_samples(trak)) else {
+        return Ok(Vec::new());
+    };
+    let mut chapters = Vec::with_capacity(samples.len());
+    for (start, offset, size) in samples {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut sample = Vec::new();
+        file.by_ref().take(size as u64).read_to_end(&mut sample)?;
+        chapters.push((parse_text_sample(&sample), start));
+    }
+    Ok(numbered(chapters))
+}
+
+/// The ID of the track in `trak`, from its `tkhd` atom.
+fn track_id(trak: &[u8]) -> Option<u32> {
+    let tkhd = child_atom(trak, b"tkhd")?;
+    // Version 1 has 64 bit creation and modification times
+    be_u32(tkhd, if *tkhd.first()? == 1 { 20 } else { 12 })
+}
+
+/// The start, file offset and size of each sample of the track in `trak`, from its sample table.
+fn text_samples(trak: &[u8]) -> Option<Vec<(Duration, u64, u32)>> {
+    let mdia = child_atom(trak, b"mdia")?;
+    let mdhd = child_atom(mdia, b"mdhd")?;
+    let timescale = be_u32(mdhd, if *mdhd.first()? == 1 { 20 } else { 12 })?;
+    if timescale == 0 {
+        return None;
+    }
+    let stbl = atom_path(mdia, &[b"minf", b"stbl"])?;
+
+    let stsz = child_atom(stbl, b"stsz")?;
+    let fixed_size = be_u32(stsz, 4)?;
+    let count = (be_u32(stsz, 8)? as usize).min(MAX_TRACK_CHAPTERS);
+    let size = |i: usize| match fixed_size {
+        0 => be_u32(stsz, 12 + 4 * i),
+        size => Some(size),
+    };
+
+    // The time to sample table has runs of samples with the same duration
+    let stts = child_atom(stbl, b"stts")?;
+    let mut starts = Vec::with_capacity(count);
+    let mut time: u64 = 0;
+    'runs: for run in 0..be_u32(stts, 4)? as usize {
+        let (samples, duration) = (be_u32(stts, 8 + 8 * run)?, be_u32(stts, 12 + 8 * run)?);
+        for _ in 0..samples {
+            if starts.len() == count {
+                break 'runs;
+            }
+            let nanos = time as u128 * 1_000_000_000 / timescale as u128;
+            starts.push(Duration::from_nanos(nanos.try_into().ok()?));
+            time = time.saturating_add(duration as u64);
+        }
+    }
+
+    let chunk_offsets: Vec<u64> = match (child_atom(stbl, b"stco"), child_atom(stbl, b"co64")) {
+        (Some(stco), _) => (0..be_u32(stco, 4)? as usize)
+            .map(|i| be_u32(stco, 8 + 4 * i).map(u64::from))
+            .collect::<Option<_>>()?,
+        (None, Some(co64)) => (0..be_u32(co64, 4)? as usize)
+            .map(|i| be_u64(co64, 8 + 8 * i))
+            .collect::<Option<_>>()?,
+        (None, None) => return None,
+    };
+    // The sample to chunk table has runs of chunks with the same number of samples, by their first chunk
+    let stsc = child_atom(stbl, b"stsc")?;
+    let stsc_runs: Vec<(u32, u32)> = (0..be_u32(stsc, 4)? as usize)
+        .map(|run| Some((be_u32(stsc, 8 + 12 * run)?, be_u32(stsc, 12 + 12 * run)?)))
+        .collect::<Option<_>>()?;
+
+    let mut samples = Vec::with_capacity(starts.len());
+    for (chunk, mut offset) in chunk_offsets.into_iter().enumerate() {
+        let samples_in_chunk = stsc_runs
+            .iter()
+            .rev()
+            .find(|(first_chunk, _)| *first_chunk as usize <= chunk + 1)
+            .map_or(0, |(_, samples)| *samples);
+        for _ in 0..samples_in_chunk {
+            let Some(&start) = starts.get(samples.len()) else {
+                return Some(samples);
+            };
+            let size = size(samples.len())?;
+            samples.push((start, offset, size));
+            offset += size as u64;
+        }
+    }
+    Some(samples)
+}
+
+/// The text of a sample of a QuickTime text track, a 16 bit length followed by UTF-8 or UTF-16 text.
+fn parse_text_sample(sample: &[u8]) -> Option<String> {
+    let len = u16::from_be_bytes(sample.get(..2)?.try_into().unwrap()) as usize;
+    let text = sample.get(2..2 + len)?;
+    let text = match text.strip_prefix(&[0xfe, 0xff]) {
+        Some(utf16) => String::from_utf16_lossy(
+            &utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect::<Vec<u16>>(),
+        ),
+        None => String::from_utf8_lossy(text).into_owned(),
+    };
+    Some(text.trim().to_string())
+}
+
+/// Parse the body of a `chpl` atom, whose chapter starts are in units of 100 nanoseconds.
+fn parse_chpl(chpl: &[u8]) -> Option<Vec<Chapter>> {
+    // Version 1 has 4 more bytes after the version and flags
+    let mut pos = if *chpl.first()? > 0 { 8 } else { 4 };
+    let count = *chpl.get(pos)?;
+    pos += 1;
+    let mut chapters = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let start = u64::from_be_bytes(chpl.get(pos..pos + 8)?.try_into().unwrap());
+        let title_len = *chpl.get(pos + 8)? as usize;
+        let title = chpl.get(pos + 9..pos + 9 + title_len)?;
+        pos += 9 + title_len;
+        chapters.push((
+            Some(String::from_utf8_lossy(title).trim().to_string()),
+            Duration::from_nanos(start.saturating_mul(100)),
+        ));
+    }
+    Some(numbered(chapters))
+}
+
+/// Sort chapters by their start, and number the ones without a title.
+fn numbered(mut chapters: Vec<(Option<String>, Duration)>) -> Vec<Chapter> {
+    chapters.sort_by_key(|(_, start)| *start);
+    chapters
+        .into_iter()
+        .enumerate()
+        .map(|(i, (title, start))| Chapter {
+            title: title
+                .filter(|title| !title.is_empty())
+                .unwrap_or_else(|| format!("Chapter {}", i + 1)),
+            start,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use id3::{
+        frame::{Chapter as ChapterFrame, TableOfContents},
+        Frame, TagLike,
+    };
+
+    use super::*;
+
+    fn chapter(title: &str, millis: u64) -> Chapter {
+        Chapter {
+            title: title.into(),
+            start: Duration::from_millis(millis),
+        }
+    }
+
+    fn chapter_frame(id: &str, start_time: u32, title: Option<&str>) -> ChapterFrame {
+        ChapterFrame {
+            element_id: id.into(),
+            start_time,
+            end_time: start_time + 1000,
+            start_offset: u32::MAX,
+            end_offset: u32::MAX,
+            frames: title
+                .map(|title| Frame::text("TIT2", title))
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_from_id3() {
+        let mut tag = id3::Tag::new();
+        tag.add_frame(chapter_frame("ch2", 60_000, Some("Second")));
+        tag.add_frame(chapter_frame("ch1", 0, None));
+        tag.add_frame(chapter_frame("extra", 30_000, Some("Not listed")));
+        assert_eq!(
+            from_id3(&tag),
+            vec![
+                chapter("Chapter 1", 0),
+                chapter("Not listed", 30_000),
+                chapter("Second", 60_000)
+            ]
+        );
+
+        tag.add_frame(TableOfContents {
+            element_id: "toc".into(),
+            top_level: true,
+            ordered: true,
+            elements: vec!["ch1".into(), "ch2".into()],
+            frames: Vec::new(),
+        });
+        let chapters = from_id3(&tag);
+        assert_eq!(
+            chapters,
+            vec![chapter("Chapter 1", 0), chapter("Second", 60_000)]
+        );
+        assert_eq!(chapter_at(&chapters, Duration::from_secs(59)), Some(0));
+        assert_eq!(chapter_at(&chapters, Duration::from_secs(60)), Some(1));
+        assert_eq!(chapter_at(&[], Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn test_read_mp4() {
+        let dir = std::env::temp_dir().join("amuseing-test-chapters");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let atom = |kind: &[u8], body: &[u8]| {
+            let mut atom = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+            atom.extend_from_slice(kind);
+            atom.extend_from_slice(body);
+            atom
+        };
+        let mut chpl = vec![1, 0, 0, 0, 0, 0, 0, 0, 2];
+        for (start, title) in [(0u64, "Intro"), (600_000_000, "")] {
+            chpl.extend_from_slice(&start.to_be_bytes());
+            chpl.push(title.len() as u8);
+            chpl.extend_from_slice(title.as_bytes());
+        }
+        let udta = atom(b"udta", &atom(b"chpl", &chpl));
+        let mut file = atom(b"ftyp", b"M4A \0\0\0\0");
+        file.extend(atom(b"mdat", &[0; 32]));
+        file.extend(atom(b"moov", &[atom(b"mvhd", &[0; 8]), udta].concat()));
+        let path = dir.join("book.m4a");
+        fs::write(&path, file).unwrap();
+
+        assert_eq!(
+            read(&path).unwrap(),
+            vec![chapter("Intro", 0), chapter("Chapter 2", 60_000)]
+        );
+
+        // A QuickTime chapter track, the audio track is 1 and the text track with the titles is 2
+        let mut titles = Vec::new();
+        for title in ["Opening", "Part two"] {
+            titles.extend_from_slice(&(title.len() as u16).to_be_bytes());
+            titles.extend_from_slice(title.as_bytes());
+        }
+        let ftyp = atom(b"ftyp", b"M4B \0\0\0\0");
+        // The titles are the first thing in the mdat atom, right after the ftyp atom
+        let titles_offset = ftyp.len() as u32 + 8;
+        let tkhd = |id: u32| atom(b"tkhd", &[[0; 12].as_slice(), &id.to_be_bytes()].concat());
+        let table = |kind: &[u8], entries: &[u32]| {
+            let body: Vec<u8> = entries.iter().flat_map(|n| n.to_be_bytes()).collect();
+            atom(kind, &[[0; 4].as_slice(), &body].concat())
+        };
+        let stbl = [
+            // 2 samples, 30 seconds long each
+            table(b"stts", &[1, 2, 30_000]),
+            table(b"stsz", &[0, 2, 9, 10]),
+            table(b"stsc", &[1, 1, 2, 1]),
+            table(b"stco", &[1, titles_offset]),
+        ]
+        .concat();
+        let mdhd = atom(
+            b"mdhd",
+            &[[0; 12].as_slice(), &1000u32.to_be_bytes()].concat(),
+        );
+        let text_trak = atom(
+            b"trak",
+            &[
+                tkhd(2),
+                atom(
+                    b"mdia",
+                    &[mdhd, atom(b"minf", &atom(b"stbl", &stbl))].concat(),
+                ),
+            ]
+            .concat(),
+        );
+        let audio_trak = atom(
+            b"trak",
+            &[tkhd(1), atom(b"tref", &atom(b"chap", &2u32.to_be_bytes()))].concat(),
+        );
+        let mut file = ftyp;
+        file.extend(atom(b"mdat", &titles));
+        file.extend(atom(b"moov", &[audio_trak, text_trak].concat()));
+        let path = dir.join("book.m4b");
+        fs::write(&path, file).unwrap();
+
+        assert_eq!(
+            read(&path).unwrap(),
+            vec![chapter("Opening", 0), chapter("Part two", 30_000)]
+        );
+
+        // A 64 bit size that would overflow when added to the position
+        let mut file = atom(b"ftyp", b"M4B \0\0\0\0");
+        file.extend_from_slice(&1u32.to_be_bytes());
+        file.extend_from_slice(b"moov");
+        file.extend_from_slice(&u64::MAX.to_be_bytes());
+        fs::write(&path, file).unwrap();
+        assert_eq!(read(&path).unwrap(), Vec::new());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,30 +1,60 @@
 use std::{
     ops::{Deref, DerefMut},
+    path::Path,
     sync::mpsc::Receiver,
     time::Duration,
 };
 
 use amuseing::{
+    artwork,
     config::Config,
-    playback::{Player, PlayerUpdate, Playlist, Song},
+    history::{History, Listen},
+    library::{unix_now, Library, SmartPlaylist, SongStats},
+    playback::{Player, PlayerUpdate, Playlist, PlaylistSongs, Song},
+    playlist_file::{self, PlaylistEntry, PlaylistFormat},
+    radio::LibraryRadio,
+    scanner::{ScanEvent, ScanHandle, Scanner},
+    session::Session,
+    watcher::{LibraryWatcher, WatchEvent, DEFAULT_DEBOUNCE},
 };
 use dioxus::{logger::tracing, prelude::*};
 
+use super::{
+    chapters::ChaptersPanel, library::LibraryPanel, lyrics::LyricsPanel,
+    smart_playlist::SmartPlaylistPanel,
+};
+
+/// The panel shown on the right, next to the songs.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum SidePanel {
+    #[default]
+    Queue,
+    Lyrics,
+    Chapters,
+}
+
 #[derive(Copy, Clone, Debug)]
-struct AppContext {
-    player: Signal<Player>,
+pub(super) struct AppContext {
+    pub(super) player: Signal<Player>,
     player_update: Signal<Option<Receiver<PlayerUpdate>>>,
     is_paused: Signal<bool>,
     seek_bar_position: Signal<f64>,
+    /// A copy of the player's queue, updated on `PlayerUpdate::QueueChange`
+    queue: Signal<Vec<Song>>,
+    /// Whether the queue, the [`LyricsPanel`] or the [`ChaptersPanel`] is shown.
+    side_panel: Signal<SidePanel>,
 }
 
 impl AppContext {
     fn new(player: Player, player_update: Option<Receiver<PlayerUpdate>>) -> Self {
+        let queue = player.queue().items().to_vec();
         Self {
+            queue: Signal::new(queue),
             player: Signal::new(player),
             player_update: Signal::new(player_update),
             is_paused: Signal::new(false),
             seek_bar_position: Signal::new(0.),
+            side_panel: Signal::new(SidePanel::default()),
         }
     }
 }
@@ -45,24 +75,271 @@ impl DerefMut for UpdateSeekBar {
     }
 }
 
-#[derive(Clone, Debug)]
-struct PlaylistsContext {
+#[derive(Copy, Clone, Debug)]
+pub(super) struct PlaylistsContext {
     selected: Signal<Option<(usize, Vec<Song>)>>,
+    /// The entries of the selected playlist's file that don't exist.
+    missing: Signal<Vec<PlaylistEntry>>,
     active_indexes: Signal<Option<(usize, usize)>>,
-    playlists: Vec<Signal<Playlist>>,
+    pub(super) playlists: Signal<Vec<Playlist>>,
+    /// The cache the playlists' songs are read from.
+    pub(super) library: Signal<Library>,
+    /// true if the [`LibraryPanel`] is shown instead of the selected playlist's songs.
+    pub(super) browsing_library: Signal<bool>,
+    /// The index of the smart playlist in the config that's shown instead of the selected playlist's songs.
+    pub(super) smart_selected: Signal<Option<usize>>,
+    /// The running scan of the playlists' directories, its songs are added to `library`.
+    scan: Signal<Option<(ScanHandle, Receiver<ScanEvent>)>>,
+    /// How many files the running scan read, out of how many it found so far.
+    scan_progress: Signal<Option<(usize, usize)>>,
+    /// Watches the playlists' directories, so songs are added and removed as their files are.
+    watcher: Signal<Option<(LibraryWatcher, Receiver<WatchEvent>)>>,
 }
 
 impl PlaylistsContext {
-    fn new(playlists: &[Playlist]) -> Self {
+    fn new(playlists: &[Playlist], library: Library) -> Self {
         Self {
+            library: Signal::new(library),
+            browsing_library: Signal::new(false),
+            smart_selected: Signal::new(None),
+            scan: Signal::new(None),
+            scan_progress: Signal::new(None),
+            watcher: Signal::new(None),
             selected: Signal::new(None),
+            missing: Signal::new(Vec::new()),
             active_indexes: Signal::new(None),
-            playlists: playlists
-                .to_vec()
-                .into_iter()
-                .map(|playlist| Signal::new(playlist))
-                .collect(),
+            playlists: Signal::new(playlists.to_vec()),
+        }
+    }
+
+    /// Show the songs of the playlist at `index`, read from the library or its playlist file.
+    fn select(&mut self, index: usize) {
+        let loaded = self.playlists.read()[index].load(&self.library.read());
+        let songs = match loaded {
+            Ok(songs) => songs,
+            Err(e) => {
+                tracing::error!("Could not read playlist: {e}");
+                Default::default()
+            }
+        };
+        self.selected.set(Some((index, songs.songs)));
+        self.missing.set(songs.missing);
+    }
+
+    /// Write the playlists to the config file.
+    fn save_playlists(&self, mut config: Signal<Config>) {
+        *config.write().playlists = self.playlists.read().clone();
+        if let Err(e) = config.read().write() {
+            tracing::error!("Could not save config: {e}");
+        }
+    }
+
+    /// Add `playlist` to the end of the playlists, save them and select it.
+    fn add_playlist(&mut self, playlist: Playlist, config: Signal<Config>) {
+        self.playlists.write().push(playlist);
+        self.save_playlists(config);
+        let index = self.playlists.read().len() - 1;
+        self.select(index);
+        self.browsing_library.set(false);
+        self.smart_selected.set(None);
+    }
+
+    /// Remove the playlist at `index`, its file is deleted too if it's one of the user's playlists.
+    fn remove_playlist(&mut self, index: usize, config: Signal<Config>) {
+        let playlist = self.playlists.write().remove(index);
+        if playlist.is_user_playlist() {
+            if let Err(e) = std::fs::remove_file(playlist.path()) {
+                tracing::warn!("Could not delete '{}': {}", playlist.path().display(), e);
+            }
+        }
+        self.save_playlists(config);
+        // The playlists after the removed one moved back by one
+        let shift = |i: usize| match i.cmp(&index) {
+            std::cmp::Ordering::Less => Some(i),
+            std::cmp::Ordering::Equal => None,
+            std::cmp::Ordering::Greater => Some(i - 1),
+        };
+        let selected = self.selected.take();
+        match selected.and_then(|(i, songs)| Some((shift(i)?, songs))) {
+            Some(selected) => self.selected.set(Some(selected)),
+            None => self.missing.set(Vec::new()),
+        }
+        let active = self.active_indexes.take();
+        self.active_indexes
+            .set(active.and_then(|(i, song)| Some((shift(i)?, song))));
+    }
+
+    /// Rename the playlist at `index` and save it.
+    fn rename_playlist(&mut self, index: usize, name: String, config: Signal<Config>) {
+        self.playlists.write()[index].set_name(name);
+        self.save_playlists(config);
+    }
+
+    /// Change the selected playlist's songs with `edit`, and write them to its file.
+    fn edit_selected(&mut self, edit: impl FnOnce(&mut Vec<Song>)) {
+        let mut selected = self.selected.write();
+        let Some((index, songs)) = selected.as_mut() else {
+            return;
+        };
+        edit(songs);
+        let playlist_songs = PlaylistSongs {
+            songs: songs.clone(),
+            missing: self.missing.read().clone(),
+        };
+        if let Err(e) = self.playlists.read()[*index].save(&playlist_songs) {
+            tracing::error!("Could not save playlist: {e}");
+        }
+    }
+
+    /// Add `songs` to the end of the playlist at `index`.
+    pub(super) fn add_songs(&mut self, index: usize, songs: Vec<Song>) {
+        let is_selected = self
+            .selected
+            .read()
+            .as_ref()
+            .is_some_and(|(selected, _)| *selected == index);
+        if is_selected {
+            self.edit_selected(|selected| selected.extend(songs));
+            return;
+        }
+        let playlist = self.playlists.read()[index].clone();
+        let saved = playlist.load(&self.library.read()).and_then(|mut loaded| {
+            loaded.songs.extend(songs);
+            playlist.save(&loaded)
+        });
+        if let Err(e) = saved {
+            tracing::error!("Could not add to playlist: {e}");
+        }
+    }
+
+    /// Scan the playlists' directories in the background, cancelling the scan that's already running.
+    fn start_scan(&mut self, ignore: &[String]) {
+        if let Some((handle, _)) = self.scan.take() {
+            handle.cancel();
+        }
+        let roots: Vec<_> = self
+            .playlists
+            .read()
+            .iter()
+            .map(|playlist| playlist.path().to_path_buf())
+            .filter(|path| path.is_dir())
+            .collect();
+        let scanner = ignore.iter().fold(Scanner::new(roots), |scanner, pattern| {
+            scanner.ignore(pattern.clone())
+        });
+        self.scan
+            .set(Some(scanner.start(self.library.read().clone())));
+        self.scan_progress.set(Some((0, 0)));
+    }
+
+    /// Start watching the playlists' directories for changes.
+    fn start_watching(&mut self, ignore: &[String]) {
+        let artwork_dir = self.library.read().artwork_dir().map(Path::to_path_buf);
+        let (mut watcher, events) =
+            match LibraryWatcher::new(DEFAULT_DEBOUNCE, ignore.to_vec(), artwork_dir) {
+                Ok(watcher) => watcher,
+                Err(e) => {
+                    tracing::error!("Could not watch playlists: {e}");
+                    return;
+                }
+            };
+        for playlist in self
+            .playlists
+            .read()
+            .iter()
+            .filter(|playlist| !playlist.is_file())
+        {
+            let path = playlist.path();
+            if let Err(e) = watcher.watch(path) {
+                tracing::warn!("Could not watch '{}': {}", path.display(), e);
+            }
+        }
+        self.watcher.set(Some((watcher, events)));
+    }
+
+    /// Apply the changes found by the watcher to the library and `player`'s queue.
+    fn handle_watch_events(&mut self, mut player: Signal<Player>) {
+        let mut changed = false;
+        if let Some((_, events)) = self.watcher.read().as_ref() {
+            for event in events.try_iter() {
+                match event {
+                    WatchEvent::Changed(song) => {
+                        if let Err(e) = self.library.write().insert(*song) {
+                            tracing::warn!("Could not add changed song: {e}");
+                        }
+                    }
+                    WatchEvent::Removed(path) => {
+                        self.library.write().remove_under(&path);
+                        player.write().remove_missing(&path);
+                    }
+                    WatchEvent::Error { path, error } => {
+                        tracing::warn!("Could not read '{}': {}", path.display(), error);
+                    }
+                }
+                changed = true;
+            }
+        }
+        if changed {
+            self.library_changed();
+        }
+    }
+
+    /// Save the library and refresh the selected playlist's songs from it.
+    pub(super) fn library_changed(&mut self) {
+        let mut library = self.library.write();
+        if library.is_changed() {
+            if let Err(e) = library.write() {
+                tracing::error!("Could not save library: {e}");
+            }
+        }
+        drop(library);
+        let selected = self.selected.read().as_ref().map(|(index, _)| *index);
+        if let Some(index) = selected {
+            self.select(index);
+        }
+    }
+
+    /// Add the songs from the running scan to the library, and refresh the selected playlist when it finishes.
+    ///
+    /// Returns true if the scan finished.
+    fn handle_scan_events(&mut self) -> bool {
+        let mut finished = false;
+        if let Some((_, events)) = self.scan.read().as_ref() {
+            for event in events.try_iter() {
+                match event {
+                    ScanEvent::Song(song) => {
+                        if let Err(e) = self.library.write().insert(*song) {
+                            tracing::warn!("Could not add scanned song: {e}");
+                        }
+                    }
+                    ScanEvent::Error { path, error } => {
+                        tracing::warn!("Could not scan '{}': {}", path.display(), error);
+                    }
+                    ScanEvent::Progress { scanned, found } => {
+                        self.scan_progress.set(Some((scanned, found)));
+                    }
+                    ScanEvent::Finished { .. } => finished = true,
+                }
+            }
+        }
+        if !finished {
+            return false;
         }
+        self.scan.set(None);
+        self.scan_progress.set(None);
+        self.library.write().prune();
+        self.library_changed();
+        true
+    }
+
+    /// The first valid playlist's songs, which are played when there's nothing else to play.
+    fn default_songs(&self) -> Vec<Song> {
+        first_playlist_songs(&self.playlists.read(), &self.library.read())
+    }
+
+    /// An autoplay source with the songs of all valid playlists.
+    fn radio(&self) -> LibraryRadio {
+        library_radio(&self.playlists.read(), &self.library.read())
     }
 }
 
@@ -77,24 +354,31 @@ struct PlaylistProp {
 fn PlaylistButton(props: PlaylistProp) -> Element {
     let mut div_class = "playlist-button".to_string();
     let mut is_valid = use_signal(|| props.playlist.is_valid());
+    let artwork = use_signal(|| props.playlist.artwork());
     if !is_valid() {
         div_class += " playlist-invalid";
     } else if props.selected {
         div_class += " playlist-selected";
     }
     static INVALID_PLAYLIST_ICON: Asset = asset!("/assets/icons/warning.svg");
-    let playlists_context = use_context::<PlaylistsContext>();
-    let mut selected = playlists_context.selected;
+    let mut playlists_context = use_context::<PlaylistsContext>();
+    let mut browsing_library = playlists_context.browsing_library;
     let index = props.index;
     rsx! {
         button {
             class: div_class,
             onclick: move |_| {
-                // TODO: handle Err
-                let songs = playlists_context.playlists[index].read().songs().ok();
-                selected.set(Some(index).zip(songs));
+                playlists_context.select(index);
+                browsing_library.set(false);
+                playlists_context.smart_selected.set(None);
                 is_valid.set(props.playlist.is_valid());
             },
+            if let Some(artwork) = artwork() {
+                img {
+                    class: "playlist-icon",
+                    src: artwork.display().to_string(),
+                }
+            }
             p {
                 { props.playlist.name() }
             }
@@ -108,32 +392,197 @@ fn PlaylistButton(props: PlaylistProp) -> Element {
     }
 }
 
+/// Ask where to save `songs` and write them to a playlist file, `name` is the default file name.
+fn export_songs(name: String, songs: Vec<Song>) {
+    spawn(async move {
+        let Some(file) = rfd::AsyncFileDialog::new()
+            .set_title("Export playlist")
+            .add_filter("Playlist", PlaylistFormat::EXTENSIONS)
+            .set_file_name(format!("{name}.m3u8"))
+            .save_file()
+            .await
+        else {
+            return;
+        };
+        if let Err(e) = playlist_file::write(file.path(), &songs, &[]) {
+            tracing::error!("Could not export playlist: {e}");
+        }
+    });
+}
+
 #[component]
 fn PlaylistPanel() -> Element {
-    let playlists = use_context::<PlaylistsContext>().playlists;
-    let selected = use_context::<PlaylistsContext>().selected;
+    let mut playlists_context = use_context::<PlaylistsContext>();
+    let mut playlists = playlists_context.playlists;
+    let selected = playlists_context.selected;
+    let scan_progress = playlists_context.scan_progress;
+    let mut browsing_library = playlists_context.browsing_library;
+    let mut smart_selected = playlists_context.smart_selected;
+    let mut config = use_context::<Signal<Config>>();
+
+    // Add a playlist file as a playlist, and save it to the config
+    let import_playlist = move |_| {
+        spawn(async move {
+            let Some(file) = rfd::AsyncFileDialog::new()
+                .set_title("Import playlist")
+                .add_filter("Playlist", PlaylistFormat::EXTENSIONS)
+                .pick_file()
+                .await
+            else {
+                return;
+            };
+            let path = file.path().to_path_buf();
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let playlist = match Playlist::new(path, name, None) {
+                Ok(playlist) => playlist,
+                Err(e) => {
+                    tracing::error!("Could not import playlist: {e}");
+                    return;
+                }
+            };
+            config.write().playlists.push(playlist.clone());
+            if let Err(e) = config.read().write() {
+                tracing::error!("Could not save config: {e}");
+            }
+            playlists.write().push(playlist);
+        });
+    };
+
+    let create_playlist = move |_| {
+        let name = {
+            let playlists = playlists.read();
+            (1..)
+                .map(|n| format!("New playlist {n}"))
+                .find(|name| playlists.iter().all(|playlist| playlist.name() != name))
+                .expect("There should always be a free playlist name")
+        };
+        match Playlist::create(name) {
+            Ok(playlist) => playlists_context.add_playlist(playlist, config),
+            Err(e) => tracing::error!("Could not create playlist: {e}"),
+        }
+    };
+
+    // Ask where to save the listening history, and write it as JSON
+    let export_history = move |_| {
+        spawn(async move {
+            let Some(file) = rfd::AsyncFileDialog::new()
+                .set_title("Export listening history")
+                .add_filter("JSON", &["json"])
+                .set_file_name("history.json")
+                .save_file()
+                .await
+            else {
+                return;
+            };
+            if let Err(e) = History::default().export_json(file.path()) {
+                tracing::error!("Could not export listening history: {e}");
+            }
+        });
+    };
+
+    let create_smart_playlist = move |_| {
+        let name = {
+            let config = config.read();
+            (1..)
+                .map(|n| format!("Smart playlist {n}"))
+                .find(|name| {
+                    config
+                        .smart_playlists
+                        .iter()
+                        .all(|playlist| playlist.name != *name)
+                })
+                .expect("There should always be a free playlist name")
+        };
+        config
+            .write()
+            .smart_playlists
+            .push(SmartPlaylist::new(name));
+        if let Err(e) = config.read().write() {
+            tracing::error!("Could not save config: {e}");
+        }
+        browsing_library.set(false);
+        smart_selected.set(Some(config.read().smart_playlists.len() - 1));
+    };
+
     rsx! {
         div {
             class: "playlist-panel",
             div {
                 class: "playlist-panel-buttons",
                 button {
+                    title: "New playlist",
+                    onclick: create_playlist,
                     "+"
                 }
                 button {
-                    "0"
+                    title: "New smart playlist",
+                    onclick: create_smart_playlist,
+                    "✦"
                 }
                 button {
-
+                    title: "Export listening history",
+                    onclick: export_history,
+                    "⇩"
+                }
+                button {
+                    title: "Import playlist file",
+                    onclick: import_playlist,
+                    "⇪"
+                }
+                if let Some((scanned, found)) = scan_progress() {
+                    button {
+                        title: "Cancel scan",
+                        onclick: move |_| {
+                            if let Some((handle, _)) = playlists_context.scan.read().as_ref() {
+                                handle.cancel();
+                            }
+                        },
+                        "✕"
+                    }
+                    p {
+                        class: "scan-progress",
+                        "Scanning {scanned}/{found}"
+                    }
+                } else {
+                    button {
+                        title: "Rescan playlists",
+                        onclick: move |_| {
+                            playlists_context.start_scan(&config.read().scan_ignore);
+                        },
+                        "↻"
+                    }
                 }
             }
             div {
                 class: "playlist-list",
-                for (i, playlist) in playlists.iter().enumerate() {
+                button {
+                    class: if browsing_library() { "playlist-button playlist-selected" } else { "playlist-button" },
+                    onclick: move |_| browsing_library.set(true),
+                    p {
+                        "Library"
+                    }
+                }
+                for (i, playlist) in playlists.read().iter().enumerate() {
                     PlaylistButton {
-                        playlist: playlist.read().clone(),
+                        playlist: playlist.clone(),
                         index: i,
-                        selected: selected.as_ref().is_some_and(|s| s.0 == i)
+                        selected: !browsing_library() && smart_selected().is_none() && selected.as_ref().is_some_and(|s| s.0 == i)
+                    }
+                }
+                for (i, playlist) in config.read().smart_playlists.iter().enumerate() {
+                    button {
+                        class: if !browsing_library() && smart_selected() == Some(i) { "playlist-button playlist-selected" } else { "playlist-button" },
+                        onclick: move |_| {
+                            browsing_library.set(false);
+                            smart_selected.set(Some(i));
+                        },
+                        p {
+                            class: "smart-playlist-name",
+                            { playlist.name.clone() }
+                        }
                     }
                 }
             }
@@ -141,6 +590,35 @@ fn PlaylistPanel() -> Element {
     }
 }
 
+/// Where a long song will resume, or that it was completed, see [`Library::record_position`].
+#[component]
+pub(super) fn ResumeMarker(song: Song) -> Element {
+    let library = use_context::<PlaylistsContext>().library;
+    let stats = library
+        .read()
+        .stats(song.path())
+        .cloned()
+        .unwrap_or_default();
+    let show_hours = song.duration().as_secs() > 3600;
+    match stats.resume_position {
+        Some(millis) => rsx! {
+            p {
+                class: "song-resume",
+                title: "Resumes here",
+                "▶ {format_time(millis / 1000, show_hours)}"
+            }
+        },
+        None if stats.completed => rsx! {
+            p {
+                class: "song-resume",
+                title: "Played to the end",
+                "✓"
+            }
+        },
+        None => rsx! {},
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Props)]
 struct SongComponentProps {
     song: Song,
@@ -183,13 +661,26 @@ fn SongComponent(props: SongComponentProps) -> Element {
                         src: INVALID_SONG_ICON,
                     }
                 }
+                if let Some(artwork) = props.song.artwork() {
+                    img {
+                        class: "song-art",
+                        src: artwork.display().to_string(),
+                    }
+                }
                 p {
                     class: "song-title",
                     { props.song.title() }
                 }
+                if let Some(artist) = props.song.artist() {
+                    p {
+                        class: "song-artist",
+                        { artist }
+                    }
+                }
             },
             div {
                 class: "song-component-right",
+                ResumeMarker { song: props.song.clone() }
                 p {
                     class: "song-duration",
                     { format_time(props.song.duration().as_secs(), show_hours) }
@@ -208,7 +699,20 @@ fn SongComponent(props: SongComponentProps) -> Element {
 
 #[component]
 fn SongPanel() -> Element {
-    let playlists_context = use_context::<PlaylistsContext>();
+    let mut playlists_context = use_context::<PlaylistsContext>();
+    let config = use_context::<Signal<Config>>();
+    // The position of the song that's being dragged
+    let mut dragged = use_signal(|| None::<usize>);
+    if (playlists_context.browsing_library)() {
+        return rsx! {
+            LibraryPanel {  }
+        };
+    }
+    if let Some(index) = (playlists_context.smart_selected)() {
+        return rsx! {
+            SmartPlaylistPanel { key: "{index}", index }
+        };
+    }
     let Some((selected_index, selected_songs)) = playlists_context.selected.read().clone() else {
         return rsx! {
             p {
@@ -220,14 +724,221 @@ fn SongPanel() -> Element {
     let (active_playlist_index, active_song_index) =
         playlists_context.active_indexes.read().unzip();
     let same_playlist = active_playlist_index.is_some_and(|a_i| selected_index == a_i);
+    let playlist = playlists_context.playlists.read()[selected_index].clone();
+    let name = playlist.name().to_string();
+    // Only playlist files can be edited, a directory's songs are its files
+    let editable = playlist.is_file();
+    let exported_songs = selected_songs.clone();
+    let missing = playlists_context.missing;
+
+    let duplicate = move |_| {
+        let name = format!("{} copy", playlist.name());
+        let duplicated = playlist.duplicate(name, &playlists_context.library.read());
+        match duplicated {
+            Ok(duplicated) => playlists_context.add_playlist(duplicated, config),
+            Err(e) => tracing::error!("Could not duplicate playlist: {e}"),
+        }
+    };
+
     rsx! {
         div {
             class: "song-panel",
+            div {
+                class: "song-panel-header",
+                if editable {
+                    input {
+                        class: "playlist-name",
+                        value: "{name}",
+                        onchange: move |event| {
+                            playlists_context.rename_playlist(selected_index, event.value(), config);
+                        },
+                    }
+                } else {
+                    p {
+                        class: "playlist-name",
+                        "{name}"
+                    }
+                }
+                button {
+                    onclick: duplicate,
+                    "Duplicate"
+                }
+                button {
+                    title: "Export as a playlist file",
+                    onclick: {
+                        let name = name.clone();
+                        move |_| export_songs(name.clone(), exported_songs.clone())
+                    },
+                    "Export"
+                }
+                button {
+                    title: if playlists_context.playlists.read()[selected_index].is_user_playlist() {
+                        "Delete the playlist and its file"
+                    } else {
+                        "Remove the playlist, its files are kept"
+                    },
+                    onclick: move |_| playlists_context.remove_playlist(selected_index, config),
+                    "Delete"
+                }
+            }
             for (i, song) in selected_songs.iter().enumerate() {
-                SongComponent {
-                    song: song.clone(),
-                    index: i,
-                    is_playing: same_playlist && active_song_index.is_some_and(|song_index| i == song_index)
+                if editable {
+                    div {
+                        key: "{i}",
+                        class: "playlist-song",
+                        draggable: true,
+                        ondragstart: move |_| dragged.set(Some(i)),
+                        ondragover: move |event| event.prevent_default(),
+                        ondrop: move |event| {
+                            event.prevent_default();
+                            if let Some(from) = dragged.take() {
+                                playlists_context.edit_selected(|songs| {
+                                    let song = songs.remove(from);
+                                    songs.insert(i, song);
+                                });
+                            }
+                        },
+                        SongComponent {
+                            song: song.clone(),
+                            index: i,
+                            is_playing: same_playlist && active_song_index.is_some_and(|song_index| i == song_index)
+                        }
+                        button {
+                            class: "playlist-song-remove",
+                            title: "Remove from playlist",
+                            onclick: move |_| {
+                                playlists_context.edit_selected(|songs| {
+                                    songs.remove(i);
+                                });
+                            },
+                            "x"
+                        }
+                    }
+                } else {
+                    SongComponent {
+                        song: song.clone(),
+                        index: i,
+                        is_playing: same_playlist && active_song_index.is_some_and(|song_index| i == song_index)
+                    }
+                }
+            }
+            if !missing.read().is_empty() {
+                p {
+                    class: "playlist-missing-header",
+                    "{missing.read().len()} songs in this playlist are missing"
+                }
+            }
+            for entry in missing.read().iter() {
+                div {
+                    class: "song-component song-missing",
+                    title: entry.path.display().to_string(),
+                    div {
+                        class: "song-component-left",
+                        p {
+                            class: "song-title",
+                            { entry.title.clone().unwrap_or_else(|| entry.path.display().to_string()) }
+                        }
+                    }
+                    if let Some(duration) = entry.duration {
+                        div {
+                            class: "song-component-right",
+                            p {
+                                class: "song-duration",
+                                { format_time(duration.as_secs(), duration.as_secs() > 3600) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn QueuePanel() -> Element {
+    let player_info = use_context::<AppContext>();
+    let mut player = player_info.player;
+    let queue = player_info.queue;
+    let mut player_update = player_info.player_update;
+    let config = use_context::<Signal<Config>>();
+    let current_index = player.read().queue().index();
+    let queue_name = player.read().queue_name();
+    let queue_names = player.read().queue_names();
+    // The queue position of the song that's being dragged
+    let mut dragged = use_signal(|| None::<usize>);
+
+    let switch_queue = move |event: Event<FormData>| {
+        if let Err(e) = player.write().switch_queue(&event.value()) {
+            tracing::error!("Could not switch queue: {e}");
+            return;
+        }
+        // Playback finishes when the queue runs out, so it has to be started again
+        if !player.read().is_active() {
+            let buffer_size = config.read().player.buffer_size;
+            if let Ok(receiver) = player.write().run(buffer_size) {
+                player_update.set(Some(receiver));
+            }
+        }
+    };
+    let create_queue = move |_| {
+        let names = player.read().queue_names();
+        let name = (1..)
+            .map(|n| format!("Queue {n}"))
+            .find(|name| !names.contains(name))
+            .expect("There should always be a free queue name");
+        let _ = player.write().create_queue(name);
+    };
+
+    rsx! {
+        div {
+            class: "queue-panel",
+            div {
+                class: "queue-panel-header",
+                select {
+                    class: "queue-select",
+                    onchange: switch_queue,
+                    for name in queue_names {
+                        option {
+                            value: "{name}",
+                            selected: name == queue_name,
+                            "{name}"
+                        }
+                    }
+                }
+                button {
+                    onclick: create_queue,
+                    "+"
+                }
+                button {
+                    title: "Export as a playlist file",
+                    onclick: move |_| export_songs(queue_name.clone(), queue.read().clone()),
+                    "Export"
+                }
+            }
+            for (i, song) in queue.read().iter().enumerate() {
+                div {
+                    key: "{i}",
+                    class: if i == current_index { "queue-item queue-item-current" } else { "queue-item" },
+                    draggable: true,
+                    ondragstart: move |_| dragged.set(Some(i)),
+                    ondragover: move |event| event.prevent_default(),
+                    ondrop: move |event| {
+                        event.prevent_default();
+                        if let Some(from) = dragged.take() {
+                            let _ = player.write().move_song(from, i);
+                        }
+                    },
+                    p {
+                        class: "queue-item-title",
+                        { song.title() }
+                    }
+                    button {
+                        class: "queue-item-remove",
+                        onclick: move |_| {
+                            let _ = player.write().remove_song(i);
+                        },
+                        "x"
+                    }
                 }
             }
         }
@@ -252,10 +963,29 @@ fn SeekBar() -> Element {
     };
 
     let value = player_info.seek_bar_position;
+    // Where each chapter starts, as a percentage of the song
+    let chapter_ticks: Vec<(String, f64)> = match player.read().current() {
+        Some(song) if !song.duration().is_zero() => song
+            .chapters()
+            .iter()
+            .map(|chapter| {
+                let percent = chapter.start.as_secs_f64() / song.duration().as_secs_f64() * 100.;
+                (chapter.title.clone(), percent)
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
 
     rsx! {
         div {
             id: "seek-bar",
+            for (title, percent) in chapter_ticks {
+                div {
+                    class: "seek-bar-chapter",
+                    title: "{title}",
+                    left: "{percent}%",
+                }
+            }
             input {
                 r#type: "range",
                 min: 0.,
@@ -270,7 +1000,7 @@ fn SeekBar() -> Element {
     }
 }
 
-fn format_time(mut seconds: u64, show_hours: bool) -> String {
+pub(super) fn format_time(mut seconds: u64, show_hours: bool) -> String {
     let mut formatted = String::new();
     let mut minutes = seconds / 60;
     seconds %= 60;
@@ -289,18 +1019,30 @@ fn SongDisplay() -> Element {
     let player = use_context::<AppContext>().player;
     let player_read = player.read();
     if let Some(song) = player_read.current() {
-        let title = song.title();
+        let title = match song.artist() {
+            Some(artist) => format!("{} - {}", song.title(), artist),
+            None => song.title().to_string(),
+        };
         let duration = song.duration().as_secs();
         let time_playing = player_read.time_playing().as_secs();
         let show_hours = duration > 3600;
         rsx! {
             div {
                 class: "song-display",
-                p {{ title }}
-                p {
-                    { format_time(time_playing, show_hours) }
-                    " / "
-                    { format_time(duration, show_hours) }
+                if let Some(artwork) = song.artwork() {
+                    img {
+                        class: "song-display-art",
+                        src: artwork.display().to_string(),
+                    }
+                }
+                div {
+                    class: "song-display-text",
+                    p {{ title }}
+                    p {
+                        { format_time(time_playing, show_hours) }
+                        " / "
+                        { format_time(duration, show_hours) }
+                    }
                 }
             }
         }
@@ -314,6 +1056,7 @@ fn CenterControls() -> Element {
     let player_info = use_context::<AppContext>();
     let mut player = player_info.player;
     let mut is_paused = player_info.is_paused;
+    let library = use_context::<PlaylistsContext>().library;
     rsx! {
         div {
             class: "controls-center",
@@ -342,15 +1085,47 @@ fn CenterControls() -> Element {
                 },
                 "FF"
             }
+            button {
+                onclick: move |_| player.write().shuffle(),
+                "Shuf"
+            }
+            button {
+                title: "Shuffle, higher rated songs are more likely to come first",
+                onclick: move |_| {
+                    let library = library.read();
+                    player.write().shuffle_weighted(|song| {
+                        library.stats(song.path()).map_or(1., SongStats::shuffle_weight)
+                    });
+                },
+                "W.Shuf"
+            }
         }
     }
 }
 
 #[component]
 fn RightControls() -> Element {
+    let mut side_panel = use_context::<AppContext>().side_panel;
+    // Clicking the button of the panel that's shown goes back to the queue
+    let mut toggle = move |panel: SidePanel| {
+        let shown = if side_panel() == panel {
+            SidePanel::Queue
+        } else {
+            panel
+        };
+        side_panel.set(shown);
+    };
     rsx! {
         div {
             class: "controls-right",
+            button {
+                onclick: move |_| toggle(SidePanel::Lyrics),
+                if side_panel() == SidePanel::Lyrics { "Queue" } else { "Lyrics" }
+            }
+            button {
+                onclick: move |_| toggle(SidePanel::Chapters),
+                if side_panel() == SidePanel::Chapters { "Queue" } else { "Chapters" }
+            }
             p {
                 "AMOGUS"
             }
@@ -379,25 +1154,117 @@ fn BottomPanel() -> Element {
     }
 }
 
+/// See [`PlaylistsContext::default_songs`].
+fn first_playlist_songs(playlists: &[Playlist], library: &Library) -> Vec<Song> {
+    playlists
+        .iter()
+        .find(|playlist| playlist.is_valid())
+        .map(|playlist| playlist.songs(library))
+        .unwrap_or_default()
+}
+
+/// See [`PlaylistsContext::radio`].
+fn library_radio(playlists: &[Playlist], library: &Library) -> LibraryRadio {
+    let songs = playlists
+        .iter()
+        .filter(|playlist| playlist.is_valid())
+        .flat_map(|playlist| playlist.songs(library))
+        .collect();
+    LibraryRadio::new(songs)
+}
+
 #[component]
 pub fn Amuseing() -> Element {
     let config = Config::from_default_path().unwrap_or_default();
     let playlists = config.playlists.clone();
+    let mut library = Library::from_default_path().unwrap_or_default();
+    library.set_artwork_dir(Some(artwork::cache_dir()));
     let mut player = Player::new(config.player.volume);
-    let songs = config.playlists[1].songs().unwrap();
-    player.set_songs(songs);
+    player.set_undo_depth(config.player.undo_depth);
+    player.set_repeat_mode(config.player.repeat_mode);
+    if config.player.autoplay {
+        player.set_autoplay(Some(Box::new(library_radio(&playlists, &library))));
+    }
+    match Session::from_default_path() {
+        Ok(session) => player.restore_session(session, library.artwork_dir()),
+        // Without a session, start with the first playlist there is, or an empty queue
+        Err(_) => player.set_songs(first_playlist_songs(&playlists, &library)),
+    }
     let player_update = player.run(config.player.buffer_size).ok();
+    let scan_ignore = config.scan_ignore.clone();
 
     let mut player_context = use_context_provider(|| AppContext::new(player, player_update));
     let config_context = use_context_provider(|| Signal::new(config));
-    let mut playlists_context = use_context_provider(|| PlaylistsContext::new(playlists.inner()));
+    let mut playlists_context =
+        use_context_provider(|| PlaylistsContext::new(playlists.inner(), library));
     let update_seek_bar = use_context_provider(|| UpdateSeekBar(Signal::new(true)));
+    // Bring the library up to date with the playlists' directories, once on startup
+    use_hook(move || {
+        playlists_context.start_scan(&scan_ignore);
+        playlists_context.start_watching(&scan_ignore);
+    });
+
+    let save_session = move || {
+        if let Err(e) = player_context.player.read().session().write() {
+            tracing::error!("Could not save session: {e}");
+        }
+    };
 
     // 100ms loop to update any component that depends on `player`
     spawn(async move {
         let mut interval = tokio::time::interval(Duration::from_millis(100));
+        // Save the session every 5 seconds, so the position in the current song isn't lost
+        let mut ticks: u32 = 0;
         loop {
             interval.tick().await;
+            if playlists_context.handle_scan_events() {
+                // The library is empty on the first run, so the player and autoplay only have songs after the scan
+                if config_context.read().player.autoplay {
+                    let radio = playlists_context.radio();
+                    player_context
+                        .player
+                        .write()
+                        .set_autoplay(Some(Box::new(radio)));
+                }
+                let idle = {
+                    let player = player_context.player.read();
+                    !player.is_active() && player.queue().is_empty()
+                };
+                if idle {
+                    player_context
+                        .player
+                        .write()
+                        .set_songs(playlists_context.default_songs());
+                    let buffer_size = config_context.read().player.buffer_size;
+                    if let Ok(receiver) = player_context.player.write().run(buffer_size) {
+                        player_context.player_update.set(Some(receiver));
+                    }
+                    let queue = player_context.player.read().queue().items().to_vec();
+                    player_context.queue.set(queue);
+                }
+            }
+            playlists_context.handle_watch_events(player_context.player);
+            ticks = ticks.wrapping_add(1);
+            if ticks.is_multiple_of(50) {
+                save_session();
+                // The song that's playing when the app is closed never ends, so its position is remembered as it plays
+                let current = player_context
+                    .player
+                    .read()
+                    .current()
+                    .filter(|_| player_context.player.read().is_active());
+                if let Some(song) = current {
+                    let position = player_context.player.read().time_playing().into();
+                    let threshold = config_context.read().player.resume_threshold();
+                    let mut library = playlists_context.library.write();
+                    library.record_position(song.path(), position, false, threshold);
+                    if library.is_changed() {
+                        if let Err(e) = library.write() {
+                            tracing::error!("Could not save library: {e}");
+                        }
+                    }
+                }
+            }
             let is_paused = player_context.player.read().is_paused();
             player_context.is_paused.set(is_paused);
             // Dummy write to update all components that depend on player
@@ -417,7 +1284,19 @@ pub fn Amuseing() -> Element {
                 for message in player_update.try_iter() {
                     match message {
                         PlayerUpdate::SongChange { song_info } => {
-                            if let Some((new_song_index, _)) = song_info {
+                            if let Some((new_song_index, song)) = song_info {
+                                // Long songs resume where they were stopped, unless the session already moved the song
+                                let resume_position = playlists_context
+                                    .library
+                                    .read()
+                                    .resume_position(song.path());
+                                let at_start =
+                                    player_context.player.read().time_playing().as_secs() == 0;
+                                if let Some(position) = resume_position.filter(|_| at_start) {
+                                    if let Err(e) = player_context.player.write().seek(position) {
+                                        tracing::warn!("Could not resume '{}': {e}", song.title());
+                                    }
+                                }
                                 if let Some((_, active_song_index)) =
                                     playlists_context.active_indexes.write().as_mut()
                                 {
@@ -427,6 +1306,35 @@ pub fn Amuseing() -> Element {
                                 playlists_context.active_indexes.set(None);
                             }
                         }
+                        PlayerUpdate::SongEnd {
+                            song,
+                            started,
+                            position,
+                            played,
+                            finished,
+                        } => {
+                            let listen = Listen::new(&song, started, played);
+                            if let Err(e) = History::default().record(&listen) {
+                                tracing::error!("Could not record listen: {e}");
+                            }
+                            let resume_threshold = config_context.read().player.resume_threshold();
+                            {
+                                let mut library = playlists_context.library.write();
+                                library.record_listen(song.path(), played, finished, unix_now());
+                                library.record_position(
+                                    song.path(),
+                                    position,
+                                    finished,
+                                    resume_threshold,
+                                );
+                            }
+                            playlists_context.library_changed();
+                        }
+                        PlayerUpdate::QueueChange => {
+                            let queue = player_context.player.read().queue().items().to_vec();
+                            player_context.queue.set(queue);
+                            save_session();
+                        }
                         message => {
                             tracing::debug!("{:?}", message);
                         }
@@ -436,15 +1344,49 @@ pub fn Amuseing() -> Element {
         }
     });
 
+    // Ctrl+Z undoes the last queue edit, Ctrl+Shift+Z or Ctrl+Y redoes it
+    let shortcuts = move |event: KeyboardEvent| {
+        if !event.modifiers().ctrl() {
+            return;
+        }
+        let Key::Character(key) = event.key() else {
+            return;
+        };
+        match key.to_lowercase().as_str() {
+            "z" if event.modifiers().shift() => {
+                player_context.player.write().redo_queue();
+            }
+            "z" => {
+                player_context.player.write().undo_queue();
+            }
+            "y" => {
+                player_context.player.write().redo_queue();
+            }
+            _ => {}
+        }
+    };
+
     rsx! {
         div {
-            class: "content-wrapper",
+            class: "app",
+            tabindex: 0,
+            onkeydown: shortcuts,
+
+            div {
+                class: "content-wrapper",
 
-            PlaylistPanel { }
+                PlaylistPanel { }
 
-            SongPanel {  }
-        }
+                SongPanel {  }
 
-        BottomPanel {  }
+                match (player_context.side_panel)() {
+                    SidePanel::Queue => rsx! { QueuePanel {  } },
+                    SidePanel::Lyrics => rsx! { LyricsPanel {  } },
+                    SidePanel::Chapters => rsx! { ChaptersPanel {  } },
+                }
+            }
+
+            BottomPanel {  }
+        }
     }
 }
@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use amuseing::chapters::chapter_at;
+use dioxus::{logger::tracing, prelude::*};
+
+use super::amuseing::{format_time, AppContext};
+
+/// The chapters of the current song, shown instead of the queue.
+///
+/// The current chapter is highlighted, clicking a chapter seeks to it.
+#[component]
+pub(super) fn ChaptersPanel() -> Element {
+    let mut player = use_context::<AppContext>().player;
+    let song = player.read().current();
+    let chapters = song
+        .as_ref()
+        .map(|song| song.chapters().to_vec())
+        .unwrap_or_default();
+    let show_hours = song
+        .as_ref()
+        .is_some_and(|song| song.duration().as_secs() > 3600);
+    let position = Duration::from_secs_f64(player.read().time_playing().as_secs_f64());
+    let current = chapter_at(&chapters, position);
+
+    rsx! {
+        div {
+            class: "queue-panel chapters-panel",
+            if chapters.is_empty() {
+                p {
+                    class: "playlist-missing-header",
+                    "No chapters"
+                }
+            } else {
+                div {
+                    class: "library-header",
+                    button {
+                        onclick: move |_| {
+                            player.write().previous_chapter();
+                        },
+                        "Previous chapter"
+                    }
+                    button {
+                        onclick: move |_| {
+                            player.write().next_chapter();
+                        },
+                        "Next chapter"
+                    }
+                }
+            }
+            for (i, chapter) in chapters.into_iter().enumerate() {
+                div {
+                    key: "{i}",
+                    class: if current == Some(i) { "chapter chapter-current" } else { "chapter" },
+                    onclick: move |_| {
+                        if let Err(e) = player.write().seek(chapter.start) {
+                            tracing::error!("Could not seek to chapter: {e}");
+                        }
+                    },
+                    span {
+                        class: "chapter-start",
+                        { format_time(chapter.start.as_secs(), show_hours) }
+                    }
+                    span {
+                        { chapter.title }
+                    }
+                }
+            }
+        }
+    }
+}
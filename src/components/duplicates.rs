@@ -0,0 +1,254 @@
+use std::{fs, sync::mpsc::Receiver, time::Duration};
+
+use amuseing::{
+    fingerprint::{self, DuplicateEvent, DuplicateFinder},
+    playback::{Player, Song},
+    scanner::ScanHandle,
+};
+use dioxus::{logger::tracing, prelude::*};
+
+use super::amuseing::{format_time, AppContext, PlaylistsContext};
+
+/// Keep the song at `keep` in `group`, and move the files of the others to the [`fingerprint::quarantine_dir`],
+/// or delete them if `delete` is true.
+///
+/// The others are removed from the library, and the queues and playlist files that had them get the kept song instead.
+/// Returns messages saying how it went, one for each file that couldn't be removed,
+/// and what's left of the group: the kept song and the songs whose files couldn't be removed.
+fn keep_one(
+    mut playlists_context: PlaylistsContext,
+    mut player: Signal<Player>,
+    group: &[Song],
+    keep: usize,
+    delete: bool,
+) -> (Vec<String>, Vec<Song>) {
+    let kept = &group[keep];
+    let quarantine_dir = fingerprint::quarantine_dir();
+    let mut removed = 0;
+    let mut messages = Vec::new();
+    let mut left = Vec::new();
+    for (i, song) in group.iter().enumerate() {
+        if i == keep {
+            left.push(song.clone());
+            continue;
+        }
+        let result = match delete {
+            true => fs::remove_file(song.file()),
+            false => fingerprint::quarantine(song.file(), &quarantine_dir).map(|_| ()),
+        };
+        if let Err(e) = result {
+            tracing::error!("Could not remove '{}': {e}", song.file().display());
+            messages.push(format!("Could not remove '{}': {e}", song.file().display()));
+            left.push(song.clone());
+            continue;
+        }
+        playlists_context.library.write().remove(song.path());
+        player.write().update_song(song.path(), kept);
+        for playlist in playlists_context.playlists.read().iter() {
+            if let Err(e) = playlist.replace_song(song.path(), kept.path()) {
+                tracing::error!("Could not update playlist '{}': {e}", playlist.name());
+            }
+        }
+        removed += 1;
+    }
+    playlists_context.library_changed();
+    messages.insert(
+        0,
+        match delete {
+            true => format!("Deleted {removed} files"),
+            false => format!("Moved {removed} files to '{}'", quarantine_dir.display()),
+        },
+    );
+    (messages, left)
+}
+
+/// Find the songs in the library that are the same recording, by their acoustic fingerprints,
+/// and pick the file to keep of each, moving the others to the quarantine or deleting them.
+#[component]
+pub(super) fn DuplicatesPanel(showing: Signal<bool>) -> Element {
+    let playlists_context = use_context::<PlaylistsContext>();
+    let library = playlists_context.library;
+    let player = use_context::<AppContext>().player;
+    let mut search = use_signal(|| None::<(ScanHandle, Receiver<DuplicateEvent>)>);
+    let mut progress = use_signal(|| None::<(usize, usize)>);
+    let mut groups = use_signal(|| None::<Vec<Vec<Song>>>);
+    // The group and the song in it that was picked to keep, waiting for what to do with the others
+    let mut confirm = use_signal(|| None::<(usize, usize)>);
+    let mut messages = use_signal(Vec::<String>::new);
+
+    use_future(move || async move {
+        loop {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let events: Vec<DuplicateEvent> = match search.read().as_ref() {
+                Some((_, events)) => events.try_iter().collect(),
+                None => continue,
+            };
+            for event in events {
+                match event {
+                    DuplicateEvent::Progress { done, total } => progress.set(Some((done, total))),
+                    DuplicateEvent::Error { path, error } => {
+                        tracing::warn!("Could not fingerprint '{}': {}", path.display(), error);
+                    }
+                    DuplicateEvent::Finished {
+                        groups: found,
+                        cancelled,
+                    } => {
+                        search.set(None);
+                        progress.set(None);
+                        if !cancelled {
+                            groups.set(Some(found));
+                        }
+                    }
+                }
+            }
+        }
+    });
+    use_drop(move || {
+        if let Some((handle, _)) = search.peek().as_ref() {
+            handle.cancel();
+        }
+    });
+
+    let start = move |_| {
+        let songs: Vec<Song> = library.read().songs().cloned().collect();
+        search.set(Some(DuplicateFinder::new(songs).start()));
+        progress.set(Some((0, 0)));
+        groups.set(None);
+        messages.write().clear();
+    };
+
+    rsx! {
+        div {
+            class: "duplicates",
+            div {
+                class: "library-header",
+                p {
+                    class: "playlist-name",
+                    "Duplicates"
+                }
+                if search.read().is_some() {
+                    button {
+                        onclick: move |_| {
+                            if let Some((handle, _)) = search.read().as_ref() {
+                                handle.cancel();
+                            }
+                        },
+                        "Cancel"
+                    }
+                } else {
+                    button {
+                        onclick: start,
+                        "Find duplicates"
+                    }
+                }
+                button {
+                    onclick: move |_| showing.set(false),
+                    "Close"
+                }
+            }
+            if let Some((done, total)) = progress() {
+                p {
+                    class: "playlist-missing-header",
+                    "Fingerprinting {done} / {total} songs"
+                }
+            }
+            for message in messages() {
+                p {
+                    class: "playlist-missing-header",
+                    "{message}"
+                }
+            }
+            if let Some(found) = groups() {
+                if found.is_empty() {
+                    p {
+                        class: "playlist-missing-header",
+                        "No duplicates found"
+                    }
+                }
+                for (g, group) in found.into_iter().enumerate() {
+                    div {
+                        key: "{g}",
+                        class: "duplicate-group",
+                        for (i, song) in group.iter().cloned().enumerate() {
+                            div {
+                                class: "song-component",
+                                div {
+                                    class: "song-component-left",
+                                    p {
+                                        class: "song-title",
+                                        { song.title() }
+                                    }
+                                    p {
+                                        class: "song-artist duplicate-path",
+                                        { song.file().display().to_string() }
+                                    }
+                                }
+                                div {
+                                    class: "song-component-right",
+                                    p {
+                                        class: "duplicate-format",
+                                        {
+                                            song.file()
+                                                .extension()
+                                                .map(|ext| ext.to_string_lossy().to_uppercase())
+                                                .unwrap_or_default()
+                                        }
+                                    }
+                                    p {
+                                        class: "duplicate-format",
+                                        {
+                                            song.average_bitrate()
+                                                .map(|kbps| format!("{kbps} kbps"))
+                                                .unwrap_or_default()
+                                        }
+                                    }
+                                    p {
+                                        class: "song-duration",
+                                        { format_time(song.duration().as_secs(), song.duration().as_secs() > 3600) }
+                                    }
+                                    if confirm() == Some((g, i)) {
+                                        for delete in [false, true] {
+                                            button {
+                                                title: if delete { "The files can't be restored" } else { "The files can be restored from the quarantine directory" },
+                                                onclick: {
+                                                    let group = group.clone();
+                                                    move |_| {
+                                                        let (result, left) = keep_one(playlists_context, player, &group, i, delete);
+                                                        messages.set(result);
+                                                        // Files that couldn't be removed stay in the group, to try again
+                                                        if let Some(groups) = groups.write().as_mut() {
+                                                            if left.len() > 1 {
+                                                                groups[g] = left;
+                                                            } else {
+                                                                groups.remove(g);
+                                                            }
+                                                        }
+                                                        confirm.set(None);
+                                                    }
+                                                },
+                                                if delete {
+                                                    "Delete the other {group.len() - 1} files permanently"
+                                                } else {
+                                                    "Quarantine the other {group.len() - 1} files"
+                                                }
+                                            }
+                                        }
+                                        button {
+                                            onclick: move |_| confirm.set(None),
+                                            "Cancel"
+                                        }
+                                    } else {
+                                        button {
+                                            onclick: move |_| confirm.set(Some((g, i))),
+                                            "Keep"
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
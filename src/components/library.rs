@@ -0,0 +1,362 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use amuseing::{
+    library::{Field, Filter, GroupSort, SearchIndex, SongStats, TrackSort},
+    playback::Song,
+};
+use dioxus::prelude::*;
+
+use super::{
+    amuseing::{format_time, AppContext, PlaylistsContext, ResumeMarker},
+    duplicates::DuplicatesPanel,
+    tag_editor::TagEditor,
+};
+
+/// The ways the library can be browsed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Browse {
+    Artists,
+    Albums,
+    Genres,
+    Years,
+}
+
+impl Browse {
+    const ALL: [Browse; 4] = [
+        Browse::Artists,
+        Browse::Albums,
+        Browse::Genres,
+        Browse::Years,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Browse::Artists => "Artists",
+            Browse::Albums => "Albums",
+            Browse::Genres => "Genres",
+            Browse::Years => "Years",
+        }
+    }
+
+    /// The fields that are picked one after another before the tracks are shown.
+    fn levels(self) -> &'static [Field] {
+        match self {
+            Browse::Artists => &[Field::Artist, Field::Album],
+            Browse::Albums => &[Field::Album],
+            Browse::Genres => &[Field::Genre, Field::Artist, Field::Album],
+            Browse::Years => &[Field::Year, Field::Album],
+        }
+    }
+}
+
+fn display(value: &Option<String>) -> &str {
+    value.as_deref().unwrap_or("Unknown")
+}
+
+/// The most search results that are shown.
+const SEARCH_LIMIT: usize = 200;
+
+/// Browse the library's songs by their tags, e.g. artist -> album -> tracks, or search all of them.
+#[component]
+pub(super) fn LibraryPanel() -> Element {
+    let library = use_context::<PlaylistsContext>().library;
+    let mut player = use_context::<AppContext>().player;
+    let mut browse = use_signal(|| Browse::Artists);
+    // The values picked so far, one for each of the browse levels
+    let mut filters = use_signal(Vec::<Filter>::new);
+    let mut group_sort = use_signal(GroupSort::default);
+    let mut track_sort = use_signal(TrackSort::default);
+    let mut query = use_signal(String::new);
+    // The paths of the songs checked for editing their tags
+    let mut selected = use_signal(HashSet::<PathBuf>::new);
+    let mut editing = use_signal(|| false);
+    let mut finding_duplicates = use_signal(|| false);
+    // Only built again when the library's songs change, not on every key press or played song
+    let generation = use_memo(move || library.read().generation());
+    let index = use_memo(move || {
+        generation();
+        SearchIndex::new(&library.peek())
+    });
+
+    let searching = !query.read().trim().is_empty();
+    let levels = browse().levels();
+    let level = filters.read().len();
+    let groups = (!searching && level < levels.len()).then(|| {
+        library
+            .read()
+            .groups(levels[level], &filters.read(), group_sort())
+    });
+    let tracks = if searching {
+        index
+            .read()
+            .search(&query.read(), SEARCH_LIMIT)
+            .into_iter()
+            .cloned()
+            .collect()
+    } else if groups.is_some() {
+        Vec::new()
+    } else {
+        library.read().tracks(&filters.read(), track_sort())
+    };
+    let queued_tracks = tracks.clone();
+    let added_tracks = tracks.clone();
+    let selectable_tracks: Vec<PathBuf> = tracks
+        .iter()
+        .map(|song| song.path().to_path_buf())
+        .collect();
+
+    rsx! {
+        div {
+            class: "library-panel",
+            div {
+                class: "library-header",
+                input {
+                    class: "library-search",
+                    r#type: "search",
+                    placeholder: "Search, e.g. artist:foo year:>2000",
+                    value: "{query}",
+                    oninput: move |event| query.set(event.value()),
+                }
+                for b in Browse::ALL {
+                    button {
+                        class: if browse() == b { "library-tab library-tab-selected" } else { "library-tab" },
+                        onclick: move |_| {
+                            browse.set(b);
+                            filters.write().clear();
+                            query.write().clear();
+                        },
+                        { b.label() }
+                    }
+                }
+                if groups.is_some() {
+                    select {
+                        onchange: move |event| {
+                            group_sort.set(match event.value().as_str() {
+                                "count" => GroupSort::SongCount,
+                                _ => GroupSort::Name,
+                            });
+                        },
+                        option { value: "name", selected: group_sort() == GroupSort::Name, "Name" }
+                        option { value: "count", selected: group_sort() == GroupSort::SongCount, "Song count" }
+                    }
+                } else if !searching {
+                    select {
+                        onchange: move |event| {
+                            track_sort.set(match event.value().as_str() {
+                                "title" => TrackSort::Title,
+                                "artist" => TrackSort::Artist,
+                                "year" => TrackSort::Year,
+                                "duration" => TrackSort::Duration,
+                                "plays" => TrackSort::PlayCount,
+                                "rating" => TrackSort::Rating,
+                                "last-played" => TrackSort::LastPlayed,
+                                "added" => TrackSort::Added,
+                                _ => TrackSort::Album,
+                            });
+                        },
+                        option { value: "album", selected: track_sort() == TrackSort::Album, "Album order" }
+                        option { value: "title", selected: track_sort() == TrackSort::Title, "Title" }
+                        option { value: "artist", selected: track_sort() == TrackSort::Artist, "Artist" }
+                        option { value: "year", selected: track_sort() == TrackSort::Year, "Year" }
+                        option { value: "duration", selected: track_sort() == TrackSort::Duration, "Duration" }
+                        option { value: "plays", selected: track_sort() == TrackSort::PlayCount, "Plays" }
+                        option { value: "rating", selected: track_sort() == TrackSort::Rating, "Rating" }
+                        option { value: "last-played", selected: track_sort() == TrackSort::LastPlayed, "Last played" }
+                        option { value: "added", selected: track_sort() == TrackSort::Added, "Recently added" }
+                    }
+                }
+                if groups.is_none() {
+                    button {
+                        onclick: move |_| {
+                            let songs = queued_tracks.clone();
+                            player.write().edit_queue(|queue| queue.extend(songs));
+                        },
+                        "Queue all"
+                    }
+                    AddToPlaylist { songs: added_tracks, label: "Add all to playlist" }
+                    button {
+                        onclick: move |_| {
+                            let all_selected = selectable_tracks.iter().all(|path| selected.read().contains(path));
+                            if all_selected {
+                                selected.write().clear();
+                            } else {
+                                selected.write().extend(selectable_tracks.iter().cloned());
+                            }
+                        },
+                        "Select all"
+                    }
+                }
+                if !selected.read().is_empty() {
+                    button {
+                        onclick: move |_| editing.set(true),
+                        "Edit tags ({selected.read().len()})"
+                    }
+                }
+                button {
+                    onclick: move |_| finding_duplicates.toggle(),
+                    "Duplicates"
+                }
+            }
+            if editing() && !selected.read().is_empty() {
+                TagEditor { selected, editing }
+            }
+            if finding_duplicates() {
+                DuplicatesPanel { showing: finding_duplicates }
+            }
+            if !searching {
+                div {
+                    class: "library-breadcrumbs",
+                    button {
+                        onclick: move |_| filters.write().clear(),
+                        { browse().label() }
+                    }
+                    for (i, (_, value)) in filters.read().iter().enumerate() {
+                        " › "
+                        button {
+                            onclick: move |_| filters.write().truncate(i + 1),
+                            { display(value) }
+                        }
+                    }
+                }
+            }
+            div {
+                class: "library-list",
+                if let Some(groups) = groups {
+                    for group in groups {
+                        button {
+                            class: "library-group",
+                            onclick: {
+                                let value = group.value.clone();
+                                move |_| filters.write().push((levels[level], value.clone()))
+                            },
+                            p {
+                                { display(&group.value) }
+                            }
+                            p {
+                                class: "library-group-count",
+                                "{group.song_count}"
+                            }
+                        }
+                    }
+                } else {
+                    TrackList { songs: tracks, selected }
+                }
+            }
+        }
+    }
+}
+
+/// The songs in a browsed group or search results, with their play counts and ratings.
+///
+/// With `selected`, each song has a checkbox adding it to or removing it from the selected paths.
+#[component]
+pub(super) fn TrackList(
+    songs: Vec<Song>,
+    #[props(default)] selected: Option<Signal<HashSet<PathBuf>>>,
+) -> Element {
+    let library = use_context::<PlaylistsContext>().library;
+    rsx! {
+        for song in songs {
+            div {
+                class: "song-component",
+                if let Some(mut selected) = selected {
+                    input {
+                        r#type: "checkbox",
+                        checked: selected.read().contains(song.path()),
+                        onchange: {
+                            let path = song.path().to_path_buf();
+                            move |event: FormEvent| {
+                                if event.checked() {
+                                    selected.write().insert(path.clone());
+                                } else {
+                                    selected.write().remove(&path);
+                                }
+                            }
+                        },
+                    }
+                }
+                div {
+                    class: "song-component-left",
+                    p {
+                        class: "song-title",
+                        { song.title() }
+                    }
+                    if let Some(artist) = song.artist() {
+                        p {
+                            class: "song-artist",
+                            { artist }
+                        }
+                    }
+                }
+                div {
+                    class: "song-component-right",
+                    {
+                        let stats = library.read().stats(song.path()).cloned().unwrap_or_default();
+                        rsx! {
+                            p {
+                                class: "song-plays",
+                                title: "{stats.skip_count} skips",
+                                "{stats.play_count} plays"
+                            }
+                            Rating { song: song.clone(), rating: stats.rating }
+                        }
+                    }
+                    ResumeMarker { song: song.clone() }
+                    p {
+                        class: "song-duration",
+                        { format_time(song.duration().as_secs(), song.duration().as_secs() > 3600) }
+                    }
+                    AddToPlaylist { songs: vec![song.clone()], label: "Add to playlist" }
+                }
+            }
+        }
+    }
+}
+
+/// Pick one of the playlist files to add `songs` to, directories can't be added to.
+#[component]
+fn AddToPlaylist(songs: Vec<Song>, label: &'static str) -> Element {
+    let mut playlists_context = use_context::<PlaylistsContext>();
+    let playlists = playlists_context.playlists;
+    rsx! {
+        select {
+            class: "library-add-to-playlist",
+            onchange: move |event| {
+                if let Ok(index) = event.value().parse() {
+                    playlists_context.add_songs(index, songs.clone());
+                }
+            },
+            option { value: "", selected: true, { label } }
+            for (i, playlist) in playlists.read().iter().enumerate() {
+                if playlist.is_file() {
+                    option { value: "{i}", { playlist.name() } }
+                }
+            }
+        }
+    }
+}
+
+/// The song's rating as stars, clicking a star rates the song and clicking its current rating removes it.
+#[component]
+fn Rating(song: Song, rating: Option<u8>) -> Element {
+    let mut playlists_context = use_context::<PlaylistsContext>();
+    rsx! {
+        div {
+            class: "song-rating",
+            for stars in 1..=SongStats::MAX_RATING {
+                button {
+                    class: "song-rating-star",
+                    onclick: {
+                        let path = song.path().to_path_buf();
+                        move |_| {
+                            let new_rating = (rating != Some(stars)).then_some(stars);
+                            playlists_context.library.write().set_rating(&path, new_rating);
+                            playlists_context.library_changed();
+                        }
+                    },
+                    if rating.is_some_and(|rating| rating >= stars) { "★" } else { "☆" }
+                }
+            }
+        }
+    }
+}
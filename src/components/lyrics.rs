@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use amuseing::lyrics::Lyrics;
+use dioxus::{logger::tracing, prelude::*};
+
+use super::amuseing::AppContext;
+
+/// The lyrics of the current song, shown instead of the queue.
+///
+/// The line being sung is highlighted and kept in view, clicking a line seeks to it.
+/// Lyrics without times are shown as plain text.
+#[component]
+pub(super) fn LyricsPanel() -> Element {
+    let mut player = use_context::<AppContext>().player;
+    // Only changes when the song does, so the lyrics aren't read again on every update of the player
+    let current_path = use_memo(move || {
+        player
+            .read()
+            .current()
+            .map(|song| song.path().to_path_buf())
+    });
+    let lyrics = use_memo(move || {
+        current_path.read().as_ref()?;
+        let song = player.peek().current()?;
+        match Lyrics::for_song(&song) {
+            Ok(lyrics) => lyrics,
+            Err(e) => {
+                tracing::warn!("Could not read lyrics of '{}': {e}", song.path().display());
+                None
+            }
+        }
+    });
+    let current_line = use_memo(move || {
+        let position = Duration::from_secs_f64(player.read().time_playing().as_secs_f64());
+        lyrics.read().as_ref()?.current_line(position)
+    });
+    use_effect(move || {
+        if current_line().is_some() {
+            document::eval(
+                "document.querySelector('.lyrics-line-current')?.scrollIntoView({ block: 'center', behavior: 'smooth' })",
+            );
+        }
+    });
+
+    rsx! {
+        div {
+            class: "queue-panel lyrics-panel",
+            match lyrics() {
+                Some(Lyrics::Synced(lines)) => rsx! {
+                    for (i, line) in lines.into_iter().enumerate() {
+                        p {
+                            key: "{i}",
+                            class: if current_line() == Some(i) { "lyrics-line lyrics-line-current" } else { "lyrics-line" },
+                            onclick: move |_| {
+                                if let Err(e) = player.write().seek(line.time) {
+                                    tracing::error!("Could not seek to lyrics line: {e}");
+                                }
+                            },
+                            { line.text }
+                        }
+                    }
+                },
+                Some(Lyrics::Unsynced(text)) => rsx! {
+                    p {
+                        class: "lyrics-text",
+                        { text }
+                    }
+                },
+                None => rsx! {
+                    p {
+                        class: "playlist-missing-header",
+                        "No lyrics"
+                    }
+                },
+            }
+        }
+    }
+}
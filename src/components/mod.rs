@@ -3,4 +3,10 @@
 //! component  to be used in our app.
 
 mod amuseing;
+mod chapters;
+mod duplicates;
+mod library;
+mod lyrics;
+mod smart_playlist;
+mod tag_editor;
 pub use amuseing::Amuseing;
@@ -0,0 +1,259 @@
+use amuseing::{
+    config::Config,
+    library::{MatchMode, Operator, Rule, RuleField, SmartPlaylist, SmartSort},
+};
+use dioxus::{logger::tracing, prelude::*};
+
+use super::{
+    amuseing::{AppContext, PlaylistsContext},
+    library::TrackList,
+};
+
+fn field_label(field: RuleField) -> &'static str {
+    match field {
+        RuleField::Title => "Title",
+        RuleField::Artist => "Artist",
+        RuleField::Album => "Album",
+        RuleField::AlbumArtist => "Album artist",
+        RuleField::Genre => "Genre",
+        RuleField::Composer => "Composer",
+        RuleField::Path => "Path",
+        RuleField::Year => "Year",
+        RuleField::Duration => "Duration (seconds)",
+        RuleField::Bpm => "BPM",
+        RuleField::Rating => "Rating",
+        RuleField::PlayCount => "Play count",
+        RuleField::SkipCount => "Skip count",
+        RuleField::DaysSincePlayed => "Days since played",
+        RuleField::DaysSinceAdded => "Days since added",
+    }
+}
+
+fn operator_label(operator: Operator) -> &'static str {
+    match operator {
+        Operator::Is => "is",
+        Operator::IsNot => "is not",
+        Operator::Contains => "contains",
+        Operator::DoesNotContain => "does not contain",
+        Operator::AtLeast => "is at least",
+        Operator::AtMost => "is at most",
+    }
+}
+
+fn sort_label(sort: SmartSort) -> &'static str {
+    match sort {
+        SmartSort::Random => "Random",
+        SmartSort::Album => "Album order",
+        SmartSort::Title => "Title",
+        SmartSort::Artist => "Artist",
+        SmartSort::Year => "Year",
+        SmartSort::Duration => "Duration",
+        SmartSort::PlayCount => "Plays",
+        SmartSort::Rating => "Rating",
+        SmartSort::LastPlayed => "Last played",
+        SmartSort::Added => "Recently added",
+    }
+}
+
+/// Change the smart playlist at `index` in the config with `edit`, and save the config.
+fn edit_playlist(mut config: Signal<Config>, index: usize, edit: impl FnOnce(&mut SmartPlaylist)) {
+    if let Some(playlist) = config.write().smart_playlists.get_mut(index) {
+        edit(playlist);
+    }
+    if let Err(e) = config.read().write() {
+        tracing::error!("Could not save config: {e}");
+    }
+}
+
+/// The rules of the smart playlist at `index` in the config, and the library's songs matching them.
+///
+/// The songs are picked again whenever the rules or the library change.
+#[component]
+pub(super) fn SmartPlaylistPanel(index: usize) -> Element {
+    let mut config = use_context::<Signal<Config>>();
+    let mut playlists_context = use_context::<PlaylistsContext>();
+    let library = playlists_context.library;
+    let mut player = use_context::<AppContext>().player;
+    let songs = use_memo(move || {
+        let config = config.read();
+        let Some(playlist) = config.smart_playlists.get(index) else {
+            return Vec::new();
+        };
+        library.read().smart_playlist(playlist)
+    });
+    let Some(playlist) = config.read().smart_playlists.get(index).cloned() else {
+        return rsx! {};
+    };
+
+    let delete = move |_| {
+        playlists_context.smart_selected.set(None);
+        config.write().smart_playlists.remove(index);
+        if let Err(e) = config.read().write() {
+            tracing::error!("Could not save config: {e}");
+        }
+    };
+
+    rsx! {
+        div {
+            class: "song-panel",
+            div {
+                class: "song-panel-header",
+                input {
+                    class: "playlist-name",
+                    value: "{playlist.name}",
+                    onchange: move |event| {
+                        edit_playlist(config, index, |playlist| playlist.name = event.value());
+                    },
+                }
+                button {
+                    onclick: move |_| {
+                        let songs = songs();
+                        player.write().edit_queue(|queue| queue.extend(songs));
+                    },
+                    "Queue all"
+                }
+                button {
+                    onclick: delete,
+                    "Delete"
+                }
+            }
+            div {
+                class: "smart-rules",
+                div {
+                    class: "smart-rule",
+                    "Songs matching"
+                    select {
+                        onchange: move |event| {
+                            let match_mode = match event.value().as_str() {
+                                "any" => MatchMode::Any,
+                                _ => MatchMode::All,
+                            };
+                            edit_playlist(config, index, |playlist| playlist.match_mode = match_mode);
+                        },
+                        option { value: "all", selected: playlist.match_mode == MatchMode::All, "all" }
+                        option { value: "any", selected: playlist.match_mode == MatchMode::Any, "any" }
+                    }
+                    "of the rules"
+                }
+                for (i, rule) in playlist.rules.iter().enumerate() {
+                    RuleEditor { key: "{i}", playlist_index: index, rule_index: i, rule: rule.clone() }
+                }
+                div {
+                    class: "smart-rule",
+                    button {
+                        onclick: move |_| {
+                            edit_playlist(config, index, |playlist| {
+                                playlist.rules.push(Rule {
+                                    field: RuleField::Genre,
+                                    operator: Operator::Is,
+                                    value: String::new(),
+                                });
+                            });
+                        },
+                        "Add rule"
+                    }
+                    "Sort by"
+                    select {
+                        onchange: move |event| {
+                            if let Some(sort) = event.value().parse().ok().and_then(|i: usize| SmartSort::ALL.get(i)) {
+                                edit_playlist(config, index, |playlist| playlist.sort = *sort);
+                            }
+                        },
+                        for (i, sort) in SmartSort::ALL.into_iter().enumerate() {
+                            option { value: "{i}", selected: playlist.sort == sort, { sort_label(sort) } }
+                        }
+                    }
+                    if playlist.sort == SmartSort::Random {
+                        button {
+                            onclick: move |_| edit_playlist(config, index, SmartPlaylist::reshuffle),
+                            "Reshuffle"
+                        }
+                    }
+                    "Limit"
+                    input {
+                        class: "smart-limit",
+                        r#type: "number",
+                        min: 1,
+                        placeholder: "None",
+                        value: playlist.limit.map(|limit| limit.to_string()).unwrap_or_default(),
+                        onchange: move |event| {
+                            let limit = event.value().trim().parse().ok();
+                            edit_playlist(config, index, |playlist| playlist.limit = limit);
+                        },
+                    }
+                }
+            }
+            p {
+                class: "playlist-missing-header",
+                "{songs.read().len()} songs"
+            }
+            TrackList { songs: songs() }
+        }
+    }
+}
+
+/// One rule of a smart playlist, changing the field, operator or value saves it.
+#[component]
+fn RuleEditor(playlist_index: usize, rule_index: usize, rule: Rule) -> Element {
+    let config = use_context::<Signal<Config>>();
+    let edit_rule = move |edit: &dyn Fn(&mut Rule)| {
+        edit_playlist(config, playlist_index, |playlist| {
+            if let Some(rule) = playlist.rules.get_mut(rule_index) {
+                edit(rule);
+            }
+        });
+    };
+    let operators = rule.field.operators();
+
+    rsx! {
+        div {
+            class: "smart-rule",
+            select {
+                onchange: move |event| {
+                    let Some(field) = event.value().parse().ok().and_then(|i: usize| RuleField::ALL.get(i)) else {
+                        return;
+                    };
+                    edit_rule(&|rule| {
+                        rule.field = *field;
+                        // Text and number fields have different operators
+                        if !field.operators().contains(&rule.operator) {
+                            rule.operator = field.operators()[0];
+                        }
+                    });
+                },
+                for (i, field) in RuleField::ALL.into_iter().enumerate() {
+                    option { value: "{i}", selected: rule.field == field, { field_label(field) } }
+                }
+            }
+            select {
+                onchange: move |event| {
+                    if let Some(operator) = event.value().parse().ok().and_then(|i: usize| operators.get(i)) {
+                        edit_rule(&|rule| rule.operator = *operator);
+                    }
+                },
+                for (i, operator) in operators.iter().enumerate() {
+                    option { value: "{i}", selected: rule.operator == *operator, { operator_label(*operator) } }
+                }
+            }
+            input {
+                class: "smart-rule-value",
+                r#type: if rule.field.is_number() { "number" } else { "text" },
+                value: "{rule.value}",
+                onchange: move |event| {
+                    let value = event.value();
+                    edit_rule(&|rule| rule.value = value.clone());
+                },
+            }
+            button {
+                class: "playlist-song-remove",
+                title: "Remove rule",
+                onclick: move |_| {
+                    edit_playlist(config, playlist_index, |playlist| {
+                        playlist.rules.remove(rule_index);
+                    });
+                },
+                "x"
+            }
+        }
+    }
+}
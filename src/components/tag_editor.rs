@@ -0,0 +1,240 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::mpsc::Receiver,
+    time::Duration,
+};
+
+use amuseing::{
+    library::{BatchEdit, BatchEditEvent, BatchEditKind},
+    playback::Song,
+    scanner::ScanHandle,
+    tags::{TagEdit, TagField},
+};
+use dioxus::{logger::tracing, prelude::*};
+
+use super::amuseing::{AppContext, PlaylistsContext};
+
+fn field_label(field: TagField) -> &'static str {
+    match field {
+        TagField::Title => "Title",
+        TagField::Artist => "Artist",
+        TagField::Album => "Album",
+        TagField::AlbumArtist => "Album artist",
+        TagField::TrackNumber => "Track",
+        TagField::DiscNumber => "Disc",
+        TagField::Year => "Year",
+        TagField::Genre => "Genre",
+        TagField::Composer => "Composer",
+        TagField::Comment => "Comment",
+        TagField::Bpm => "BPM",
+    }
+}
+
+/// Edit the tags of the songs selected in the library, all at once.
+///
+/// Fields that differ between the songs are left empty, and only the fields that were changed are written.
+/// Tags can also be read from the file names, and the files renamed from the tags, with patterns like `{artist} - {title}`.
+#[component]
+pub(super) fn TagEditor(selected: Signal<HashSet<PathBuf>>, editing: Signal<bool>) -> Element {
+    let playlists_context = use_context::<PlaylistsContext>();
+    let library = playlists_context.library;
+    let player = use_context::<AppContext>().player;
+    let mut edits = use_signal(HashMap::<TagField, String>::new);
+    let mut name_pattern = use_signal(|| String::from("{track} - {title}"));
+    let mut rename_pattern = use_signal(|| String::from("{track} - {title}"));
+    let mut message = use_signal(String::new);
+    let mut running = use_signal(|| None::<(ScanHandle, Receiver<BatchEditEvent>)>);
+    let mut progress = use_signal(|| None::<(usize, usize)>);
+
+    let songs: Vec<Song> = {
+        let library = library.read();
+        selected
+            .read()
+            .iter()
+            .filter_map(|path| library.get(path).cloned())
+            .collect()
+    };
+    // The value shared by all the songs, None if they differ
+    let common = |field: TagField| -> Option<String> {
+        let mut values = songs.iter().map(|song| field.get(song.tags()));
+        let first = values.next()?;
+        values
+            .all(|value| value == first)
+            .then(|| first.unwrap_or_default())
+    };
+
+    // Put the edited songs in the library and the player's queues, and keep them selected when they're renamed
+    use_future(move || async move {
+        let mut playlists_context = playlists_context;
+        let mut player = player;
+        let mut edited = 0;
+        loop {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let events: Vec<BatchEditEvent> = match running.read().as_ref() {
+                Some((_, events)) => events.try_iter().collect(),
+                None => continue,
+            };
+            let mut changed = false;
+            for event in events {
+                match event {
+                    BatchEditEvent::Edited { path, song } => {
+                        player.write().update_song(&path, &song);
+                        let mut selected = selected.write();
+                        selected.remove(&path);
+                        selected.insert(song.path().to_path_buf());
+                        if let Err(e) = playlists_context.library.write().replace(&path, *song) {
+                            tracing::error!("Could not add edited song: {e}");
+                        }
+                        edited += 1;
+                        changed = true;
+                    }
+                    BatchEditEvent::Progress { done, total } => progress.set(Some((done, total))),
+                    BatchEditEvent::Error { path, error } => {
+                        message.set(format!("Could not edit '{}': {error}", path.display()));
+                    }
+                    BatchEditEvent::Finished { .. } => {
+                        running.set(None);
+                        progress.set(None);
+                        if message.read().is_empty() {
+                            message.set(format!("Edited {edited} songs"));
+                        }
+                        edited = 0;
+                    }
+                }
+            }
+            if changed {
+                playlists_context.library_changed();
+            }
+        }
+    });
+    use_drop(move || {
+        if let Some((handle, _)) = running.peek().as_ref() {
+            handle.cancel();
+        }
+    });
+
+    let mut start = move |kind: BatchEditKind| {
+        if running.read().is_some() {
+            return;
+        }
+        let edit = BatchEdit::new(
+            library.read().clone(),
+            selected.read().iter().cloned(),
+            kind,
+        )
+        .playlists(playlists_context.playlists.read().clone());
+        running.set(Some(edit.start()));
+        progress.set(Some((0, selected.read().len())));
+        message.write().clear();
+    };
+    let save = move |_| {
+        let changes: Vec<TagEdit> = edits
+            .read()
+            .iter()
+            .map(|(field, value)| (*field, Some(value.clone()).filter(|v| !v.trim().is_empty())))
+            .collect();
+        if changes.is_empty() {
+            return;
+        }
+        start(BatchEditKind::Tags(changes));
+        edits.write().clear();
+    };
+    let from_file_names = move |_| start(BatchEditKind::FromFileNames(name_pattern()));
+    let rename = move |_| start(BatchEditKind::Rename(rename_pattern()));
+
+    rsx! {
+        div {
+            class: "tag-editor",
+            div {
+                class: "library-header",
+                p {
+                    class: "playlist-name",
+                    "Editing {songs.len()} songs"
+                }
+                button {
+                    onclick: save,
+                    "Save"
+                }
+                button {
+                    onclick: move |_| {
+                        edits.write().clear();
+                        editing.set(false);
+                    },
+                    "Close"
+                }
+            }
+            div {
+                class: "tag-editor-fields",
+                for field in TagField::ALL {
+                    label {
+                        { field_label(field) }
+                    }
+                    {
+                        let common = common(field);
+                        let value = edits.read().get(&field).cloned().or(common.clone()).unwrap_or_default();
+                        rsx! {
+                            input {
+                                r#type: if field.is_number() { "number" } else { "text" },
+                                placeholder: if common.is_none() { "(multiple)" } else { "" },
+                                value: "{value}",
+                                oninput: move |event| {
+                                    edits.write().insert(field, event.value());
+                                },
+                            }
+                        }
+                    }
+                }
+            }
+            div {
+                class: "smart-rule",
+                "Tags from file name"
+                input {
+                    class: "smart-rule-value",
+                    value: "{name_pattern}",
+                    oninput: move |event| name_pattern.set(event.value()),
+                }
+                button {
+                    onclick: from_file_names,
+                    "Apply"
+                }
+            }
+            div {
+                class: "smart-rule",
+                "Rename from tags"
+                input {
+                    class: "smart-rule-value",
+                    value: "{rename_pattern}",
+                    oninput: move |event| rename_pattern.set(event.value()),
+                }
+                button {
+                    onclick: rename,
+                    "Rename"
+                }
+            }
+            if let Some((done, total)) = progress() {
+                div {
+                    class: "smart-rule",
+                    p {
+                        class: "playlist-missing-header",
+                        "Editing {done} / {total} songs"
+                    }
+                    button {
+                        onclick: move |_| {
+                            if let Some((handle, _)) = running.read().as_ref() {
+                                handle.cancel();
+                            }
+                        },
+                        "Cancel"
+                    }
+                }
+            }
+            if !message.read().is_empty() {
+                p {
+                    class: "playlist-missing-header",
+                    "{message}"
+                }
+            }
+        }
+    }
+}
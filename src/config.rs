@@ -2,9 +2,10 @@ use std::{
     fs,
     ops::{Deref, DerefMut},
     path::{Path, PathBuf},
+    time::Duration,
 };
 
-use crate::{errors::ConfigError, playback::Playlist};
+use crate::{errors::ConfigError, library::SmartPlaylist, playback::Playlist, queue::RepeatMode};
 use serde::{Deserialize, Serialize};
 
 use tracing::{debug, error, info, warn};
@@ -61,9 +62,27 @@ impl Default for Playlists {
 
 #[derive(Copy, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
+#[serde(default)]
 pub struct PlayerConfig {
     pub buffer_size: usize,
     pub volume: f64,
+    /// How many queue edits can be undone.
+    pub undo_depth: usize,
+    /// Keep playing similar songs from the playlists when the queue runs out.
+    pub autoplay: bool,
+    /// The repeat mode of new queues.
+    pub repeat_mode: RepeatMode,
+    /// Songs at least this many minutes long, like audiobooks and podcasts, resume where they were stopped.
+    ///
+    /// 0 turns resuming off.
+    pub resume_threshold_minutes: u64,
+}
+
+impl PlayerConfig {
+    /// How long songs have to be to resume where they were stopped, see [`resume_threshold_minutes`](Self::resume_threshold_minutes).
+    pub fn resume_threshold(&self) -> Duration {
+        Duration::from_secs(self.resume_threshold_minutes * 60)
+    }
 }
 
 impl Default for PlayerConfig {
@@ -71,6 +90,10 @@ impl Default for PlayerConfig {
         Self {
             buffer_size: 2048,
             volume: 0.5,
+            undo_depth: 100,
+            autoplay: false,
+            repeat_mode: RepeatMode::All,
+            resume_threshold_minutes: 20,
         }
     }
 }
@@ -83,6 +106,13 @@ pub struct InnerConfig {
     #[serde(rename = "playlist")]
     #[serde(default)]
     pub playlists: Playlists,
+    /// Playlists of the library's songs that match rules, see [`SmartPlaylist`].
+    #[serde(rename = "smart-playlist")]
+    #[serde(default)]
+    pub smart_playlists: Vec<SmartPlaylist>,
+    /// Names of files and directories that are skipped when scanning the playlists, `*` and `?` are wildcards.
+    #[serde(default)]
+    pub scan_ignore: Vec<String>,
 }
 
 pub struct Config {
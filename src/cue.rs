@@ -0,0 +1,392 @@
+//! Reading cue sheets, which split one audio file into tracks.
+//!
+//! Each track of a sheet becomes a [`Song`] with a [`CueRange`] in the audio file. The track's path is a virtual path
+//! under the sheet, like `/music/live.cue/03`, so it can be kept in the library and in playlists like any other song.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::CueError, playback::Song, tags::Tags};
+
+/// Cue sheet times are in minutes, seconds and frames, with 75 frames in a second.
+const FRAMES_PER_SECOND: u64 = 75;
+
+/// The part of an audio file a track of a cue sheet is, see [`Song::cue_range`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CueRange {
+    /// The audio file the track is in.
+    pub file: PathBuf,
+    pub start: Duration,
+    /// Where the next track starts, None for the last track in the file.
+    pub end: Option<Duration>,
+}
+
+/// A track of a [`CueSheet`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub songwriter: Option<String>,
+    /// The audio file the track is in, relative paths in the sheet are relative to its directory.
+    pub file: PathBuf,
+    /// Where the track starts in the file, its `INDEX 01`.
+    pub start: Duration,
+}
+
+/// A parsed `.cue` file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CueSheet {
+    /// The album's title.
+    pub title: Option<String>,
+    /// The album's artist.
+    pub performer: Option<String>,
+    /// From `REM GENRE`.
+    pub genre: Option<String>,
+    /// From `REM DATE`.
+    pub date: Option<String>,
+    pub tracks: Vec<CueTrack>,
+}
+
+impl CueSheet {
+    /// Read the cue sheet at `path`, which is UTF-8 or, for older sheets, Latin-1.
+    pub fn from_path(path: &Path) -> Result<Self, CueError> {
+        let bytes = fs::read(path)?;
+        let text = match String::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(e) => e.into_bytes().iter().map(|&byte| byte as char).collect(),
+        };
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let sheet = Self::parse(text.trim_start_matches('\u{feff}'), dir);
+        if sheet.tracks.is_empty() {
+            return Err(CueError::NoTracks(path.to_path_buf()));
+        }
+        Ok(sheet)
+    }
+
+    /// Parse the text of a cue sheet in `dir`, ignoring the commands that aren't needed for playing it.
+    ///
+    /// Tracks without an `INDEX 01` are left out.
+    pub fn parse(text: &str, dir: &Path) -> Self {
+        let mut sheet = CueSheet::default();
+        let mut file = None;
+        let mut track: Option<CueTrack> = None;
+        let mut has_start = false;
+        let finish_track = |sheet: &mut CueSheet, track: Option<CueTrack>, has_start| {
+            if let Some(track) = track.filter(|_| has_start) {
+                sheet.tracks.push(track);
+            }
+        };
+        for line in text.lines() {
+            let (command, rest) = split_word(line.trim());
+            match command.to_ascii_uppercase().as_str() {
+                "FILE" => file = Some(dir.join(file_name(rest))),
+                "TRACK" => {
+                    finish_track(&mut sheet, track.take(), has_start);
+                    has_start = false;
+                    let (number, _) = split_word(rest);
+                    track = file.clone().map(|file| CueTrack {
+                        number: number.parse().unwrap_or(sheet.tracks.len() as u32 + 1),
+                        file,
+                        ..Default::default()
+                    });
+                }
+                "INDEX" => {
+                    let (number, time) = split_word(rest);
+                    if let (Some(track), Ok(1)) = (&mut track, number.parse::<u32>()) {
+                        if let Some(start) = parse_time(time) {
+                            track.start = start;
+                            has_start = true;
+                        }
+                    }
+                }
+                "TITLE" | "PERFORMER" | "SONGWRITER" => {
+                    let value = Some(unquote(rest).to_string()).filter(|v| !v.is_empty());
+                    match (command.to_ascii_uppercase().as_str(), &mut track) {
+                        ("TITLE", Some(track)) => track.title = value,
+                        ("PERFORMER", Some(track)) => track.performer = value,
+                        ("SONGWRITER", Some(track)) => track.songwriter = value,
+                        ("TITLE", None) => sheet.title = value,
+                        ("PERFORMER", None) => sheet.performer = value,
+                        _ => {}
+                    }
+                }
+                "REM" => {
+                    let (key, value) = split_word(rest);
+                    let value = Some(unquote(value).to_string()).filter(|v| !v.is_empty());
+                    match key.to_ascii_uppercase().as_str() {
+                        "GENRE" => sheet.genre = value,
+                        "DATE" => sheet.date = value,
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        finish_track(&mut sheet, track, has_start);
+        sheet
+    }
+
+    /// Make the songs of the sheet at `path`, reading each audio file once for its duration, tags and album art.
+    ///
+    /// Tags in the sheet take precedence over the audio file's tags, album art is cached in `artwork_dir`, see [`Song::from_path`].
+    pub fn songs(&self, path: &Path, artwork_dir: Option<&Path>) -> Result<Vec<Song>, CueError> {
+        let mut files: HashMap<&Path, Song> = HashMap::new();
+        for track in &self.tracks {
+            if !files.contains_key(track.file.as_path()) {
+                let song = Song::from_file(track.file.clone(), artwork_dir)?;
+                files.insert(&track.file, song);
+            }
+        }
+        let mut songs = Vec::with_capacity(self.tracks.len());
+        for (i, track) in self.tracks.iter().enumerate() {
+            let file_song = &files[track.file.as_path()];
+            let end = self
+                .tracks
+                .get(i + 1)
+                .filter(|next| next.file == track.file)
+                .map(|next| next.start);
+            let duration = end
+                .unwrap_or(*file_song.duration())
+                .saturating_sub(track.start);
+            let year = self
+                .date
+                .as_deref()
+                .and_then(|date| date.get(..4)?.parse().ok());
+            let tags = Tags {
+                title: track.title.clone(),
+                artist: track
+                    .performer
+                    .clone()
+                    .or_else(|| self.performer.clone())
+                    .or_else(|| file_song.tags().artist.clone()),
+                album: self
+                    .title
+                    .clone()
+                    .or_else(|| file_song.tags().album.clone()),
+                album_artist: self
+                    .performer
+                    .clone()
+                    .or_else(|| file_song.tags().album_artist.clone()),
+                track_number: Some(track.number),
+                year: year.or(file_song.tags().year),
+                genre: self
+                    .genre
+                    .clone()
+                    .or_else(|| file_song.tags().genre.clone()),
+                composer: track.songwriter.clone(),
+                ..file_song.tags().clone()
+            };
+            let title = track
+                .title
+                .clone()
+                .unwrap_or_else(|| format!("Track {:02}", track.number));
+            let range = CueRange {
+                file: file_song.path().to_path_buf(),
+                start: track.start,
+                end,
+            };
+            songs.push(file_song.cue_track(
+                track_path(path, track.number),
+                title,
+                tags,
+                range,
+                duration,
+            ));
+        }
+        Ok(songs)
+    }
+}
+
+/// Read the songs of the cue sheet at `path`, see [`CueSheet::songs`].
+pub fn read_songs(path: &Path, artwork_dir: Option<&Path>) -> Result<Vec<Song>, CueError> {
+    CueSheet::from_path(path)?.songs(path, artwork_dir)
+}
+
+/// Check if `path` is a `.cue` file, ignoring case.
+pub fn is_cue_sheet(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("cue"))
+}
+
+/// The virtual path of the track numbered `number` in the sheet at `sheet`.
+pub fn track_path(sheet: &Path, number: u32) -> PathBuf {
+    sheet.join(format!("{number:02}"))
+}
+
+/// The path of the cue sheet a virtual track path is in, None if `path` isn't one.
+pub fn sheet_of(path: &Path) -> Option<&Path> {
+    let sheet = path.parent()?;
+    let number = path.file_name()?.to_str()?;
+    (is_cue_sheet(sheet) && number.parse::<u32>().is_ok()).then_some(sheet)
+}
+
+/// Like [`Path::canonicalize`], for virtual track paths only the sheet's path is made canonical.
+pub fn canonicalize(path: &Path) -> std::io::Result<PathBuf> {
+    match (sheet_of(path), path.file_name()) {
+        (Some(sheet), Some(number)) => Ok(sheet.canonicalize()?.join(number)),
+        _ => path.canonicalize(),
+    }
+}
+
+/// The file a song's path comes from, the sheet for virtual track paths and `path` itself otherwise.
+pub fn source_path(path: &Path) -> &Path {
+    sheet_of(path).unwrap_or(path)
+}
+
+/// Find a cue sheet next to the audio file at `path` that splits it into tracks.
+pub fn sheet_referencing(path: &Path) -> Option<PathBuf> {
+    path.parent()?
+        .read_dir()
+        .ok()?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|sheet| is_cue_sheet(sheet))
+        .find(|sheet| {
+            CueSheet::from_path(sheet)
+                .is_ok_and(|cue| cue.tracks.iter().any(|track| track.file == path))
+        })
+}
+
+/// Split the first word off `s`, the rest has its leading whitespace removed.
+fn split_word(s: &str) -> (&str, &str) {
+    match s.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (s, ""),
+    }
+}
+
+/// The file name of a `FILE` command, without the file type after it like `WAVE` or `MP3`.
+fn file_name(rest: &str) -> &str {
+    match rest.strip_prefix('"').and_then(|rest| rest.split_once('"')) {
+        Some((name, _)) => name,
+        None => split_word(rest).0,
+    }
+}
+
+/// Remove the quotes around a value, if it has them.
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+/// Parse a time like `03:25:40`, in minutes, seconds and frames.
+fn parse_time(time: &str) -> Option<Duration> {
+    let mut parts = time.trim().splitn(3, ':').map(|part| part.parse::<u64>());
+    let (minutes, seconds, frames) = (
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+    );
+    let millis = ((minutes * 60 + seconds) * FRAMES_PER_SECOND + frames) * 1000 / FRAMES_PER_SECOND;
+    Some(Duration::from_millis(millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHEET: &str = r#"REM GENRE Rock
+REM DATE 1999
+PERFORMER "The Band"
+TITLE "Live at Somewhere"
+FILE "live part 1.flac" WAVE
+  TRACK 01 AUDIO
+    TITLE "Intro"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "Song"
+    PERFORMER "Guest"
+    SONGWRITER "Writer"
+    INDEX 00 03:10:00
+    INDEX 01 03:12:37
+FILE "part 2.flac" WAVE
+  TRACK 03 AUDIO
+    INDEX 01 00:00:00
+"#;
+
+    #[test]
+    fn test_parse() {
+        let sheet = CueSheet::parse(SHEET, Path::new("/music"));
+        assert_eq!(sheet.title.as_deref(), Some("Live at Somewhere"));
+        assert_eq!(sheet.performer.as_deref(), Some("The Band"));
+        assert_eq!(sheet.genre.as_deref(), Some("Rock"));
+        assert_eq!(sheet.date.as_deref(), Some("1999"));
+        assert_eq!(
+            sheet.tracks,
+            vec![
+                CueTrack {
+                    number: 1,
+                    title: Some("Intro".into()),
+                    file: "/music/live part 1.flac".into(),
+                    ..Default::default()
+                },
+                CueTrack {
+                    number: 2,
+                    title: Some("Song".into()),
+                    performer: Some("Guest".into()),
+                    songwriter: Some("Writer".into()),
+                    file: "/music/live part 1.flac".into(),
+                    start: Duration::from_millis(192_493),
+                },
+                CueTrack {
+                    number: 3,
+                    file: "/music/part 2.flac".into(),
+                    ..Default::default()
+                },
+            ]
+        );
+        assert_eq!(file_name("unquoted.mp3 MP3"), "unquoted.mp3");
+    }
+
+    #[test]
+    fn test_track_path() {
+        let path = track_path(Path::new("/music/live.cue"), 3);
+        assert_eq!(path, Path::new("/music/live.cue/03"));
+        assert_eq!(sheet_of(&path), Some(Path::new("/music/live.cue")));
+        assert_eq!(sheet_of(Path::new("/music/album/03")), None);
+        assert_eq!(sheet_of(Path::new("/music/live.cue/cover.jpg")), None);
+    }
+
+    #[test]
+    fn test_read_songs() {
+        let dir = std::env::temp_dir().join("amuseing-test-cue");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        // 40 silent MPEG-1 layer 3 frames, a bit over a second long
+        let mut frame = vec![0xff, 0xfb, 0x90, 0x64];
+        frame.resize(417, 0);
+        fs::write(dir.join("live.mp3"), frame.repeat(40)).unwrap();
+        // Latin-1, like sheets written by older rippers
+        let sheet = b"PERFORMER \"Caf\xe9\"\nFILE \"live.mp3\" MP3\nTRACK 01 AUDIO\nINDEX 01 00:00:00\nTRACK 02 AUDIO\nTITLE \"Two\"\nINDEX 01 00:00:30\n";
+        let sheet_path = dir.join("live.cue");
+        fs::write(&sheet_path, sheet).unwrap();
+
+        let songs = read_songs(&sheet_path, None).unwrap();
+        assert_eq!(songs.len(), 2);
+        assert_eq!(songs[0].path(), dir.join("live.cue/01"));
+        assert_eq!(
+            songs[0].file(),
+            dir.join("live.mp3").canonicalize().unwrap()
+        );
+        assert_eq!(songs[0].title(), "Track 01");
+        assert_eq!(songs[0].artist(), Some("Café"));
+        assert_eq!(*songs[0].duration(), Duration::from_millis(400));
+        assert_eq!(songs[1].title(), "Two");
+        assert_eq!(songs[1].tags().track_number, Some(2));
+        let range = songs[1].cue_range().unwrap();
+        assert_eq!((range.start, range.end), (Duration::from_millis(400), None));
+        assert!(songs[1].duration() > &Duration::from_millis(600));
+        assert_eq!(sheet_referencing(&dir.join("live.mp3")), Some(sheet_path));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -59,6 +59,13 @@ impl<T: PartialOrd + Debug> OutOfBoundsError<T> {
     }
 }
 
+/// Returned when parsing a [`RepeatMode`] from a string fails.
+///
+/// [`RepeatMode`]: crate::queue::RepeatMode
+#[derive(Debug, Error)]
+#[error("Unknown repeat mode '{0}', expected 'off', 'one' or 'all'")]
+pub struct ParseRepeatModeError(pub String);
+
 #[derive(Debug, Error)]
 pub enum StreamSetupError {
     #[error("Unsupported sample format {0}")]
@@ -78,3 +85,133 @@ pub enum ConfigError {
     #[error("Could not serialize config, error: {0}")]
     SerializeError(#[from] toml::ser::Error),
 }
+
+/// Returned when managing the named queues of a [`Player`].
+///
+/// [`Player`]: crate::playback::Player
+#[derive(Debug, Error)]
+pub enum PlayContextError {
+    #[error("There is no queue named '{0}'")]
+    NotFound(String),
+    #[error("A queue named '{0}' already exists")]
+    AlreadyExists(String),
+    /// The queue can't be removed because it's the one being played.
+    #[error("The queue '{0}' is active")]
+    Active(String),
+}
+
+#[derive(Debug, Error)]
+pub enum SessionError {
+    #[error("Io Error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Could not deserialize session, error: {0}")]
+    DeserializeError(#[from] toml::de::Error),
+    #[error("Could not serialize session, error: {0}")]
+    SerializeError(#[from] toml::ser::Error),
+}
+
+/// Returned when album art can't be read or its thumbnail can't be cached.
+#[derive(Debug, Error)]
+pub enum ArtworkError {
+    #[error("Io Error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Image Error: {0}")]
+    ImageError(#[from] image::ImageError),
+}
+
+#[derive(Debug, Error)]
+pub enum LibraryError {
+    #[error("Io Error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Could not deserialize library, error: {0}")]
+    DeserializeError(#[from] toml::de::Error),
+    #[error("Could not serialize library, error: {0}")]
+    SerializeError(#[from] toml::ser::Error),
+}
+
+/// Returned when a playlist file can't be read or written.
+#[derive(Debug, Error)]
+pub enum PlaylistFileError {
+    #[error("Io Error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Could not parse XSPF playlist, error: {0}")]
+    XmlError(#[from] quick_xml::Error),
+    #[error("'{0}' is not an M3U, PLS or XSPF playlist")]
+    UnknownFormat(std::path::PathBuf),
+}
+
+/// Returned when the listening history can't be recorded or exported.
+#[derive(Debug, Error)]
+pub enum HistoryError {
+    #[error("Io Error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Could not read or write listening history, error: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+/// Returned when the tags of a song can't be written to its file.
+#[derive(Debug, Error)]
+pub enum TagWriteError {
+    #[error("Io Error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Could not write ID3 tag, error: {0}")]
+    Id3Error(#[from] id3::Error),
+    #[error("Could not read ogg file, error: {0}")]
+    OggError(#[from] ogg::OggReadError),
+    #[error("The file is not a valid {0} file")]
+    Malformed(&'static str),
+    #[error("Writing tags to '{0}' files is not supported")]
+    UnsupportedFormat(String),
+    #[error("Could not read the written song, error: {0}")]
+    ReadError(#[from] symphonia::core::errors::Error),
+    #[error("A file named '{0}' already exists")]
+    AlreadyExists(std::path::PathBuf),
+    /// Renaming from tags needs every tag in the pattern.
+    #[error("The song is missing a tag used in the pattern")]
+    MissingTag,
+    #[error("Invalid pattern: {0}")]
+    PatternError(#[from] TagPatternError),
+}
+
+/// Returned when the lyrics of a song can't be read.
+#[derive(Debug, Error)]
+pub enum LyricsError {
+    #[error("Io Error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Could not read ID3 tag, error: {0}")]
+    Id3Error(#[from] id3::Error),
+    #[error("Could not read the song's tags, error: {0}")]
+    ReadError(#[from] symphonia::core::errors::Error),
+}
+
+/// Returned when a file name pattern like `{artist} - {title}` can't be parsed.
+#[derive(Debug, Error)]
+pub enum TagPatternError {
+    #[error("Unknown tag '{{{0}}}'")]
+    UnknownField(String),
+    #[error("A '{{' is never closed")]
+    Unclosed,
+    /// Two tags next to each other can't be told apart in a file name.
+    #[error("Tags must be separated by text")]
+    AdjacentFields,
+}
+
+/// Returned when a cue sheet or the audio files it splits can't be read.
+#[derive(Debug, Error)]
+pub enum CueError {
+    #[error("Io Error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Could not read the sheet's audio file, error: {0}")]
+    ReadError(#[from] symphonia::core::errors::Error),
+    #[error("The cue sheet '{0}' has no tracks")]
+    NoTracks(std::path::PathBuf),
+}
+
+/// Returned when the chapters of a song can't be read.
+#[derive(Debug, Error)]
+pub enum ChaptersError {
+    #[error("Io Error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Could not read ID3 tag, error: {0}")]
+    Id3Error(#[from] id3::Error),
+}
@@ -0,0 +1,517 @@
+//! Acoustic fingerprints of songs, for finding the same recording in files with different names, formats and bitrates.
+//!
+//! The fingerprints are in the style of Chromaprint: the audio is downmixed and resampled to 11025 Hz, split into
+//! overlapping frames, and the energy in each frame's spectrum is folded into the 12 pitch classes of a chroma vector.
+//! Each frame then becomes 24 bits, from comparing neighbouring pitch classes with each other and with the last frame.
+//! Fingerprints of the same recording match in most of their bits, unrelated ones in about half of them.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use realfft::RealFftPlanner;
+use symphonia::core::{
+    audio::{AudioBuffer, Signal},
+    errors::{Error, Result as SymphoniaResult},
+};
+use tracing::debug;
+
+use crate::{config::Config, playback::Song, scanner::ScanHandle};
+
+/// The sample rate the audio is resampled to before it's fingerprinted.
+const SAMPLE_RATE: u32 = 11025;
+const FRAME_LEN: usize = 4096;
+/// Frames overlap by two thirds, so there are about 8 frames in a second.
+const FRAME_HOP: usize = FRAME_LEN / 3;
+/// Only the start of a song is fingerprinted.
+const MAX_DURATION: Duration = Duration::from_secs(120);
+/// The frequencies that count towards the chroma vectors, from A0 to A7.
+const MIN_FREQ: f32 = 27.5;
+const MAX_FREQ: f32 = 3520.;
+/// How many frames the chroma vectors are averaged over, which evens out small differences in timing.
+const SMOOTHING: usize = 4;
+/// How many bits of each frame's `u32` are used.
+const BITS: u32 = 24;
+/// How many frames fingerprints are shifted against each other when they're compared,
+/// for files with a bit more or less silence at the start.
+const MAX_OFFSET: usize = 24;
+/// Fingerprints have to overlap by this many frames to be compared, about 5 seconds.
+const MIN_OVERLAP: usize = 40;
+/// Songs whose durations differ by more than this aren't compared.
+const MAX_DURATION_DIFFERENCE: Duration = Duration::from_secs(5);
+/// The fraction of matching bits from which two fingerprints are of the same recording.
+pub const DUPLICATE_SIMILARITY: f64 = 0.85;
+
+/// The acoustic fingerprint of a song, see the [module docs](self).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Fingerprint(Vec<u32>);
+
+impl Fingerprint {
+    /// Decode the start of `song` and fingerprint it.
+    pub fn of_song(song: &Song) -> SymphoniaResult<Self> {
+        let (mut reader, mut decoder) = song.reader_decoder()?;
+        let track = reader
+            .default_track()
+            .ok_or(Error::Unsupported("file without a track"))?;
+        let track_id = track.id;
+        let sample_rate = track
+            .codec_params
+            .sample_rate
+            .ok_or(Error::Unsupported("track without a sample rate"))?;
+        let mut resampler = Resampler::new(sample_rate);
+        let max_samples = (MAX_DURATION.as_secs() * SAMPLE_RATE as u64) as usize;
+        while resampler.samples.len() < max_samples {
+            let packet = match reader.next_packet() {
+                Ok(packet) => packet,
+                // The end of the file
+                Err(Error::IoError(_)) => break,
+                Err(e) => return Err(e),
+            };
+            if packet.track_id() != track_id {
+                continue;
+            }
+            let decoded = match decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A corrupt packet is skipped, like when playing the song
+                Err(Error::DecodeError(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            let mut buffer: AudioBuffer<f32> = decoded.make_equivalent();
+            decoded.convert(&mut buffer);
+            let channels = buffer.spec().channels.count();
+            for frame in 0..buffer.frames() {
+                let sum: f32 = (0..channels)
+                    .map(|channel| buffer.chan(channel)[frame])
+                    .sum();
+                resampler.push(sum / channels as f32);
+            }
+        }
+        Ok(Self::from_resampled(&resampler.samples))
+    }
+
+    /// Fingerprint mono `samples` at `sample_rate`.
+    pub fn from_samples(samples: &[f32], sample_rate: u32) -> Self {
+        let mut resampler = Resampler::new(sample_rate);
+        for sample in samples {
+            resampler.push(*sample);
+        }
+        Self::from_resampled(&resampler.samples)
+    }
+
+    /// Fingerprint mono samples at [`SAMPLE_RATE`].
+    fn from_resampled(samples: &[f32]) -> Self {
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FRAME_LEN);
+        let mut input = fft.make_input_vec();
+        let mut spectrum = fft.make_output_vec();
+        // A Hann window, so the edges of the frames don't add frequencies that aren't in the audio
+        let window: Vec<f32> = (0..FRAME_LEN)
+            .map(|i| 0.5 - 0.5 * (2. * std::f32::consts::PI * i as f32 / FRAME_LEN as f32).cos())
+            .collect();
+        // The pitch class of each frequency bin, from 0 for A to 11 for G#
+        let pitch_classes: Vec<Option<usize>> = (0..spectrum.len())
+            .map(|bin| {
+                let freq = bin as f32 * SAMPLE_RATE as f32 / FRAME_LEN as f32;
+                (MIN_FREQ..=MAX_FREQ)
+                    .contains(&freq)
+                    .then(|| ((12. * (freq / 440.).log2()).round() as i32).rem_euclid(12) as usize)
+            })
+            .collect();
+
+        let mut chroma = Vec::new();
+        let mut start = 0;
+        while start + FRAME_LEN <= samples.len() {
+            for ((input, sample), weight) in input
+                .iter_mut()
+                .zip(&samples[start..start + FRAME_LEN])
+                .zip(&window)
+            {
+                *input = sample * weight;
+            }
+            fft.process(&mut input, &mut spectrum)
+                .expect("The buffers have the lengths the FFT was planned for");
+            let mut frame = [0f32; 12];
+            for (bin, value) in spectrum.iter().enumerate() {
+                if let Some(class) = pitch_classes[bin] {
+                    frame[class] += value.norm_sqr();
+                }
+            }
+            let norm = frame
+                .iter()
+                .map(|energy| energy * energy)
+                .sum::<f32>()
+                .sqrt();
+            if norm > f32::EPSILON {
+                frame.iter_mut().for_each(|energy| *energy /= norm);
+            }
+            chroma.push(frame);
+            start += FRAME_HOP;
+        }
+
+        let smoothed: Vec<[f32; 12]> = chroma
+            .windows(SMOOTHING)
+            .map(|frames| {
+                let mut average = [0f32; 12];
+                for frame in frames {
+                    for (average, energy) in average.iter_mut().zip(frame) {
+                        *average += energy / SMOOTHING as f32;
+                    }
+                }
+                average
+            })
+            .collect();
+        let frames = smoothed
+            .windows(2)
+            .map(|frames| {
+                let (last, frame) = (&frames[0], &frames[1]);
+                let mut bits = 0;
+                for class in 0..12 {
+                    let next = (class + 1) % 12;
+                    let difference = frame[class] - frame[next];
+                    if difference > 0. {
+                        bits |= 1 << class;
+                    }
+                    if difference > last[class] - last[next] {
+                        bits |= 1 << (12 + class);
+                    }
+                }
+                bits
+            })
+            .collect();
+        Self(frames)
+    }
+
+    /// How many frames the fingerprint has.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The fraction of bits that match between the fingerprints, at the offset where the most of them do.
+    ///
+    /// From 0 to 1, 0 if the fingerprints are too short to compare.
+    pub fn similarity(&self, other: &Self) -> f64 {
+        let mut best = 0f64;
+        for offset in -(MAX_OFFSET as isize)..=MAX_OFFSET as isize {
+            let (a, b) = if offset >= 0 {
+                (&self.0[(offset as usize).min(self.len())..], &other.0[..])
+            } else {
+                (&self.0[..], &other.0[(-offset as usize).min(other.len())..])
+            };
+            let overlap = a.len().min(b.len());
+            if overlap < MIN_OVERLAP {
+                continue;
+            }
+            let errors: u32 = a.iter().zip(b).map(|(a, b)| (a ^ b).count_ones()).sum();
+            best = best.max(1. - errors as f64 / (overlap as f64 * BITS as f64));
+        }
+        best
+    }
+}
+
+/// Resamples mono audio to [`SAMPLE_RATE`], averaging the samples that go into each output sample.
+struct Resampler {
+    /// How many input samples there are for each output sample.
+    step: f64,
+    position: f64,
+    sum: f32,
+    count: usize,
+    samples: Vec<f32>,
+}
+
+impl Resampler {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            step: sample_rate as f64 / SAMPLE_RATE as f64,
+            position: 0.,
+            sum: 0.,
+            count: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.sum += sample;
+        self.count += 1;
+        self.position += 1.;
+        // Lower sample rates repeat samples
+        if self.position >= self.step {
+            let average = self.sum / self.count as f32;
+            while self.position >= self.step {
+                self.samples.push(average);
+                self.position -= self.step;
+            }
+            self.sum = 0.;
+            self.count = 0;
+        }
+    }
+}
+
+/// Group the songs whose fingerprints are similar enough to be the same recording, see [`DUPLICATE_SIMILARITY`].
+///
+/// Only groups of at least two songs are returned, with the songs and groups sorted by path.
+pub fn group_duplicates(songs: &[(Song, Fingerprint)]) -> Vec<Vec<Song>> {
+    let mut order: Vec<usize> = (0..songs.len()).collect();
+    order.sort_by_key(|&i| *songs[i].0.duration());
+    // The first song of each song's group
+    let mut group_of: Vec<usize> = (0..songs.len()).collect();
+    fn root(group_of: &mut [usize], mut i: usize) -> usize {
+        while group_of[i] != i {
+            group_of[i] = group_of[group_of[i]];
+            i = group_of[i];
+        }
+        i
+    }
+    for (n, &i) in order.iter().enumerate() {
+        let (song, fingerprint) = &songs[i];
+        for &j in &order[n + 1..] {
+            let (other, other_fingerprint) = &songs[j];
+            if *other.duration() - *song.duration() > MAX_DURATION_DIFFERENCE {
+                break;
+            }
+            if fingerprint.similarity(other_fingerprint) >= DUPLICATE_SIMILARITY {
+                let (a, b) = (root(&mut group_of, i), root(&mut group_of, j));
+                group_of[a.max(b)] = a.min(b);
+            }
+        }
+    }
+    let mut groups: Vec<Vec<Song>> = vec![Vec::new(); songs.len()];
+    for (i, (song, _)) in songs.iter().enumerate() {
+        groups[root(&mut group_of, i)].push(song.clone());
+    }
+    groups.retain(|group| group.len() > 1);
+    for group in &mut groups {
+        group.sort_by(|a, b| a.path().cmp(b.path()));
+    }
+    groups.sort_by(|a, b| a[0].path().cmp(b[0].path()));
+    groups
+}
+
+/// Sent by a running [`DuplicateFinder`].
+#[derive(Debug)]
+pub enum DuplicateEvent {
+    /// How many songs were fingerprinted so far, out of how many.
+    Progress { done: usize, total: usize },
+    /// A song couldn't be decoded.
+    Error { path: PathBuf, error: String },
+    /// The songs that are the same recording, see [`group_duplicates`].
+    ///
+    /// This is always the last event, without any groups if the search was cancelled.
+    Finished {
+        groups: Vec<Vec<Song>>,
+        cancelled: bool,
+    },
+}
+
+/// Fingerprints songs in the background and groups the duplicates.
+///
+/// Tracks of cue sheets are skipped, since they're parts of a file.
+#[derive(Clone, Debug)]
+pub struct DuplicateFinder {
+    songs: Vec<Song>,
+    threads: usize,
+}
+
+impl DuplicateFinder {
+    /// Create a finder for the duplicates among `songs`, fingerprinting on as many threads as the system has cores.
+    pub fn new(songs: impl IntoIterator<Item = Song>) -> Self {
+        Self {
+            songs: songs
+                .into_iter()
+                .filter(|song| song.cue_range().is_none())
+                .collect(),
+            threads: thread::available_parallelism().map_or(4, |n| n.get()),
+        }
+    }
+
+    /// Set how many threads fingerprint songs, at least 1.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Start fingerprinting in the background, the [`DuplicateEvent`]s are sent to the returned receiver.
+    pub fn start(self) -> (ScanHandle, Receiver<DuplicateEvent>) {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (event_tx, event_rx) = mpsc::channel();
+        let thread = {
+            let cancel = cancel.clone();
+            thread::spawn(move || self.run(&cancel, event_tx))
+        };
+        (ScanHandle::new(cancel, thread), event_rx)
+    }
+
+    fn run(&self, cancel: &AtomicBool, events: Sender<DuplicateEvent>) {
+        let next = AtomicUsize::new(0);
+        let done = AtomicUsize::new(0);
+        let fingerprints = Mutex::new(Vec::with_capacity(self.songs.len()));
+        thread::scope(|scope| {
+            for _ in 0..self.threads {
+                let events = events.clone();
+                let (next, done, fingerprints) = (&next, &done, &fingerprints);
+                scope.spawn(move || {
+                    while !cancel.load(Ordering::Relaxed) {
+                        let Some(song) = self.songs.get(next.fetch_add(1, Ordering::Relaxed))
+                        else {
+                            break;
+                        };
+                        match Fingerprint::of_song(song) {
+                            Ok(fingerprint) => {
+                                fingerprints
+                                    .lock()
+                                    .unwrap()
+                                    .push((song.clone(), fingerprint));
+                            }
+                            Err(e) => {
+                                let _ = events.send(DuplicateEvent::Error {
+                                    path: song.path().to_path_buf(),
+                                    error: e.to_string(),
+                                });
+                            }
+                        }
+                        let _ = events.send(DuplicateEvent::Progress {
+                            done: done.fetch_add(1, Ordering::Relaxed) + 1,
+                            total: self.songs.len(),
+                        });
+                    }
+                });
+            }
+        });
+        let cancelled = cancel.load(Ordering::Relaxed);
+        let groups = if cancelled {
+            Vec::new()
+        } else {
+            group_duplicates(&fingerprints.into_inner().unwrap())
+        };
+        debug!(
+            "Found {} groups of duplicates in {} songs",
+            groups.len(),
+            self.songs.len()
+        );
+        let _ = events.send(DuplicateEvent::Finished { groups, cancelled });
+    }
+}
+
+/// Gets the directory duplicates are moved to instead of being deleted (`quarantine` in [`Config::default_path`]).
+pub fn quarantine_dir() -> PathBuf {
+    let mut path = Config::default_path();
+    path.push("quarantine");
+    path
+}
+
+/// Move the file at `path` into `dir`, so it's out of the library but can still be restored.
+///
+/// A number is added to the file's name if `dir` already has a file with it, e.g. `song (2).mp3`.
+/// Returns the file's new path.
+pub fn quarantine(path: &Path, dir: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let target = (1..)
+        .map(|n| match n {
+            1 => dir.join(format!("{stem}{extension}")),
+            n => dir.join(format!("{stem} ({n}){extension}")),
+        })
+        .find(|target| !target.exists())
+        .expect("there's always a free name");
+    // Renaming fails across file systems, then the file is copied instead
+    if fs::rename(path, &target).is_err() {
+        fs::copy(path, &target)?;
+        if let Err(e) = fs::remove_file(path) {
+            let _ = fs::remove_file(&target);
+            return Err(e);
+        }
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A melody of sine waves, one note every half second, with a bit of deterministic noise.
+    fn melody(notes: &[f32], sample_rate: u32, noise: f32) -> Vec<f32> {
+        let per_note = sample_rate as usize / 2;
+        let mut seed: u32 = 1;
+        (0..notes.len() * per_note)
+            .map(|i| {
+                let freq = notes[i / per_note];
+                let t = i as f32 / sample_rate as f32;
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let noise = noise * ((seed >> 16) as f32 / 32768. - 1.);
+                (2. * std::f32::consts::PI * freq * t).sin() * 0.5 + noise
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_similarity() {
+        let tune = [220., 330., 262., 440., 392., 294., 349., 523.].repeat(4);
+        let other_tune = [247., 494., 196., 311., 415., 185., 370., 277.].repeat(4);
+        let original = Fingerprint::from_samples(&melody(&tune, 44100, 0.), 44100);
+        // The same tune at another sample rate and with noise, like a lower bitrate file
+        let copy = Fingerprint::from_samples(&melody(&tune, 22050, 0.05), 22050);
+        let other = Fingerprint::from_samples(&melody(&other_tune, 44100, 0.), 44100);
+        assert!(original.len() >= MIN_OVERLAP);
+        assert!(original.similarity(&copy) >= DUPLICATE_SIMILARITY);
+        assert!(original.similarity(&other) < DUPLICATE_SIMILARITY);
+        assert_eq!(Fingerprint::default().similarity(&original), 0.);
+
+        let song =
+            |name: &str| Song::new(0, name.into(), PathBuf::from(name), Duration::from_secs(16));
+        let groups = group_duplicates(&[
+            (song("/b.mp3"), copy),
+            (song("/c.mp3"), other),
+            (song("/a.flac"), original),
+        ]);
+        let paths: Vec<Vec<&str>> = groups
+            .iter()
+            .map(|group| group.iter().map(|song| song.title()).collect())
+            .collect();
+        assert_eq!(paths, vec![vec!["/a.flac", "/b.mp3"]]);
+    }
+
+    #[test]
+    fn test_resampler() {
+        let mut resampler = Resampler::new(SAMPLE_RATE * 4);
+        for sample in [0., 4., 0., 4., 1., 1., 1., 1.] {
+            resampler.push(sample);
+        }
+        assert_eq!(resampler.samples, vec![2., 1.]);
+        let mut resampler = Resampler::new(SAMPLE_RATE / 2);
+        resampler.push(3.);
+        assert_eq!(resampler.samples, vec![3., 3.]);
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let dir = std::env::temp_dir().join("amuseing-test-quarantine");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("a")).unwrap();
+        fs::create_dir_all(dir.join("b")).unwrap();
+        fs::write(dir.join("a/song.mp3"), b"a").unwrap();
+        fs::write(dir.join("b/song.mp3"), b"b").unwrap();
+
+        let quarantined = dir.join("quarantine");
+        let first = quarantine(&dir.join("a/song.mp3"), &quarantined).unwrap();
+        let second = quarantine(&dir.join("b/song.mp3"), &quarantined).unwrap();
+        assert_eq!(first, quarantined.join("song.mp3"));
+        assert_eq!(second, quarantined.join("song (2).mp3"));
+        assert_eq!(fs::read(&second).unwrap(), b"b");
+        assert!(!dir.join("a/song.mp3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
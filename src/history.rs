@@ -0,0 +1,230 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, errors::HistoryError, library::counts_as_play, playback::Song};
+
+/// The first lines of a `.scrobbler.log`, the times in it are in UTC.
+const SCROBBLER_LOG_HEADER: &str = concat!(
+    "#AUDIOSCROBBLER/1.1\n#TZ/UTC\n#CLIENT/amuseing ",
+    env!("CARGO_PKG_VERSION"),
+    "\n"
+);
+
+/// One time a song was listened to, see [`History::record`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Listen {
+    pub path: PathBuf,
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    /// The length of the song in seconds.
+    pub duration: u64,
+    /// How long the song was listened to in seconds.
+    pub played: u64,
+    /// When the song started playing, in seconds since the unix epoch.
+    pub timestamp: u64,
+}
+
+impl Listen {
+    /// A listen of `song` which `started` playing and stopped after `played` of it.
+    pub fn new(song: &Song, started: SystemTime, played: Duration) -> Self {
+        let timestamp = started
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+        Self {
+            path: song.path().to_path_buf(),
+            title: song.title().to_string(),
+            artist: song.artist().map(Into::into),
+            album: song.album().map(Into::into),
+            track_number: song.tags().track_number,
+            duration: song.duration().as_secs(),
+            played: played.as_secs(),
+            timestamp,
+        }
+    }
+
+    /// Check if the listen passes the scrobble rule: the song is longer than 30 seconds,
+    /// and at least half of it or 4 minutes were played, see [`counts_as_play`].
+    pub fn is_scrobble(&self) -> bool {
+        self.duration > 30
+            && counts_as_play(
+                Duration::from_secs(self.duration),
+                Duration::from_secs(self.played),
+            )
+    }
+
+    /// The listen as a line of a `.scrobbler.log`, without the line break.
+    ///
+    /// The fields are artist, album, title, track number, duration, `L` for listened, the timestamp and a
+    /// MusicBrainz id, which is always empty.
+    fn scrobbler_log_line(&self) -> String {
+        // Tabs separate the fields, so they can't be in them
+        let field = |field: &str| field.replace(['\t', '\n', '\r'], " ");
+        let track_number = self.track_number.map(|n| n.to_string()).unwrap_or_default();
+        format!(
+            "{}\t{}\t{}\t{}\t{}\tL\t{}\t",
+            field(self.artist.as_deref().unwrap_or_default()),
+            field(self.album.as_deref().unwrap_or_default()),
+            field(&self.title),
+            track_number,
+            self.duration,
+            self.timestamp,
+        )
+    }
+}
+
+/// The songs that were listened to, kept as a full history and for syncing them to a scrobbling service.
+///
+/// Every listen is appended to `history.jsonl`, one JSON object per line, which is kept as the full history and can be
+/// [exported](Self::export_json). Listens passing the scrobble rule are also appended to `.scrobbler.log`,
+/// in the format portable players use, so scrobbling tools can submit and then delete it. The format needs an artist,
+/// so songs without one are only kept in the history.
+///
+/// Both files are in the same directory, by default the one with the config file.
+#[derive(Clone, Debug)]
+pub struct History {
+    dir: PathBuf,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(Config::default_path())
+    }
+}
+
+impl History {
+    /// The history kept in `dir`.
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn scrobbler_log_path(&self) -> PathBuf {
+        self.dir.join(".scrobbler.log")
+    }
+
+    pub fn history_path(&self) -> PathBuf {
+        self.dir.join("history.jsonl")
+    }
+
+    /// Append `listen` to the history, and to the `.scrobbler.log` if it passes the [scrobble rule](Listen::is_scrobble)
+    /// and the song has an artist, which the log's format requires.
+    ///
+    /// Returns false if it wasn't scrobbled.
+    pub fn record(&self, listen: &Listen) -> Result<bool, HistoryError> {
+        fs::create_dir_all(&self.dir)?;
+        let mut history = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.history_path())?;
+        writeln!(history, "{}", serde_json::to_string(listen)?)?;
+        let has_artist = listen
+            .artist
+            .as_deref()
+            .is_some_and(|artist| !artist.trim().is_empty());
+        if !listen.is_scrobble() || !has_artist {
+            return Ok(false);
+        }
+
+        let log_path = self.scrobbler_log_path();
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)?;
+        // The log starts over when a scrobbling tool deletes it
+        if log.metadata()?.len() == 0 {
+            log.write_all(SCROBBLER_LOG_HEADER.as_bytes())?;
+        }
+        writeln!(log, "{}", listen.scrobbler_log_line())?;
+        Ok(true)
+    }
+
+    /// All of the recorded listens, oldest first, empty if nothing was recorded yet.
+    pub fn listens(&self) -> Result<Vec<Listen>, HistoryError> {
+        let file = match fs::File::open(self.history_path()) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut listens = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                listens.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(listens)
+    }
+
+    /// Write all of the recorded listens to `path` as a JSON array.
+    pub fn export_json(&self, path: &Path) -> Result<(), HistoryError> {
+        let listens = self.listens()?;
+        fs::write(path, serde_json::to_string_pretty(&listens)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listen(title: &str, duration: u64, played: u64) -> Listen {
+        Listen {
+            path: PathBuf::from(format!("/{title}.mp3")),
+            title: title.into(),
+            artist: Some("Artist".into()),
+            album: None,
+            track_number: Some(3),
+            duration,
+            played,
+            timestamp: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn test_scrobble_rule() {
+        assert!(listen("a", 200, 100).is_scrobble());
+        assert!(!listen("a", 200, 99).is_scrobble());
+        assert!(listen("a", 3600, 240).is_scrobble());
+        assert!(!listen("a", 30, 30).is_scrobble());
+    }
+
+    #[test]
+    fn test_history() {
+        let dir = std::env::temp_dir().join("amuseing-test-history");
+        let _ = fs::remove_dir_all(&dir);
+        let history = History::new(dir.clone());
+        let played = listen("Tab\tTitle", 200, 150);
+        let skipped = listen("Skipped", 200, 10);
+        let no_artist = Listen {
+            artist: None,
+            ..listen("No artist", 200, 150)
+        };
+        assert!(history.record(&played).unwrap());
+        assert!(!history.record(&skipped).unwrap());
+        assert!(!history.record(&no_artist).unwrap());
+
+        let log = fs::read_to_string(history.scrobbler_log_path()).unwrap();
+        assert_eq!(
+            log,
+            format!("{SCROBBLER_LOG_HEADER}Artist\t\tTab Title\t3\t200\tL\t1700000000\t\n")
+        );
+        // Only scrobbles are in the log, but every listen is in the history
+        let listens = vec![played, skipped, no_artist];
+        assert_eq!(history.listens().unwrap(), listens);
+
+        let export_path = dir.join("history.json");
+        history.export_json(&export_path).unwrap();
+        let exported: Vec<Listen> =
+            serde_json::from_str(&fs::read_to_string(&export_path).unwrap()).unwrap();
+        assert_eq!(exported, listens);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,18 @@
+pub mod artwork;
+pub mod chapters;
 pub mod config;
+pub mod cue;
 pub mod errors;
+pub mod fingerprint;
+pub mod history;
+pub mod library;
+pub mod lyrics;
 pub mod playback;
+pub mod playlist_file;
 pub mod queue;
+pub mod radio;
+pub mod scanner;
+pub mod session;
+pub mod tags;
+pub mod undo;
+pub mod watcher;
@@ -0,0 +1,469 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+use symphonia::core::errors::{Error as SymphoniaError, Result as SymphoniaResult};
+use tracing::error;
+
+use crate::{
+    config::Config,
+    cue,
+    errors::{CueError, LibraryError},
+    playback::Song,
+};
+
+mod browse;
+mod edit;
+mod search;
+mod smart;
+mod stats;
+pub use browse::{Field, Filter, Group, GroupSort, TrackSort};
+pub use edit::{BatchEdit, BatchEditEvent, BatchEditKind};
+pub use search::SearchIndex;
+pub use smart::{MatchMode, Operator, Rule, RuleField, SmartPlaylist, SmartSort};
+pub use stats::{counts_as_play, unix_now, SongStats};
+
+/// The version of what's read from a song's file, songs cached by an older version are read again.
+///
+/// Version 1 added the song's chapters.
+const FORMAT_VERSION: u32 = 1;
+
+/// A cached song, with the size and modification time its file had when it was read.
+///
+/// For tracks of cue sheets, it's the size and modification time of the sheet.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct LibraryEntry {
+    size: u64,
+    modified_millis: u64,
+    /// The [`FORMAT_VERSION`] the song was read with, 0 for songs cached before there were versions.
+    #[serde(default)]
+    version: u32,
+    song: Song,
+    /// Kept when the song is read again, so editing a file doesn't reset them.
+    #[serde(default)]
+    stats: SongStats,
+}
+
+impl LibraryEntry {
+    /// Check if the song was read from a file with this size and modification time, by the current [`FORMAT_VERSION`].
+    fn is_current(&self, size: u64, modified_millis: u64) -> bool {
+        self.size == size
+            && self.modified_millis == modified_millis
+            && self.version == FORMAT_VERSION
+    }
+}
+
+/// The format of the library file.
+#[derive(Default, Serialize, Deserialize)]
+struct LibraryFile {
+    #[serde(rename = "song")]
+    #[serde(default)]
+    songs: Vec<LibraryEntry>,
+}
+
+/// A cache of the songs read from disk, so reading a directory of songs doesn't have to open and parse every file.
+///
+/// Songs are keyed by their path, and read again with [`Song::from_path`] only when the file's size or modification time changed,
+/// or when they were cached by an older version that didn't read everything that's read now, like chapters.
+/// The duration, tags and album art thumbnail path of every song are cached, along with its [`SongStats`].
+///
+/// The library is stored in `library.toml`, next to the config file.
+#[derive(Clone, Debug, Default)]
+pub struct Library {
+    entries: HashMap<PathBuf, LibraryEntry>,
+    changed: bool,
+    /// Counts the changes to the library's songs but not to their stats, see [`generation`](Self::generation).
+    generation: u64,
+    /// Where the album art thumbnails of the songs read into the library are cached, see [`set_artwork_dir`](Self::set_artwork_dir).
+    artwork_dir: Option<PathBuf>,
+}
+
+impl Library {
+    /// Gets the default library path (`library.toml` in [`Config::default_path`]).
+    pub fn default_path() -> PathBuf {
+        let mut path = Config::default_path();
+        path.push("library.toml");
+        path
+    }
+
+    /// Get the library from the [`default_path`].
+    ///
+    /// [`default_path`]: Self::default_path
+    pub fn from_default_path() -> Result<Self, LibraryError> {
+        Self::from_path(&Self::default_path())
+    }
+
+    /// Get the library from the file at `path`.
+    pub fn from_path(path: &Path) -> Result<Self, LibraryError> {
+        let toml_str = fs::read_to_string(path)?;
+        let file: LibraryFile =
+            toml::from_str(&toml_str).inspect_err(|e| error!("Error parsing library file: {e}"))?;
+        let entries = file
+            .songs
+            .into_iter()
+            .map(|entry| (entry.song.path().to_path_buf(), entry))
+            .collect();
+        Ok(Self {
+            entries,
+            ..Default::default()
+        })
+    }
+
+    /// A library of `songs` with empty stats, without reading their files.
+    #[cfg(test)]
+    pub(crate) fn with_songs(songs: impl IntoIterator<Item = Song>) -> Self {
+        let entries = songs
+            .into_iter()
+            .map(|song| {
+                let entry = LibraryEntry {
+                    size: 0,
+                    modified_millis: 0,
+                    version: FORMAT_VERSION,
+                    song,
+                    stats: SongStats::default(),
+                };
+                (entry.song.path().to_path_buf(), entry)
+            })
+            .collect();
+        Self {
+            entries,
+            ..Default::default()
+        }
+    }
+
+    /// The directory album art thumbnails are cached in when songs are read, see [`Song::from_path`].
+    pub fn artwork_dir(&self) -> Option<&Path> {
+        self.artwork_dir.as_deref()
+    }
+
+    /// Cache the album art thumbnails of songs read from now on in `dir`, usually [`artwork::cache_dir`].
+    /// Songs don't get artwork if it's None, which is the default.
+    ///
+    /// [`artwork::cache_dir`]: crate::artwork::cache_dir
+    pub fn set_artwork_dir(&mut self, dir: Option<PathBuf>) {
+        self.artwork_dir = dir;
+    }
+
+    /// Try to write the library to the [`default_path`], creating the directory if it doesn't exist.
+    ///
+    /// [`default_path`]: Self::default_path
+    pub fn write(&mut self) -> Result<(), LibraryError> {
+        self.write_to(&Self::default_path())
+    }
+
+    /// Try to write the library to `path`, creating the parent directory if it doesn't exist.
+    ///
+    /// Resets [`is_changed`].
+    ///
+    /// [`is_changed`]: Self::is_changed
+    pub fn write_to(&mut self, path: &Path) -> Result<(), LibraryError> {
+        let mut songs: Vec<LibraryEntry> = self.entries.values().cloned().collect();
+        songs.sort_by(|a, b| a.song.path().cmp(b.song.path()));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, toml::to_string_pretty(&LibraryFile { songs })?)?;
+        self.changed = false;
+        Ok(())
+    }
+
+    /// Check if songs were added or removed since the library was read or written, meaning it should be written again.
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    /// A number that changes whenever songs are added, changed or removed, but not when only their stats change,
+    /// so things made from the songs, like a [`SearchIndex`], are only made again when they would be different.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// All the cached songs, in no particular order.
+    pub fn songs(&self) -> impl Iterator<Item = &Song> {
+        self.entries.values().map(|entry| &entry.song)
+    }
+
+    /// Get the cached song at `path`, without checking if the file changed.
+    pub fn get(&self, path: &Path) -> Option<&Song> {
+        self.entries.get(path).map(|entry| &entry.song)
+    }
+
+    /// All the cached songs in `dir` and its subdirectories, sorted by path.
+    pub fn songs_under(&self, dir: &Path) -> Vec<Song> {
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let mut songs: Vec<Song> = self
+            .songs()
+            .filter(|song| song.path().starts_with(&dir))
+            .cloned()
+            .collect();
+        songs.sort_by(|a, b| a.path().cmp(b.path()));
+        songs
+    }
+
+    /// Get the cached song at `path` if the file didn't change since it was read, and it was read by this version.
+    pub fn cached(&self, path: &Path) -> Option<Song> {
+        let path = cue::canonicalize(path).ok()?;
+        let (size, modified_millis) = file_stamp(&cue::source_path(&path).metadata().ok()?);
+        self.entries
+            .get(&path)
+            .filter(|entry| entry.is_current(size, modified_millis))
+            .map(|entry| entry.song.clone())
+    }
+
+    /// Get the cached tracks of the cue sheet at `sheet`, sorted by path, if the sheet didn't change since it was read.
+    pub fn cached_sheet(&self, sheet: &Path) -> Option<Vec<Song>> {
+        let sheet = sheet.canonicalize().ok()?;
+        let (size, modified_millis) = file_stamp(&sheet.metadata().ok()?);
+        let entries: Vec<&LibraryEntry> = self
+            .entries
+            .iter()
+            .filter(|(path, _)| cue::sheet_of(path) == Some(sheet.as_path()))
+            .map(|(_, entry)| entry)
+            .collect();
+        let unchanged = entries
+            .iter()
+            .all(|entry| entry.is_current(size, modified_millis));
+        if entries.is_empty() || !unchanged {
+            return None;
+        }
+        let mut songs: Vec<Song> = entries
+            .into_iter()
+            .map(|entry| entry.song.clone())
+            .collect();
+        songs.sort_by(|a, b| a.path().cmp(b.path()));
+        Some(songs)
+    }
+
+    /// Add `song` to the library, replacing the song with the same path but keeping its stats.
+    ///
+    /// A track of a cue sheet replaces the song of its whole audio file, since the file is split into the sheet's tracks.
+    /// Fails if the song's file can't be read.
+    pub fn insert(&mut self, song: Song) -> io::Result<()> {
+        let (size, modified_millis) = file_stamp(&cue::source_path(song.path()).metadata()?);
+        if song.cue_range().is_some() {
+            self.remove(song.file());
+        }
+        let stats = match self.entries.get(song.path()) {
+            Some(old) => old.stats.clone(),
+            None => SongStats {
+                added: Some(unix_now()),
+                ..Default::default()
+            },
+        };
+        let entry = LibraryEntry {
+            size,
+            modified_millis,
+            version: FORMAT_VERSION,
+            song,
+            stats,
+        };
+        let path = entry.song.path().to_path_buf();
+        let unchanged = self
+            .entries
+            .get(&path)
+            .is_some_and(|old| old.is_current(size, modified_millis) && old.song == entry.song);
+        if !unchanged {
+            self.entries.insert(path, entry);
+            self.changed = true;
+            self.generation += 1;
+        }
+        Ok(())
+    }
+
+    /// Remove the song at `path` from the library.
+    pub fn remove(&mut self, path: &Path) -> Option<Song> {
+        let removed = self.entries.remove(path).map(|entry| entry.song);
+        if removed.is_some() {
+            self.changed = true;
+            self.generation += 1;
+        }
+        removed
+    }
+
+    /// Remove the songs at or under `path`, for when a file or directory was deleted.
+    pub fn remove_under(&mut self, path: &Path) -> Vec<Song> {
+        let removed: Vec<PathBuf> = self
+            .entries
+            .keys()
+            .filter(|song_path| song_path.starts_with(path))
+            .cloned()
+            .collect();
+        removed
+            .iter()
+            .filter_map(|song_path| self.remove(song_path))
+            .collect()
+    }
+
+    /// Remove the songs whose files no longer exist.
+    pub fn prune(&mut self) {
+        let before = self.entries.len();
+        self.entries
+            .retain(|path, _| cue::source_path(path).exists());
+        if self.entries.len() != before {
+            self.changed = true;
+            self.generation += 1;
+        }
+    }
+
+    /// Get the song at `path`, reading it with [`Song::from_file`] only if it isn't cached or the file changed.
+    ///
+    /// The tracks of cue sheets are read with [`sheet_songs`](Self::sheet_songs).
+    pub fn song(&mut self, path: &Path) -> SymphoniaResult<Song> {
+        if let Some(song) = self.cached(path) {
+            return Ok(song);
+        }
+        if let Some(sheet) = cue::sheet_of(path) {
+            let path = cue::canonicalize(path)?;
+            let songs = self.sheet_songs(sheet).map_err(|e| match e {
+                CueError::IoError(e) => SymphoniaError::IoError(e),
+                CueError::ReadError(e) => e,
+                CueError::NoTracks(_) => SymphoniaError::Unsupported("cue sheet without tracks"),
+            })?;
+            return songs
+                .into_iter()
+                .find(|song| song.path() == path)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound).into());
+        }
+        let path = path.canonicalize()?;
+        match Song::from_file(path.clone(), self.artwork_dir()) {
+            Ok(song) => {
+                self.insert(song.clone())?;
+                Ok(song)
+            }
+            Err(e) => {
+                self.remove(&path);
+                Err(e)
+            }
+        }
+    }
+
+    /// Get the tracks of the cue sheet at `sheet`, reading it only if it isn't cached or changed.
+    ///
+    /// Tracks that are no longer in the sheet are removed.
+    pub fn sheet_songs(&mut self, sheet: &Path) -> Result<Vec<Song>, CueError> {
+        if let Some(songs) = self.cached_sheet(sheet) {
+            return Ok(songs);
+        }
+        let sheet = sheet.canonicalize()?;
+        let songs = cue::read_songs(&sheet, self.artwork_dir())?;
+        let removed: Vec<PathBuf> = self
+            .entries
+            .keys()
+            .filter(|path| path.starts_with(&sheet))
+            .filter(|path| !songs.iter().any(|song| song.path() == *path))
+            .cloned()
+            .collect();
+        for path in removed {
+            self.remove(&path);
+        }
+        for song in &songs {
+            self.insert(song.clone())?;
+        }
+        Ok(songs)
+    }
+}
+
+/// The size and modification time (in milliseconds since the unix epoch) of a file, used to check if it changed.
+fn file_stamp(metadata: &fs::Metadata) -> (u64, u64) {
+    let modified_millis = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since_epoch| since_epoch.as_millis() as u64);
+    (metadata.len(), modified_millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_library_cache() {
+        let dir = std::env::temp_dir().join("amuseing-test-library");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        // Not a real mp3 file, so it can only be read from the cache
+        fs::write(dir.join("song.mp3"), b"not audio").unwrap();
+        let song_path = dir.join("song.mp3").canonicalize().unwrap();
+        let song = Song::new(0, "Song".into(), song_path.clone(), Duration::from_secs(3));
+        let mut library = Library::default();
+        library.insert(song.clone()).unwrap();
+
+        let library_path = dir.join("library.toml");
+        library.write_to(&library_path).unwrap();
+        // Songs cached before the format had versions are read again
+        let toml = fs::read_to_string(&library_path).unwrap();
+        fs::write(&library_path, toml.replace("version = 1\n", "")).unwrap();
+        let outdated = Library::from_path(&library_path).unwrap();
+        assert_eq!(outdated.len(), 1);
+        assert_eq!(outdated.cached(&song_path), None);
+        fs::write(&library_path, toml).unwrap();
+        let mut library = Library::from_path(&library_path).unwrap();
+        assert_eq!(library.song(&song_path).unwrap(), song);
+        assert_eq!(library.songs_under(&dir), vec![song.clone()]);
+        let generation = library.generation();
+        library.insert(song).unwrap();
+        assert!(!library.is_changed());
+        // Stats have to be saved, but don't change the songs
+        library.record_listen(&song_path, Duration::from_secs(3), true, 100);
+        assert_eq!(library.generation(), generation);
+
+        // The changed file is read again, which fails and removes it from the library
+        fs::write(&song_path, b"still not audio").unwrap();
+        assert!(library.song(&song_path).is_err());
+        assert!(library.is_empty());
+        assert!(library.is_changed());
+        assert_ne!(library.generation(), generation);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cue_sheet() {
+        let dir = std::env::temp_dir().join("amuseing-test-library-cue");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        // 40 silent MPEG-1 layer 3 frames, a bit over a second long
+        let mut frame = vec![0xff, 0xfb, 0x90, 0x64];
+        frame.resize(417, 0);
+        fs::write(dir.join("album.mp3"), frame.repeat(40)).unwrap();
+        let mut library = Library::default();
+        library.song(&dir.join("album.mp3")).unwrap();
+        fs::write(
+            dir.join("album.cue"),
+            "FILE \"album.mp3\" MP3\nTRACK 01 AUDIO\nINDEX 01 00:00:00\nTRACK 02 AUDIO\nINDEX 01 00:00:30\n",
+        )
+        .unwrap();
+        let sheet = dir.join("album.cue").canonicalize().unwrap();
+
+        // The tracks replace the whole file, and are cached until the sheet changes
+        let track = library.song(&dir.join("album.cue/02")).unwrap();
+        assert_eq!(track.path(), sheet.join("02"));
+        assert_eq!(library.len(), 2);
+        assert_eq!(library.cached_sheet(&sheet).unwrap().len(), 2);
+        assert_eq!(library.cached(&sheet.join("02")), Some(track));
+        fs::write(
+            dir.join("album.cue"),
+            "FILE \"album.mp3\" MP3\nTRACK 01 AUDIO\nINDEX 01 00:00:00\n",
+        )
+        .unwrap();
+        assert!(library.cached_sheet(&sheet).is_none());
+        assert_eq!(library.sheet_songs(&sheet).unwrap().len(), 1);
+        assert_eq!(library.len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,236 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use super::Library;
+use crate::playback::Song;
+
+/// A tag the songs in a [`Library`] can be grouped and filtered by, see [`Library::groups`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Field {
+    Artist,
+    Album,
+    Genre,
+    Year,
+}
+
+impl Field {
+    /// The value of this field for `song`, None if the song isn't tagged with it.
+    ///
+    /// [`Field::Artist`] uses the album artist if it's set, so an album with guest artists is kept under one artist.
+    pub fn value(self, song: &Song) -> Option<String> {
+        let tags = song.tags();
+        match self {
+            Field::Artist => tags.album_artist.clone().or_else(|| tags.artist.clone()),
+            Field::Album => tags.album.clone(),
+            Field::Genre => tags.genre.clone(),
+            Field::Year => tags.year.map(|year| year.to_string()),
+        }
+    }
+}
+
+/// Only songs whose [`Field`] has this value, None matches the songs that aren't tagged with it.
+pub type Filter = (Field, Option<String>);
+
+/// The songs sharing one value of a [`Field`], None for the songs that aren't tagged with it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Group {
+    pub value: Option<String>,
+    pub song_count: usize,
+}
+
+/// How [`Library::groups`] are sorted, untagged songs always come last.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GroupSort {
+    /// Alphabetically, ignoring case.
+    #[default]
+    Name,
+    /// Groups with the most songs first.
+    SongCount,
+}
+
+/// How [`Library::tracks`] are sorted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrackSort {
+    /// By album, then disc and track number, like the albums are meant to be played.
+    #[default]
+    Album,
+    Title,
+    Artist,
+    /// Oldest first.
+    Year,
+    /// Shortest first.
+    Duration,
+    /// Most played first.
+    PlayCount,
+    /// Highest rated first, unrated songs last.
+    Rating,
+    /// Most recently played first, songs that were never played last.
+    LastPlayed,
+    /// Most recently added to the library first.
+    Added,
+}
+
+impl Library {
+    /// Group the songs matching all of the `filters` by the value of `field`.
+    ///
+    /// For example, the albums of an artist are `library.groups(Field::Album, &[(Field::Artist, Some(artist))], sort)`.
+    pub fn groups(&self, field: Field, filters: &[Filter], sort: GroupSort) -> Vec<Group> {
+        let mut counts: HashMap<Option<String>, usize> = HashMap::new();
+        for song in self.songs().filter(|song| matches(song, filters)) {
+            *counts.entry(field.value(song)).or_default() += 1;
+        }
+        let mut groups: Vec<Group> = counts
+            .into_iter()
+            .map(|(value, song_count)| Group { value, song_count })
+            .collect();
+        groups.sort_by_cached_key(|group| {
+            let count_key = match sort {
+                GroupSort::Name => 0,
+                GroupSort::SongCount => usize::MAX - group.song_count,
+            };
+            let name = group.value.as_ref().map(|value| value.to_lowercase());
+            (group.value.is_none(), count_key, name)
+        });
+        groups
+    }
+
+    /// The songs matching all of the `filters`.
+    pub fn tracks(&self, filters: &[Filter], sort: TrackSort) -> Vec<Song> {
+        let mut songs: Vec<&Song> = self.songs().filter(|song| matches(song, filters)).collect();
+        sort_tracks(self, &mut songs, sort);
+        songs.into_iter().cloned().collect()
+    }
+}
+
+/// Sort `songs` like [`Library::tracks`] does, the stats are read from `library`.
+pub(super) fn sort_tracks(library: &Library, songs: &mut [&Song], sort: TrackSort) {
+    let stats = |song: &Song| library.stats(song.path()).cloned().unwrap_or_default();
+    // The path is the last key everywhere, so the order is stable between calls
+    match sort {
+        TrackSort::Album => songs.sort_by_cached_key(|song| {
+            let tags = song.tags();
+            (
+                tags.album.as_ref().map(|album| album.to_lowercase()),
+                tags.disc_number,
+                tags.track_number,
+                song.path().to_path_buf(),
+            )
+        }),
+        TrackSort::Title => songs
+            .sort_by_cached_key(|song| (song.title().to_lowercase(), song.path().to_path_buf())),
+        TrackSort::Artist => songs.sort_by_cached_key(|song| {
+            (
+                Field::Artist
+                    .value(song)
+                    .map(|artist| artist.to_lowercase()),
+                song.path().to_path_buf(),
+            )
+        }),
+        TrackSort::Year => {
+            songs.sort_by_cached_key(|song| (song.tags().year, song.path().to_path_buf()))
+        }
+        TrackSort::Duration => {
+            songs.sort_by_cached_key(|song| (*song.duration(), song.path().to_path_buf()))
+        }
+        TrackSort::PlayCount => songs.sort_by_cached_key(|song| {
+            (Reverse(stats(song).play_count), song.path().to_path_buf())
+        }),
+        TrackSort::Rating => songs
+            .sort_by_cached_key(|song| (Reverse(stats(song).rating), song.path().to_path_buf())),
+        TrackSort::LastPlayed => songs.sort_by_cached_key(|song| {
+            (Reverse(stats(song).last_played), song.path().to_path_buf())
+        }),
+        TrackSort::Added => {
+            songs.sort_by_cached_key(|song| (Reverse(stats(song).added), song.path().to_path_buf()))
+        }
+    }
+}
+
+fn matches(song: &Song, filters: &[Filter]) -> bool {
+    filters
+        .iter()
+        .all(|(field, value)| field.value(song) == *value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use super::*;
+    use crate::tags::Tags;
+
+    /// Path, artist, album and track number.
+    type TestSong<'a> = (&'a str, Option<&'a str>, Option<&'a str>, Option<u32>);
+
+    fn library(songs: &[TestSong]) -> Library {
+        Library::with_songs(songs.iter().map(|(path, artist, album, track_number)| {
+            let tags = Tags {
+                artist: artist.map(Into::into),
+                album: album.map(Into::into),
+                track_number: *track_number,
+                ..Default::default()
+            };
+            Song::new(0, path.to_string(), PathBuf::from(path), Duration::ZERO).with_tags(tags)
+        }))
+    }
+
+    #[test]
+    fn test_groups() {
+        let library = library(&[
+            ("/a", Some("beta"), Some("One"), None),
+            ("/b", Some("Alpha"), Some("Two"), None),
+            ("/c", Some("beta"), Some("Three"), None),
+            ("/d", None, None, None),
+        ]);
+        let names = |groups: Vec<Group>| -> Vec<(Option<String>, usize)> {
+            groups
+                .into_iter()
+                .map(|group| (group.value, group.song_count))
+                .collect()
+        };
+        assert_eq!(
+            names(library.groups(Field::Artist, &[], GroupSort::Name)),
+            vec![
+                (Some("Alpha".into()), 1),
+                (Some("beta".into()), 2),
+                (None, 1)
+            ]
+        );
+        assert_eq!(
+            names(library.groups(Field::Artist, &[], GroupSort::SongCount)),
+            vec![
+                (Some("beta".into()), 2),
+                (Some("Alpha".into()), 1),
+                (None, 1)
+            ]
+        );
+        let filters = [(Field::Artist, Some("beta".to_string()))];
+        assert_eq!(
+            names(library.groups(Field::Album, &filters, GroupSort::Name)),
+            vec![(Some("One".into()), 1), (Some("Three".into()), 1)]
+        );
+    }
+
+    #[test]
+    fn test_tracks() {
+        let library = library(&[
+            ("/3", Some("x"), Some("Album"), Some(2)),
+            ("/1", Some("x"), Some("Album"), Some(10)),
+            ("/2", Some("x"), Some("Album"), Some(1)),
+            ("/4", Some("y"), Some("Album"), Some(1)),
+        ]);
+        let filters = [(Field::Artist, Some("x".to_string()))];
+        let paths: Vec<PathBuf> = library
+            .tracks(&filters, TrackSort::Album)
+            .iter()
+            .map(|song| song.path().to_path_buf())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/2"),
+                PathBuf::from("/3"),
+                PathBuf::from("/1")
+            ]
+        );
+    }
+}
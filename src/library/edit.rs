@@ -0,0 +1,337 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread,
+};
+
+use tracing::{error, warn};
+
+use super::Library;
+use crate::{
+    errors::TagWriteError,
+    playback::{Playlist, Song},
+    scanner::ScanHandle,
+    tags::{apply_edits, file_name_from_tags, tags_from_file_name, write_tags, TagEdit},
+};
+
+impl Library {
+    /// Apply `edits` to the tags of the song at `path`, write them to its file and read the song again.
+    ///
+    /// Returns the edited song, which replaces the old one in the library.
+    pub fn edit_tags(&mut self, path: &Path, edits: &[TagEdit]) -> Result<Song, TagWriteError> {
+        let song = self.song(path)?;
+        if song.cue_range().is_some() {
+            return Err(TagWriteError::UnsupportedFormat("cue".into()));
+        }
+        let mut tags = song.tags().clone();
+        apply_edits(&mut tags, edits);
+        if &tags != song.tags() {
+            write_tags(song.path(), &tags)?;
+        }
+        let song = Song::from_file(song.path().to_path_buf(), self.artwork_dir())?;
+        self.insert(song.clone())?;
+        Ok(song)
+    }
+
+    /// Rename the file of the song at `path` to a name made from its tags, see [`file_name_from_tags`].
+    ///
+    /// The file stays in its directory and keeps its extension, and the song keeps its stats.
+    /// Fails if a file with the new name already exists.
+    pub fn rename_from_tags(&mut self, path: &Path, pattern: &str) -> Result<Song, TagWriteError> {
+        let song = self.song(path)?;
+        if song.cue_range().is_some() {
+            return Err(TagWriteError::UnsupportedFormat("cue".into()));
+        }
+        let mut name =
+            file_name_from_tags(pattern, song.tags())?.ok_or(TagWriteError::MissingTag)?;
+        if let Some(extension) = song.path().extension() {
+            name = format!("{name}.{}", extension.to_string_lossy());
+        }
+        let new_path = song.path().with_file_name(name);
+        if new_path == song.path() {
+            return Ok(song);
+        }
+        if new_path.exists() {
+            return Err(TagWriteError::AlreadyExists(new_path));
+        }
+        fs::rename(song.path(), &new_path)?;
+        let renamed = Song::from_file(new_path, self.artwork_dir())?;
+        self.replace(song.path(), renamed.clone())?;
+        Ok(renamed)
+    }
+
+    /// Put `song`, an edited version of the song at `old`, in the library, keeping the stats if its path changed.
+    pub fn replace(&mut self, old: &Path, song: Song) -> io::Result<()> {
+        let stats = match old == song.path() {
+            true => None,
+            false => self.entries.remove(old).map(|entry| entry.stats),
+        };
+        let path = song.path().to_path_buf();
+        self.insert(song)?;
+        if let (Some(entry), Some(stats)) = (self.entries.get_mut(&path), stats) {
+            entry.stats = stats;
+        }
+        self.changed = true;
+        Ok(())
+    }
+}
+
+/// What a [`BatchEdit`] does to each song.
+#[derive(Clone, Debug)]
+pub enum BatchEditKind {
+    /// Apply the edits to the tags, see [`Library::edit_tags`].
+    Tags(Vec<TagEdit>),
+    /// Read the tags from the file names with a pattern, see [`tags_from_file_name`].
+    ///
+    /// Songs whose file name doesn't match are left as they are.
+    FromFileNames(String),
+    /// Rename the files from the tags with a pattern, see [`Library::rename_from_tags`].
+    Rename(String),
+}
+
+/// Sent by a running [`BatchEdit`].
+#[derive(Debug)]
+pub enum BatchEditEvent {
+    /// The song at `path` was edited, `song` has a different path if its file was renamed.
+    ///
+    /// It can be put in the library with [`Library::replace`].
+    Edited { path: PathBuf, song: Box<Song> },
+    /// How many songs were edited so far, out of how many.
+    Progress { done: usize, total: usize },
+    /// A song couldn't be edited, or a playlist couldn't be updated for a renamed song.
+    Error { path: PathBuf, error: String },
+    /// The edit is done, stopped at a song that couldn't be edited, or stopped early because of [`ScanHandle::cancel`].
+    ///
+    /// This is always the last event.
+    Finished { cancelled: bool },
+}
+
+/// Edits the tags or file names of songs in the background, one song at a time.
+///
+/// The songs are edited in a copy of the library, the [`BatchEditEvent::Edited`] songs should be put in the real one.
+/// Playlist files that have a renamed song are rewritten to point at the new file, see [`Playlist::replace_song`].
+#[derive(Clone, Debug)]
+pub struct BatchEdit {
+    library: Library,
+    paths: Vec<PathBuf>,
+    kind: BatchEditKind,
+    playlists: Vec<Playlist>,
+}
+
+impl BatchEdit {
+    /// Create an edit of the songs at `paths`, read from `library`, in the order of their paths.
+    pub fn new(
+        library: Library,
+        paths: impl IntoIterator<Item = PathBuf>,
+        kind: BatchEditKind,
+    ) -> Self {
+        let mut paths: Vec<PathBuf> = paths.into_iter().collect();
+        paths.sort();
+        Self {
+            library,
+            paths,
+            kind,
+            playlists: Vec::new(),
+        }
+    }
+
+    /// Set the playlists to update when a song is renamed.
+    pub fn playlists(mut self, playlists: Vec<Playlist>) -> Self {
+        self.playlists = playlists;
+        self
+    }
+
+    /// Start editing in the background, the [`BatchEditEvent`]s are sent to the returned receiver.
+    pub fn start(self) -> (ScanHandle, Receiver<BatchEditEvent>) {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (event_tx, event_rx) = mpsc::channel();
+        let thread = {
+            let cancel = cancel.clone();
+            thread::spawn(move || self.run(&cancel, event_tx))
+        };
+        (ScanHandle::new(cancel, thread), event_rx)
+    }
+
+    fn run(mut self, cancel: &AtomicBool, events: Sender<BatchEditEvent>) {
+        let total = self.paths.len();
+        let mut cancelled = false;
+        for (done, path) in std::mem::take(&mut self.paths).into_iter().enumerate() {
+            if cancel.load(Ordering::Relaxed) {
+                cancelled = true;
+                break;
+            }
+            match self.edit(&path) {
+                Ok(Some(song)) => {
+                    if song.path() != path {
+                        self.update_playlists(&path, song.path(), &events);
+                    }
+                    let _ = events.send(BatchEditEvent::Edited {
+                        path,
+                        song: Box::new(song),
+                    });
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    error!("Could not edit '{}': {e}", path.display());
+                    let _ = events.send(BatchEditEvent::Error {
+                        path,
+                        error: e.to_string(),
+                    });
+                    break;
+                }
+            }
+            let _ = events.send(BatchEditEvent::Progress {
+                done: done + 1,
+                total,
+            });
+        }
+        let _ = events.send(BatchEditEvent::Finished { cancelled });
+    }
+
+    /// Edit the song at `path`, None if it was left as it is.
+    fn edit(&mut self, path: &Path) -> Result<Option<Song>, TagWriteError> {
+        match &self.kind {
+            BatchEditKind::Tags(edits) => self.library.edit_tags(path, edits).map(Some),
+            BatchEditKind::FromFileNames(pattern) => {
+                let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                match tags_from_file_name(pattern, &name)? {
+                    Some(edits) => self.library.edit_tags(path, &edits).map(Some),
+                    None => {
+                        warn!("'{name}' doesn't match the pattern '{pattern}'");
+                        Ok(None)
+                    }
+                }
+            }
+            BatchEditKind::Rename(pattern) => {
+                self.library.rename_from_tags(path, pattern).map(Some)
+            }
+        }
+    }
+
+    fn update_playlists(&self, old: &Path, new: &Path, events: &Sender<BatchEditEvent>) {
+        for playlist in &self.playlists {
+            if let Err(e) = playlist.replace_song(old, new) {
+                error!(
+                    "Could not update playlist '{}': {e}",
+                    playlist.path().display()
+                );
+                let _ = events.send(BatchEditEvent::Error {
+                    path: playlist.path().to_path_buf(),
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tags::TagField;
+
+    #[test]
+    fn test_edit_tags() {
+        let dir = std::env::temp_dir().join("amuseing-test-edit-tags");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        // Silent MPEG-1 layer 3 frames
+        let mut frame = vec![0xff, 0xfb, 0x90, 0x64];
+        frame.resize(417, 0);
+        let path = dir.join("song.mp3");
+        fs::write(&path, frame.repeat(40)).unwrap();
+
+        let mut library = Library::default();
+        library.song(&path).unwrap();
+        library.set_rating(&path.canonicalize().unwrap(), Some(4));
+        let edits = [
+            (TagField::Title, Some("Title".into())),
+            (TagField::Artist, Some("Artist".into())),
+            (TagField::TrackNumber, Some("3".into())),
+        ];
+        let song = library.edit_tags(&path, &edits).unwrap();
+        assert_eq!(song.title(), "Title");
+        assert_eq!(library.get(song.path()), Some(&song));
+
+        let song = library
+            .rename_from_tags(song.path(), "{track} {artist} - {title}")
+            .unwrap();
+        assert_eq!(
+            song.path(),
+            dir.join("03 Artist - Title.mp3").canonicalize().unwrap()
+        );
+        assert!(!path.exists());
+        assert_eq!(library.len(), 1);
+        assert_eq!(library.stats(song.path()).unwrap().rating, Some(4));
+        assert!(matches!(
+            library.rename_from_tags(song.path(), "{album}"),
+            Err(TagWriteError::MissingTag)
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_batch_edit() {
+        let dir = std::env::temp_dir().join("amuseing-test-batch-edit");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut frame = vec![0xff, 0xfb, 0x90, 0x64];
+        frame.resize(417, 0);
+        for name in ["01 A.mp3", "02 B.mp3"] {
+            fs::write(dir.join(name), frame.repeat(40)).unwrap();
+        }
+        fs::write(dir.join("list.m3u8"), "02 B.mp3\n01 A.mp3\n").unwrap();
+        let playlist = Playlist::new(dir.join("list.m3u8"), "List".into(), None).unwrap();
+        let mut library = Library::default();
+        let paths: Vec<PathBuf> = ["01 A.mp3", "02 B.mp3"]
+            .iter()
+            .map(|name| library.song(&dir.join(name)).unwrap().path().to_path_buf())
+            .collect();
+
+        let read_tags = BatchEdit::new(
+            library.clone(),
+            paths.clone(),
+            BatchEditKind::FromFileNames("{track} {title}".into()),
+        );
+        let (handle, events) = read_tags.start();
+        for event in events {
+            if let BatchEditEvent::Edited { path, song } = event {
+                library.replace(&path, *song).unwrap();
+            }
+        }
+        handle.join();
+        assert_eq!(library.get(&paths[1]).unwrap().title(), "B");
+
+        let rename = BatchEdit::new(
+            library.clone(),
+            paths,
+            BatchEditKind::Rename("{title}".into()),
+        )
+        .playlists(vec![playlist.clone()]);
+        let (handle, events) = rename.start();
+        let events: Vec<BatchEditEvent> = events.iter().collect();
+        handle.join();
+        assert!(matches!(
+            events.last(),
+            Some(BatchEditEvent::Finished { cancelled: false })
+        ));
+        let names: Vec<String> = crate::playlist_file::read(playlist.path())
+            .unwrap()
+            .into_iter()
+            .map(|entry| {
+                entry
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        assert_eq!(names, vec!["B.mp3", "A.mp3"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
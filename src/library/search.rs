@@ -0,0 +1,618 @@
+use std::{cell::OnceCell, collections::HashMap};
+
+use memchr::memmem::Finder;
+
+use super::Library;
+use crate::playback::Song;
+
+/// A field that a search term can be limited to, with `field:value`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SearchField {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Path,
+}
+
+impl SearchField {
+    /// The fields in the order they're stored in an [`IndexEntry`].
+    const ALL: [SearchField; 5] = [
+        SearchField::Title,
+        SearchField::Artist,
+        SearchField::Album,
+        SearchField::Genre,
+        SearchField::Path,
+    ];
+    /// The fields that terms without a field are matched against.
+    const ANY: [SearchField; 4] = [
+        SearchField::Title,
+        SearchField::Artist,
+        SearchField::Album,
+        SearchField::Path,
+    ];
+}
+
+/// One whitespace separated part of a search query.
+#[derive(Clone, Debug, PartialEq)]
+enum Term {
+    /// Matched against the title, artist, album and path.
+    Any(String),
+    Field(SearchField, String),
+    /// `year:2000`, `year:>2000`, `year:<=1999` etc, the two years are the inclusive range that matches.
+    Year(i32, i32),
+}
+
+/// A search over every song in a [`Library`], see [`Library::search`].
+///
+/// The index keeps its own copy of the songs, so it should be built again when the library changes.
+/// Building the index is the slow part, the searches themselves are fast enough to run on every key press.
+///
+/// # Query syntax
+///
+/// Every word in the query has to match the title, artist, album or path of a song.
+/// Words are matched as substrings first, then as typos of words in the song's fields (one typo for short words, two for longer ones),
+/// and titles, artists and albums also match if the word's letters appear in order, e.g. "drkside" finds "Dark Side".
+///
+/// A word can be limited to a field with `title:`, `artist:`, `album:`, `genre:` or `path:`,
+/// quotes match several words together (`artist:"pink floyd"`),
+/// and `year:` takes a year with an optional comparison, like `year:>2000` or `year:<=1999`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchIndex {
+    /// The songs, in the same order as `entries`.
+    songs: Vec<Song>,
+    entries: Vec<IndexEntry>,
+    /// Every distinct word in the songs' fields, terms are compared to each word once instead of once for each song.
+    words: Vec<Word>,
+}
+
+/// The searched fields of a song.
+#[derive(Clone, Debug, PartialEq)]
+struct IndexEntry {
+    /// The fields in lowercase, in the order of [`SearchField::ALL`], separated by newlines.
+    text: String,
+    /// Where each field ends in `text`.
+    text_ends: [u32; 5],
+    /// The indexes of the fields' words in [`SearchIndex::words`], in the order they appear in `text`.
+    words: Vec<u32>,
+    /// Where each field's words end in `words`.
+    word_ends: [u32; 5],
+    year: Option<i32>,
+}
+
+/// A word in the songs' fields.
+#[derive(Clone, Debug, PartialEq)]
+struct Word {
+    text: String,
+    chars: Vec<char>,
+    /// See [`letter_mask`].
+    letters: u64,
+}
+
+/// The score of a term that's at the start of a field.
+const FIELD_START_SCORE: u8 = 100;
+/// The score of a term that's at the start of a word.
+const WORD_START_SCORE: u8 = 80;
+/// The score of a term that's inside of a word.
+const INNER_SCORE: u8 = 60;
+/// The score of a term with no typos, each typo lowers it by 10.
+const TYPO_SCORE: u8 = 40;
+/// The score of a term whose letters are in a field in order.
+const IN_ORDER_SCORE: u8 = 10;
+/// Longer words are only matched as substrings, not with typos.
+const MAX_TYPO_WORD_LEN: usize = 32;
+
+impl SearchIndex {
+    pub fn new(library: &Library) -> Self {
+        let mut words = Vec::new();
+        let mut word_indexes: HashMap<String, u32> = HashMap::new();
+        let lowercase = |value: Option<&str>| value.unwrap_or_default().to_lowercase();
+        // Sorted by title, so results with the same score are sorted by their index
+        let mut songs: Vec<&Song> = library.songs().collect();
+        songs.sort_by_cached_key(|song| (song.title().to_lowercase(), song.path().to_path_buf()));
+        let (songs, entries) = songs
+            .into_iter()
+            .map(|song| {
+                let tags = song.tags();
+                let artist = match (&tags.artist, &tags.album_artist) {
+                    (Some(artist), Some(album_artist)) if artist != album_artist => {
+                        format!("{artist} {album_artist}").to_lowercase()
+                    }
+                    (artist, album_artist) => {
+                        lowercase(artist.as_deref().or(album_artist.as_deref()))
+                    }
+                };
+                let fields = [
+                    song.title().to_lowercase(),
+                    artist,
+                    lowercase(tags.album.as_deref()),
+                    lowercase(tags.genre.as_deref()),
+                    song.path().to_string_lossy().to_lowercase(),
+                ];
+                let mut entry = IndexEntry {
+                    text: String::new(),
+                    text_ends: [0; 5],
+                    words: Vec::new(),
+                    word_ends: [0; 5],
+                    year: tags.year,
+                };
+                for (i, field) in fields.iter().enumerate() {
+                    if i > 0 {
+                        entry.text.push('\n');
+                    }
+                    entry.text.push_str(field);
+                    entry.text_ends[i] = entry.text.len() as u32;
+                    for word in field.split(is_separator).filter(|word| !word.is_empty()) {
+                        let index = match word_indexes.get(word) {
+                            Some(index) => *index,
+                            None => {
+                                let index = words.len() as u32;
+                                let chars: Vec<char> = word.chars().collect();
+                                let letters = letter_mask(&chars);
+                                words.push(Word {
+                                    text: word.to_string(),
+                                    chars,
+                                    letters,
+                                });
+                                word_indexes.insert(word.to_string(), index);
+                                index
+                            }
+                        };
+                        entry.words.push(index);
+                    }
+                    entry.word_ends[i] = entry.words.len() as u32;
+                }
+                (song.clone(), entry)
+            })
+            .unzip();
+        Self {
+            songs,
+            entries,
+            words,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Find up to `limit` songs matching `query`, best matches first.
+    ///
+    /// An empty query matches nothing.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<&Song> {
+        let terms = parse_query(query);
+        if terms.is_empty() || limit == 0 {
+            return Vec::new();
+        }
+        let mut terms: Vec<Matcher> = terms
+            .iter()
+            .map(|term| match term {
+                Term::Any(text) => {
+                    Matcher::Text(&SearchField::ANY, Box::new(self.text_matcher(text)))
+                }
+                Term::Field(field, text) => {
+                    let fields = &SearchField::ALL[*field as usize..*field as usize + 1];
+                    Matcher::Text(fields, Box::new(self.text_matcher(text)))
+                }
+                Term::Year(min, max) => Matcher::Year(*min, *max),
+            })
+            .collect();
+        // Years are quick to check and rule out most songs
+        terms.sort_by_key(|term| !matches!(term, Matcher::Year(..)));
+        let mut scored: Vec<(u32, usize)> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entry)| Some((entry.score(&terms)?, i)))
+            .collect();
+        let best_first = |(a_score, a): &(u32, usize), (b_score, b): &(u32, usize)| {
+            b_score.cmp(a_score).then(a.cmp(b))
+        };
+        // Only the results that are returned have to be sorted
+        if scored.len() > limit {
+            scored.select_nth_unstable_by(limit - 1, best_first);
+            scored.truncate(limit);
+        }
+        scored.sort_by(best_first);
+        scored.into_iter().map(|(_, i)| &self.songs[i]).collect()
+    }
+
+    fn text_matcher<'a>(&self, term: &'a str) -> TextMatcher<'a> {
+        let chars: Vec<char> = term.chars().collect();
+        let spans_words = term.contains(is_separator);
+        let max_typos = match chars.len() {
+            _ if spans_words => 0,
+            0..=2 => 0,
+            3..=5 => 1,
+            _ => 2,
+        };
+        let letters = letter_mask(&chars);
+        let finder = Finder::new(term);
+        let word_scores = self
+            .words
+            .iter()
+            .map(|word| {
+                if spans_words {
+                    return 0;
+                }
+                // A word can only contain the term if it has all of its letters
+                let has_letters = letters & !word.letters == 0;
+                let substring = match has_letters && word.text.len() >= term.len() {
+                    true => finder.find(word.text.as_bytes()),
+                    false => None,
+                };
+                if substring == Some(0) {
+                    WORD_START_SCORE
+                } else if substring.is_some() {
+                    INNER_SCORE
+                // Every typo changes at most two letters, so most words are ruled out without comparing them
+                } else if (letters ^ word.letters).count_ones() as usize > 2 * max_typos {
+                    0
+                } else {
+                    edit_distance(&chars, &word.chars, max_typos)
+                        .map_or(0, |typos| TYPO_SCORE - 10 * typos as u8)
+                }
+            })
+            .collect();
+        TextMatcher {
+            term,
+            finder,
+            word_scores,
+            spans_words,
+            in_order: chars.len() > 2,
+        }
+    }
+}
+
+/// A query [`Term`] with what's needed to check it against every song quickly.
+enum Matcher<'a> {
+    /// Matched against any of the fields.
+    Text(&'static [SearchField], Box<TextMatcher<'a>>),
+    Year(i32, i32),
+}
+
+struct TextMatcher<'a> {
+    term: &'a str,
+    finder: Finder<'a>,
+    /// How well the term matches each of the [`SearchIndex::words`], 0 if it doesn't.
+    word_scores: Vec<u8>,
+    /// The term has characters that words are split on, so it can only match the fields' text.
+    spans_words: bool,
+    /// Whether the letters of the term can match in order, too short terms would match nearly everything.
+    in_order: bool,
+}
+
+impl IndexEntry {
+    /// The sum of the scores of all the terms, None if any of them doesn't match.
+    fn score(&self, terms: &[Matcher]) -> Option<u32> {
+        terms.iter().try_fold(0, |total, term| {
+            let score = match term {
+                Matcher::Text(fields, matcher) => {
+                    // The fields before the path are next to each other in the text,
+                    // so they're ruled out for matching in order together, only if it's needed
+                    let in_order = OnceCell::new();
+                    let in_order = || {
+                        *in_order.get_or_init(|| {
+                            let text = &self.text[..self.text_ends[3] as usize];
+                            matcher.in_order && is_subsequence(matcher.term, text)
+                        })
+                    };
+                    fields
+                        .iter()
+                        .filter_map(|field| matcher.score(self, *field, in_order))
+                        .max()?
+                }
+                Matcher::Year(min, max) => {
+                    let year = self.year?;
+                    (*min..=*max).contains(&year).then_some(0)?
+                }
+            };
+            Some(total + u32::from(score))
+        })
+    }
+
+    fn text(&self, field: SearchField) -> &str {
+        let i = field as usize;
+        // Skip the newline before the field
+        let start = if i == 0 { 0 } else { self.text_ends[i - 1] + 1 };
+        &self.text[start as usize..self.text_ends[i] as usize]
+    }
+
+    fn words(&self, field: SearchField) -> &[u32] {
+        let i = field as usize;
+        let start = if i == 0 { 0 } else { self.word_ends[i - 1] };
+        &self.words[start as usize..self.word_ends[i] as usize]
+    }
+}
+
+impl TextMatcher<'_> {
+    /// How well the term matches `field` of `entry`, None if it doesn't match at all.
+    ///
+    /// Substrings score highest, especially at the start of a field or word, then typos of a word,
+    /// then the letters of the term in order for fields other than the path, since nearly every path would match.
+    /// Path matches only score half.
+    ///
+    /// `in_order` returns false if the term's letters can't be in order in any of the fields.
+    fn score(
+        &self,
+        entry: &IndexEntry,
+        field: SearchField,
+        in_order: impl Fn() -> bool,
+    ) -> Option<u8> {
+        let score = if self.spans_words {
+            let text = entry.text(field);
+            self.finder
+                .find(text.as_bytes())
+                .map_or(0, |position| substring_score(text, position))
+        } else {
+            entry
+                .words(field)
+                .iter()
+                .enumerate()
+                .map(|(i, word)| match self.word_scores[*word as usize] {
+                    WORD_START_SCORE if i == 0 && entry.text(field).starts_with(self.term) => {
+                        FIELD_START_SCORE
+                    }
+                    score => score,
+                })
+                .max()
+                .unwrap_or(0)
+        };
+        let score = match score {
+            0 if field != SearchField::Path
+                && in_order()
+                && is_subsequence(self.term, entry.text(field)) =>
+            {
+                IN_ORDER_SCORE
+            }
+            0 => return None,
+            score => score,
+        };
+        Some(match field {
+            SearchField::Path => score / 2,
+            _ => score,
+        })
+    }
+}
+
+/// The score of a substring of `text` at `position`, higher if it's at the start of the text or a word.
+fn substring_score(text: &str, position: usize) -> u8 {
+    match text[..position].chars().next_back() {
+        None => FIELD_START_SCORE,
+        Some(c) if is_separator(c) => WORD_START_SCORE,
+        Some(_) => INNER_SCORE,
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    !c.is_alphanumeric()
+}
+
+impl Library {
+    /// Find up to `limit` songs matching `query`, best matches first, see [`SearchIndex`] for the query syntax.
+    ///
+    /// This builds a new index every time, use a [`SearchIndex`] directly to search the same library many times.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<Song> {
+        SearchIndex::new(self)
+            .search(query, limit)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+}
+
+/// Split `query` into lowercase terms, see [`SearchIndex`].
+fn parse_query(query: &str) -> Vec<Term> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in query.to_lowercase().chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => words.push(std::mem::take(&mut word)),
+            c => word.push(c),
+        }
+    }
+    words.push(word);
+    words
+        .into_iter()
+        .filter(|word| !word.is_empty())
+        .map(|word| parse_term(&word).unwrap_or(Term::Any(word)))
+        .collect()
+}
+
+/// Parse a `field:value` term, None if it's plain text.
+fn parse_term(word: &str) -> Option<Term> {
+    let (field, value) = word.split_once(':')?;
+    if value.is_empty() {
+        return None;
+    }
+    let field = match field {
+        "title" => SearchField::Title,
+        "artist" => SearchField::Artist,
+        "album" => SearchField::Album,
+        "genre" => SearchField::Genre,
+        "path" => SearchField::Path,
+        "year" => {
+            let (min, max) = if let Some(year) = value.strip_prefix(">=") {
+                (year.parse().ok()?, i32::MAX)
+            } else if let Some(year) = value.strip_prefix("<=") {
+                (i32::MIN, year.parse().ok()?)
+            } else if let Some(year) = value.strip_prefix('>') {
+                (year.parse::<i32>().ok()?.checked_add(1)?, i32::MAX)
+            } else if let Some(year) = value.strip_prefix('<') {
+                (i32::MIN, year.parse::<i32>().ok()?.checked_sub(1)?)
+            } else {
+                let year = value.parse().ok()?;
+                (year, year)
+            };
+            return Some(Term::Year(min, max));
+        }
+        _ => return None,
+    };
+    Some(Term::Field(field, value.to_string()))
+}
+
+/// A bit for each different character in `chars`, characters can share a bit.
+fn letter_mask(chars: &[char]) -> u64 {
+    chars
+        .iter()
+        .fold(0, |mask, c| mask | 1 << (u32::from(*c) % 64))
+}
+
+/// Check if all the characters of `term` appear in `text` in order.
+fn is_subsequence(term: &str, text: &str) -> bool {
+    if term.is_ascii() {
+        let mut text = text.as_bytes();
+        return term.bytes().all(|byte| match memchr::memchr(byte, text) {
+            Some(position) => {
+                text = &text[position + 1..];
+                true
+            }
+            None => false,
+        });
+    }
+    let mut text = text.chars();
+    term.chars().all(|c| text.any(|t| t == c))
+}
+
+/// The Levenshtein distance between `a` and `b`, None if it's more than `max`.
+///
+/// Words longer than [`MAX_TYPO_WORD_LEN`] never match, so the rows fit on the stack.
+fn edit_distance(a: &[char], b: &[char], max: usize) -> Option<usize> {
+    if a.len().abs_diff(b.len()) > max || b.len() > MAX_TYPO_WORD_LEN {
+        return None;
+    }
+    let mut previous = [0; MAX_TYPO_WORD_LEN + 1];
+    let mut current = [0; MAX_TYPO_WORD_LEN + 1];
+    for (j, distance) in previous.iter_mut().enumerate().take(b.len() + 1) {
+        *distance = j;
+    }
+    for (i, a_char) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        // Every later row is at least the smallest value of this one
+        if current[..=b.len()]
+            .iter()
+            .min()
+            .is_some_and(|min| *min > max)
+        {
+            return None;
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    Some(previous[b.len()]).filter(|distance| *distance <= max)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use super::*;
+    use crate::tags::Tags;
+
+    fn library(songs: &[(&str, &str, Option<i32>)]) -> Library {
+        Library::with_songs(songs.iter().map(|(title, artist, year)| {
+            let path = PathBuf::from(format!("/music/{artist}/{title}.mp3"));
+            let tags = Tags {
+                artist: Some(artist.to_string()),
+                year: *year,
+                ..Default::default()
+            };
+            Song::new(0, title.to_string(), path, Duration::ZERO).with_tags(tags)
+        }))
+    }
+
+    fn titles(songs: Vec<&Song>) -> Vec<&str> {
+        songs.into_iter().map(|song| song.title()).collect()
+    }
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(
+            parse_query(r#"Dark  artist:"Pink Floyd" year:>1970 foo:bar"#),
+            vec![
+                Term::Any("dark".into()),
+                Term::Field(SearchField::Artist, "pink floyd".into()),
+                Term::Year(1971, i32::MAX),
+                Term::Any("foo:bar".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_search() {
+        let index = SearchIndex::new(&library(&[
+            ("Money", "Pink Floyd", Some(1973)),
+            ("Time", "Pink Floyd", Some(1973)),
+            ("Comfortably Numb", "Pink Floyd", Some(1979)),
+            ("Yesterday", "The Beatles", Some(1965)),
+        ]));
+        assert_eq!(titles(index.search("", 10)), Vec::<&str>::new());
+        assert_eq!(titles(index.search("money", 10)), vec!["Money"]);
+        // A typo, and letters in order
+        assert_eq!(titles(index.search("yesterdya", 10)), vec!["Yesterday"]);
+        assert_eq!(
+            titles(index.search("cmfnumb", 10)),
+            vec!["Comfortably Numb"]
+        );
+        assert_eq!(
+            titles(index.search("floyd year:>=1975", 10)),
+            vec!["Comfortably Numb"]
+        );
+        assert_eq!(
+            titles(index.search("artist:beetles", 10)),
+            vec!["Yesterday"]
+        );
+        assert_eq!(titles(index.search("floyd", 2)).len(), 2);
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        let chars = |text: &str| text.chars().collect::<Vec<char>>();
+        assert_eq!(
+            edit_distance(&chars("kitten"), &chars("sitting"), 3),
+            Some(3)
+        );
+        assert_eq!(edit_distance(&chars("kitten"), &chars("sitting"), 2), None);
+        assert_eq!(edit_distance(&chars("abc"), &chars("abc"), 0), Some(0));
+    }
+
+    /// Run with `cargo test --release -- --ignored`, the time limit is for optimized builds.
+    #[test]
+    #[ignore]
+    fn test_search_speed() {
+        let songs: Vec<(String, String, Option<i32>)> = (0..100_000)
+            .map(|i| {
+                (
+                    format!("Song number {i}"),
+                    format!("Artist {}", i % 1000),
+                    Some(1950 + i % 70),
+                )
+            })
+            .collect();
+        let songs: Vec<(&str, &str, Option<i32>)> = songs
+            .iter()
+            .map(|(title, artist, year)| (title.as_str(), artist.as_str(), *year))
+            .collect();
+        let index = SearchIndex::new(&library(&songs));
+        // The fastest of a few runs, so a busy machine doesn't fail the test
+        let fastest = (0..5)
+            .map(|_| {
+                let start = std::time::Instant::now();
+                let results = index.search("artst 42 year:>2000 numbr", 100);
+                assert!(!results.is_empty());
+                start.elapsed()
+            })
+            .min()
+            .unwrap();
+        assert!(fastest < Duration::from_millis(50), "{fastest:?}");
+    }
+}
@@ -0,0 +1,419 @@
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use super::{browse::sort_tracks, Library, SongStats, TrackSort};
+use crate::playback::Song;
+
+/// A playlist of the library's songs that match its rules, e.g. the jazz songs from before 1970.
+///
+/// Smart playlists are defined in the config file, and their songs are picked again whenever the library changes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SmartPlaylist {
+    pub name: String,
+    #[serde(rename = "match")]
+    #[serde(default)]
+    pub match_mode: MatchMode,
+    #[serde(rename = "rule")]
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    #[serde(default)]
+    pub sort: SmartSort,
+    /// The most songs the playlist has, the first ones after sorting are kept.
+    #[serde(default)]
+    pub limit: Option<usize>,
+    /// The seed of the [random](SmartSort::Random) order, so the songs keep their order until the playlist is reshuffled.
+    #[serde(default)]
+    pub seed: u64,
+}
+
+impl SmartPlaylist {
+    /// A smart playlist without rules, which has all of the library's songs.
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            match_mode: MatchMode::All,
+            rules: Vec::new(),
+            sort: SmartSort::default(),
+            limit: None,
+            seed: rand::random(),
+        }
+    }
+
+    /// Pick a new random order, see [`seed`](Self::seed).
+    pub fn reshuffle(&mut self) {
+        self.seed = rand::random();
+    }
+
+    /// Check if `song` with `stats` matches the playlist's rules at `now`, every song matches if there are none.
+    pub fn matches(&self, song: &Song, stats: &SongStats, now: u64) -> bool {
+        let matches = |rule: &Rule| rule.matches(song, stats, now);
+        match self.match_mode {
+            MatchMode::All => self.rules.iter().all(matches),
+            MatchMode::Any => self.rules.is_empty() || self.rules.iter().any(matches),
+        }
+    }
+}
+
+/// Whether a song has to match all of a [`SmartPlaylist`]'s rules, or only one of them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatchMode {
+    #[default]
+    All,
+    Any,
+}
+
+/// How the songs of a [`SmartPlaylist`] are sorted, before the [limit](SmartPlaylist::limit) is applied.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SmartSort {
+    /// In a random order, so a limited playlist has different songs every time it's [reshuffled](SmartPlaylist::reshuffle).
+    Random,
+    #[default]
+    Album,
+    Title,
+    Artist,
+    Year,
+    Duration,
+    PlayCount,
+    Rating,
+    LastPlayed,
+    Added,
+}
+
+impl SmartSort {
+    pub const ALL: [SmartSort; 10] = [
+        SmartSort::Random,
+        SmartSort::Album,
+        SmartSort::Title,
+        SmartSort::Artist,
+        SmartSort::Year,
+        SmartSort::Duration,
+        SmartSort::PlayCount,
+        SmartSort::Rating,
+        SmartSort::LastPlayed,
+        SmartSort::Added,
+    ];
+
+    fn track_sort(self) -> Option<TrackSort> {
+        match self {
+            SmartSort::Random => None,
+            SmartSort::Album => Some(TrackSort::Album),
+            SmartSort::Title => Some(TrackSort::Title),
+            SmartSort::Artist => Some(TrackSort::Artist),
+            SmartSort::Year => Some(TrackSort::Year),
+            SmartSort::Duration => Some(TrackSort::Duration),
+            SmartSort::PlayCount => Some(TrackSort::PlayCount),
+            SmartSort::Rating => Some(TrackSort::Rating),
+            SmartSort::LastPlayed => Some(TrackSort::LastPlayed),
+            SmartSort::Added => Some(TrackSort::Added),
+        }
+    }
+}
+
+/// One condition of a [`SmartPlaylist`], e.g. `genre is Jazz` or `year at-most 1970`.
+///
+/// The value is kept as it was written, so a rule that's being edited isn't lost.
+/// Text is compared ignoring case, a number rule whose value isn't a number matches nothing.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Rule {
+    pub field: RuleField,
+    pub operator: Operator,
+    #[serde(default)]
+    pub value: String,
+}
+
+impl Rule {
+    /// Check if `song` with `stats` matches the rule at `now`,
+    /// a song that isn't tagged with the field only matches the negated operators.
+    pub fn matches(&self, song: &Song, stats: &SongStats, now: u64) -> bool {
+        match self.field.value(song, stats, now) {
+            None => matches!(self.operator, Operator::IsNot | Operator::DoesNotContain),
+            Some(Value::Text(text)) => {
+                let text = text.to_lowercase();
+                let value = self.value.trim().to_lowercase();
+                match self.operator {
+                    Operator::Is => text == value,
+                    Operator::IsNot => text != value,
+                    Operator::Contains => text.contains(&value),
+                    Operator::DoesNotContain => !text.contains(&value),
+                    Operator::AtLeast | Operator::AtMost => false,
+                }
+            }
+            Some(Value::Number(number)) => {
+                let Ok(value) = self.value.trim().parse::<f64>() else {
+                    return false;
+                };
+                match self.operator {
+                    Operator::Is => number == value,
+                    Operator::IsNot => number != value,
+                    Operator::AtLeast => number >= value,
+                    Operator::AtMost => number <= value,
+                    Operator::Contains | Operator::DoesNotContain => false,
+                }
+            }
+        }
+    }
+}
+
+/// The value of a [`RuleField`] for one song.
+enum Value {
+    Text(String),
+    Number(f64),
+}
+
+/// The part of a song a [`Rule`] checks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuleField {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    Genre,
+    Composer,
+    Path,
+    Year,
+    /// The song's length in seconds.
+    Duration,
+    Bpm,
+    /// 1 to 5 stars, unrated songs don't have a rating.
+    Rating,
+    PlayCount,
+    SkipCount,
+    /// Songs that were never played count as played infinitely long ago.
+    DaysSincePlayed,
+    DaysSinceAdded,
+}
+
+impl RuleField {
+    pub const ALL: [RuleField; 15] = [
+        RuleField::Title,
+        RuleField::Artist,
+        RuleField::Album,
+        RuleField::AlbumArtist,
+        RuleField::Genre,
+        RuleField::Composer,
+        RuleField::Path,
+        RuleField::Year,
+        RuleField::Duration,
+        RuleField::Bpm,
+        RuleField::Rating,
+        RuleField::PlayCount,
+        RuleField::SkipCount,
+        RuleField::DaysSincePlayed,
+        RuleField::DaysSinceAdded,
+    ];
+
+    /// Check if the field is compared as a number instead of as text.
+    pub fn is_number(self) -> bool {
+        !matches!(
+            self,
+            RuleField::Title
+                | RuleField::Artist
+                | RuleField::Album
+                | RuleField::AlbumArtist
+                | RuleField::Genre
+                | RuleField::Composer
+                | RuleField::Path
+        )
+    }
+
+    /// The operators that can be used with the field.
+    pub fn operators(self) -> &'static [Operator] {
+        if self.is_number() {
+            &[
+                Operator::Is,
+                Operator::IsNot,
+                Operator::AtLeast,
+                Operator::AtMost,
+            ]
+        } else {
+            &[
+                Operator::Is,
+                Operator::IsNot,
+                Operator::Contains,
+                Operator::DoesNotContain,
+            ]
+        }
+    }
+
+    fn value(self, song: &Song, stats: &SongStats, now: u64) -> Option<Value> {
+        let tags = song.tags();
+        let text = |text: &Option<String>| text.clone().map(Value::Text);
+        match self {
+            RuleField::Title => Some(Value::Text(song.title().to_string())),
+            RuleField::Artist => text(&tags.artist),
+            RuleField::Album => text(&tags.album),
+            RuleField::AlbumArtist => text(&tags.album_artist),
+            RuleField::Genre => text(&tags.genre),
+            RuleField::Composer => text(&tags.composer),
+            RuleField::Path => Some(Value::Text(song.path().display().to_string())),
+            RuleField::Year => tags.year.map(|year| Value::Number(year.into())),
+            RuleField::Duration => Some(Value::Number(song.duration().as_secs_f64())),
+            RuleField::Bpm => tags.bpm.map(|bpm| Value::Number(bpm.into())),
+            RuleField::Rating => stats.rating.map(|rating| Value::Number(rating.into())),
+            RuleField::PlayCount => Some(Value::Number(stats.play_count.into())),
+            RuleField::SkipCount => Some(Value::Number(stats.skip_count.into())),
+            RuleField::DaysSincePlayed => Some(Value::Number(
+                stats.days_since_played(now).unwrap_or(f64::INFINITY),
+            )),
+            RuleField::DaysSinceAdded => stats.days_since_added(now).map(Value::Number),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operator {
+    Is,
+    IsNot,
+    Contains,
+    DoesNotContain,
+    AtLeast,
+    AtMost,
+}
+
+impl Library {
+    /// The songs matching `playlist`'s rules, sorted and limited like it says.
+    ///
+    /// A random order is the same every time for the playlist's [`seed`](SmartPlaylist::seed).
+    pub fn smart_playlist(&self, playlist: &SmartPlaylist) -> Vec<Song> {
+        let now = super::unix_now();
+        let mut songs: Vec<&Song> = self
+            .entries
+            .values()
+            .filter(|entry| playlist.matches(&entry.song, &entry.stats, now))
+            .map(|entry| &entry.song)
+            .collect();
+        match playlist.sort.track_sort() {
+            Some(sort) => sort_tracks(self, &mut songs, sort),
+            None => {
+                // Sorted first, so the same seed always gives the same songs
+                songs.sort_by(|a, b| a.path().cmp(b.path()));
+                songs.shuffle(&mut StdRng::seed_from_u64(playlist.seed));
+            }
+        }
+        if let Some(limit) = playlist.limit {
+            songs.truncate(limit);
+        }
+        songs.into_iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use super::*;
+    use crate::tags::Tags;
+
+    fn song(path: &str, genre: Option<&str>, year: Option<i32>) -> Song {
+        let tags = Tags {
+            genre: genre.map(Into::into),
+            year,
+            ..Default::default()
+        };
+        Song::new(
+            0,
+            path.to_string(),
+            PathBuf::from(path),
+            Duration::from_secs(60),
+        )
+        .with_tags(tags)
+    }
+
+    fn rule(field: RuleField, operator: Operator, value: &str) -> Rule {
+        Rule {
+            field,
+            operator,
+            value: value.into(),
+        }
+    }
+
+    #[test]
+    fn test_rules() {
+        let jazz = song("/a", Some("Jazz"), Some(1965));
+        let untagged = song("/b", None, None);
+        let now = 100 * 24 * 60 * 60;
+        let stats = SongStats {
+            play_count: 2,
+            last_played: Some(now - 40 * 24 * 60 * 60),
+            rating: Some(4),
+            ..Default::default()
+        };
+        assert!(rule(RuleField::Genre, Operator::Is, "jazz").matches(&jazz, &stats, now));
+        assert!(rule(RuleField::Genre, Operator::Contains, "AZ").matches(&jazz, &stats, now));
+        assert!(!rule(RuleField::Genre, Operator::Is, "Jazz").matches(&untagged, &stats, now));
+        assert!(rule(RuleField::Genre, Operator::IsNot, "Jazz").matches(&untagged, &stats, now));
+        assert!(rule(RuleField::Year, Operator::AtMost, "1970").matches(&jazz, &stats, now));
+        assert!(!rule(RuleField::Year, Operator::AtLeast, "1970").matches(&jazz, &stats, now));
+        assert!(!rule(RuleField::Year, Operator::AtMost, "sixties").matches(&jazz, &stats, now));
+        assert!(rule(RuleField::Duration, Operator::Is, "60").matches(&jazz, &stats, now));
+        assert!(rule(RuleField::Rating, Operator::AtLeast, "4").matches(&jazz, &stats, now));
+        assert!(
+            rule(RuleField::DaysSincePlayed, Operator::AtLeast, "30").matches(&jazz, &stats, now)
+        );
+        assert!(!rule(RuleField::Rating, Operator::AtLeast, "1").matches(
+            &jazz,
+            &SongStats::default(),
+            now
+        ));
+        assert!(
+            rule(RuleField::DaysSincePlayed, Operator::AtLeast, "30").matches(
+                &jazz,
+                &SongStats::default(),
+                now
+            )
+        );
+    }
+
+    #[test]
+    fn test_smart_playlist() {
+        let library = Library::with_songs([
+            song("/1", Some("Jazz"), Some(1965)),
+            song("/2", Some("Jazz"), Some(1959)),
+            song("/3", Some("Jazz"), Some(1990)),
+            song("/4", Some("Rock"), Some(1960)),
+        ]);
+        let mut playlist = SmartPlaylist::new("Old jazz".into());
+        playlist.rules = vec![
+            rule(RuleField::Genre, Operator::Is, "Jazz"),
+            rule(RuleField::Year, Operator::AtMost, "1970"),
+        ];
+        playlist.sort = SmartSort::Year;
+        let paths = |songs: Vec<Song>| -> Vec<PathBuf> {
+            songs.iter().map(|song| song.path().to_path_buf()).collect()
+        };
+        assert_eq!(
+            paths(library.smart_playlist(&playlist)),
+            vec![PathBuf::from("/2"), PathBuf::from("/1")]
+        );
+
+        playlist.match_mode = MatchMode::Any;
+        playlist.limit = Some(3);
+        assert_eq!(
+            paths(library.smart_playlist(&playlist)),
+            vec![
+                PathBuf::from("/2"),
+                PathBuf::from("/4"),
+                PathBuf::from("/1")
+            ]
+        );
+
+        playlist.sort = SmartSort::Random;
+        playlist.limit = Some(2);
+        let shuffled = paths(library.smart_playlist(&playlist));
+        assert_eq!(shuffled.len(), 2);
+        // The same seed gives the same order, until the playlist is reshuffled
+        assert_eq!(paths(library.smart_playlist(&playlist)), shuffled);
+        playlist.seed = 1;
+        assert_eq!(library.smart_playlist(&playlist).len(), 2);
+
+        // The rules are kept in the config file
+        let toml = toml::to_string(&playlist).unwrap();
+        assert_eq!(toml::from_str::<SmartPlaylist>(&toml).unwrap(), playlist);
+    }
+}
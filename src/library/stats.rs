@@ -0,0 +1,216 @@
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::Library;
+
+/// How often and how recently a song was listened to, and how the user rated it.
+///
+/// Stats are kept in the [`Library`], times are in seconds since the unix epoch.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct SongStats {
+    /// How many times the song was played long enough to count, see [`counts_as_play`].
+    pub play_count: u32,
+    /// How many times the song was skipped before it counted as a play.
+    pub skip_count: u32,
+    pub last_played: Option<u64>,
+    /// When the song was first added to the library, None for songs added before stats were kept.
+    pub added: Option<u64>,
+    /// From 1 to 5 stars, None if the song isn't rated.
+    pub rating: Option<u8>,
+    /// Where a long song was stopped, in milliseconds, see [`Library::record_position`].
+    pub resume_position: Option<u64>,
+    /// Whether a long song was ever played to its end.
+    pub completed: bool,
+}
+
+impl SongStats {
+    pub const MAX_RATING: u8 = 5;
+
+    /// How likely the song is to come early in a [`Queue::shuffle_weighted`], higher is more likely.
+    ///
+    /// Rated songs are weighted by their stars, unrated ones count as 3 stars,
+    /// and songs that are skipped more often than they're played are pushed back.
+    ///
+    /// [`Queue::shuffle_weighted`]: crate::queue::Queue::shuffle_weighted
+    pub fn shuffle_weight(&self) -> f64 {
+        let stars = self.rating.unwrap_or(3) as f64;
+        let skip_ratio = self.skip_count as f64 / (self.play_count + 1) as f64;
+        stars / (1. + skip_ratio)
+    }
+
+    /// How many days ago the song was last played, None if it never was.
+    pub fn days_since_played(&self, now: u64) -> Option<f64> {
+        self.last_played.map(|time| days_between(time, now))
+    }
+
+    /// How many days ago the song was added to the library, None if it isn't known.
+    pub fn days_since_added(&self, now: u64) -> Option<f64> {
+        self.added.map(|time| days_between(time, now))
+    }
+}
+
+fn days_between(earlier: u64, later: u64) -> f64 {
+    later.saturating_sub(earlier) as f64 / (24 * 60 * 60) as f64
+}
+
+/// Positions this close to the start of a song aren't remembered, and this close to the end mark it as completed.
+const RESUME_MARGIN: Duration = Duration::from_secs(10);
+
+/// The current time in seconds since the unix epoch.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs())
+}
+
+/// Check if listening to `played` of a song that's `duration` long counts as playing it:
+/// at least half of the song, or 4 minutes of a long one.
+pub fn counts_as_play(duration: Duration, played: Duration) -> bool {
+    played >= (duration / 2).min(Duration::from_secs(4 * 60))
+}
+
+impl Library {
+    /// The stats of the song at `path`, None if it isn't in the library.
+    pub fn stats(&self, path: &Path) -> Option<&SongStats> {
+        self.entries.get(path).map(|entry| &entry.stats)
+    }
+
+    /// Record that the song at `path` stopped after `played` of it, at `now`.
+    ///
+    /// It's a play if [`counts_as_play`], otherwise a skip unless the song `finished`, which only happens to broken files.
+    pub fn record_listen(&mut self, path: &Path, played: Duration, finished: bool, now: u64) {
+        let Some(entry) = self.entries.get_mut(path) else {
+            return;
+        };
+        if counts_as_play(*entry.song.duration(), played) {
+            entry.stats.play_count += 1;
+            entry.stats.last_played = Some(now);
+        } else if !finished {
+            entry.stats.skip_count += 1;
+        } else {
+            return;
+        }
+        self.changed = true;
+    }
+
+    /// Remember that the song at `path` stopped at `position`, if it's at least `threshold` long.
+    ///
+    /// A song that `finished`, or stopped close to its end, is marked as completed and resumes from the start again.
+    pub fn record_position(
+        &mut self,
+        path: &Path,
+        position: Duration,
+        finished: bool,
+        threshold: Duration,
+    ) {
+        let Some(entry) = self.entries.get_mut(path) else {
+            return;
+        };
+        let duration = *entry.song.duration();
+        if threshold.is_zero() || duration < threshold {
+            return;
+        }
+        let stats = &mut entry.stats;
+        let old = (stats.resume_position, stats.completed);
+        if finished || duration.saturating_sub(position) < RESUME_MARGIN {
+            stats.completed = true;
+            stats.resume_position = None;
+        } else if position < RESUME_MARGIN {
+            stats.resume_position = None;
+        } else {
+            stats.resume_position = Some(position.as_millis() as u64);
+        }
+        self.changed |= old != (stats.resume_position, stats.completed);
+    }
+
+    /// Where the song at `path` should resume, None if it should start from the beginning.
+    pub fn resume_position(&self, path: &Path) -> Option<Duration> {
+        self.stats(path)?.resume_position.map(Duration::from_millis)
+    }
+
+    /// Rate the song at `path` from 1 to [`MAX_RATING`](SongStats::MAX_RATING) stars, None removes the rating.
+    pub fn set_rating(&mut self, path: &Path, rating: Option<u8>) {
+        if let Some(entry) = self.entries.get_mut(path) {
+            entry.stats.rating = rating.map(|rating| rating.clamp(1, SongStats::MAX_RATING));
+            self.changed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::playback::Song;
+
+    #[test]
+    fn test_record_listen() {
+        let path = PathBuf::from("/song");
+        let mut library = Library::with_songs([Song::new(
+            0,
+            "Song".into(),
+            path.clone(),
+            Duration::from_secs(60),
+        )]);
+
+        library.record_listen(&path, Duration::from_secs(10), false, 100);
+        library.record_listen(&path, Duration::from_secs(30), false, 200);
+        library.record_listen(&path, Duration::from_secs(10), true, 300);
+        library.set_rating(&path, Some(9));
+        let stats = library.stats(&path).unwrap();
+        assert_eq!(stats.play_count, 1);
+        assert_eq!(stats.skip_count, 1);
+        assert_eq!(stats.last_played, Some(200));
+        assert_eq!(stats.rating, Some(SongStats::MAX_RATING));
+        assert_eq!(stats.days_since_played(200 + 2 * 24 * 60 * 60), Some(2.));
+
+        assert!(counts_as_play(
+            Duration::from_secs(3600),
+            Duration::from_secs(4 * 60)
+        ));
+        assert!(!counts_as_play(
+            Duration::from_secs(3600),
+            Duration::from_secs(3 * 60)
+        ));
+    }
+
+    #[test]
+    fn test_record_position() {
+        let path = PathBuf::from("/song");
+        let mut library = Library::with_songs([Song::new(
+            0,
+            "Song".into(),
+            path.clone(),
+            Duration::from_secs(60),
+        )]);
+
+        // The song is shorter than the threshold, so its position isn't remembered
+        library.record_position(
+            &path,
+            Duration::from_secs(30),
+            false,
+            Duration::from_secs(120),
+        );
+        assert_eq!(library.resume_position(&path), None);
+        let threshold = Duration::from_secs(60);
+        library.record_position(&path, Duration::from_secs(30), false, threshold);
+        assert_eq!(
+            library.resume_position(&path),
+            Some(Duration::from_secs(30))
+        );
+        library.record_position(&path, Duration::from_secs(5), false, threshold);
+        assert_eq!(library.resume_position(&path), None);
+        library.record_position(&path, Duration::from_secs(30), false, threshold);
+        library.record_position(&path, Duration::from_secs(55), false, threshold);
+        let stats = library.stats(&path).unwrap();
+        assert!(stats.completed);
+        assert_eq!(stats.resume_position, None);
+    }
+}
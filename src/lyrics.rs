@@ -0,0 +1,311 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use id3::frame::{SynchronisedLyrics, SynchronisedLyricsType, TimestampFormat};
+use symphonia::core::meta::{MetadataRevision, StandardTagKey};
+
+use crate::{errors::LyricsError, playback::Song};
+
+/// A line of synchronized lyrics, sung from `time` until the next line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LyricLine {
+    pub time: Duration,
+    pub text: String,
+}
+
+/// The lyrics of a song, see [`Lyrics::for_song`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Lyrics {
+    /// Lines with the time they're sung at, sorted by time.
+    Synced(Vec<LyricLine>),
+    /// Plain text without times.
+    Unsynced(String),
+}
+
+impl Lyrics {
+    /// The lyrics of `song`, from a `.lrc` file next to it or from its tags, None if it has none.
+    ///
+    /// The `.lrc` file is used first, then ID3v2 synchronized lyrics (SYLT), and then unsynchronized lyrics
+    /// (USLT in ID3v2, LYRICS in Vorbis comments, ©lyr in MP4), which can be in the LRC format too.
+    pub fn for_song(song: &Song) -> Result<Option<Self>, LyricsError> {
+        let sidecar = Self::sidecar_path(song.path());
+        if sidecar.exists() {
+            let bytes = fs::read(sidecar)?;
+            let text = String::from_utf8_lossy(&bytes);
+            return Ok(Some(Self::parse_lrc(text.trim_start_matches('\u{feff}'))));
+        }
+        // The tags of a cue sheet's audio file are the whole album's
+        if song.cue_range().is_some() {
+            return Ok(None);
+        }
+        if let Some(lyrics) = Self::synchronised(song.path())? {
+            return Ok(Some(lyrics));
+        }
+        Self::embedded(song.path())
+    }
+
+    /// The path of the `.lrc` file with the lyrics of the song at `path`.
+    pub fn sidecar_path(path: &Path) -> PathBuf {
+        path.with_extension("lrc")
+    }
+
+    /// Read the first SYLT frame with lyrics and times in milliseconds, if the song has ID3v2 tags.
+    fn synchronised(path: &Path) -> Result<Option<Self>, LyricsError> {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if !matches!(extension.as_str(), "mp3" | "aac") {
+            return Ok(None);
+        }
+        let Some(tag) = id3::no_tag_ok(id3::Tag::read_from_path(path))? else {
+            return Ok(None);
+        };
+        let lyrics = tag.synchronised_lyrics().find(|lyrics| {
+            lyrics.timestamp_format == TimestampFormat::Ms
+                && lyrics.content_type == SynchronisedLyricsType::Lyrics
+        });
+        Ok(lyrics
+            .map(Self::from_sylt)
+            .filter(|lyrics| !lyrics.is_empty()))
+    }
+
+    /// Read the unsynchronized lyrics tag through Symphonia.
+    fn embedded(path: &Path) -> Result<Option<Self>, LyricsError> {
+        let mut probed = Song::reader(path)?;
+        let find = |revision: &MetadataRevision| {
+            revision
+                .tags()
+                .iter()
+                .find(|tag| tag.std_key == Some(StandardTagKey::Lyrics))
+                .map(|tag| tag.value.to_string())
+        };
+        let mut text = probed.format.metadata().current().and_then(find);
+        if text.is_none() {
+            if let Some(metadata) = probed.metadata.get() {
+                text = metadata.current().and_then(find);
+            }
+        }
+        Ok(text
+            .map(|text| Self::parse_lrc(&text))
+            .filter(|lyrics| !lyrics.is_empty()))
+    }
+
+    /// Join the text fragments of a SYLT frame into lines.
+    ///
+    /// Fragments starting with a line break start a new line, if there are none each fragment is a line.
+    fn from_sylt(sylt: &SynchronisedLyrics) -> Self {
+        let has_breaks = sylt
+            .content
+            .iter()
+            .any(|(_, text)| text.starts_with(['\n', '\r']));
+        let mut lines: Vec<LyricLine> = Vec::new();
+        for (millis, text) in &sylt.content {
+            let starts_line = !has_breaks || text.starts_with(['\n', '\r']);
+            match lines.last_mut() {
+                Some(line) if !starts_line => line.text += text,
+                _ => lines.push(LyricLine {
+                    time: Duration::from_millis(*millis as u64),
+                    text: text.clone(),
+                }),
+            }
+        }
+        for line in &mut lines {
+            line.text = line.text.trim().to_string();
+        }
+        lines.sort_by_key(|line| line.time);
+        Self::Synced(lines)
+    }
+
+    /// Parse lyrics in the LRC format, e.g. `[01:02.50]A line`.
+    ///
+    /// A line can have several times, `[offset:+100]` moves all of them earlier by 100 milliseconds, and the times of
+    /// words in enhanced LRC (`<01:02.50>`) are removed. Other tags like `[ar:Artist]` are ignored.
+    /// Text without any times is returned as [`Lyrics::Unsynced`].
+    pub fn parse_lrc(text: &str) -> Self {
+        let mut lines = Vec::new();
+        let mut plain = Vec::new();
+        let mut offset_millis: i64 = 0;
+        for line in text.lines() {
+            let mut rest = line.trim();
+            let mut times = Vec::new();
+            let mut has_tags = false;
+            while let Some(tag) = rest.strip_prefix('[') {
+                let Some(end) = tag.find(']') else {
+                    break;
+                };
+                let tag = &tag[..end];
+                has_tags = true;
+                if let Some(time) = parse_time(tag) {
+                    times.push(time);
+                } else if let Some(offset) = tag.strip_prefix("offset:") {
+                    offset_millis = offset.trim().parse().unwrap_or(0);
+                }
+                rest = rest[end + 2..].trim_start();
+            }
+            let text = remove_word_times(rest);
+            if times.is_empty() {
+                if !has_tags {
+                    plain.push(text);
+                }
+                continue;
+            }
+            lines.extend(times.into_iter().map(|time| LyricLine {
+                time,
+                text: text.clone(),
+            }));
+        }
+        if lines.is_empty() {
+            return Self::Unsynced(plain.join("\n").trim().to_string());
+        }
+        for line in &mut lines {
+            let millis = line.time.as_millis() as i64 - offset_millis;
+            line.time = Duration::from_millis(millis.max(0) as u64);
+        }
+        lines.sort_by_key(|line| line.time);
+        Self::Synced(lines)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Lyrics::Synced(lines) => lines.is_empty(),
+            Lyrics::Unsynced(text) => text.is_empty(),
+        }
+    }
+
+    /// The index of the line being sung at `position`, None before the first line or for unsynchronized lyrics.
+    pub fn current_line(&self, position: Duration) -> Option<usize> {
+        match self {
+            Lyrics::Synced(lines) => lines
+                .partition_point(|line| line.time <= position)
+                .checked_sub(1),
+            Lyrics::Unsynced(_) => None,
+        }
+    }
+}
+
+/// Parse a time like `01:02`, `01:02.5`, `01:02.50` or `01:02:500`.
+fn parse_time(time: &str) -> Option<Duration> {
+    let (minutes, seconds) = time.split_once(':')?;
+    let minutes: u64 = minutes.trim().parse().ok()?;
+    let (seconds, fraction) = match seconds.split_once(['.', ':']) {
+        Some((seconds, fraction)) => (seconds, fraction),
+        None => (seconds, ""),
+    };
+    let seconds: u64 = seconds.parse().ok()?;
+    if seconds >= 60 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    // The fraction is in hundredths usually, but any number of digits is a fraction of a second
+    let millis = format!("{fraction:0<3}")[..3].parse::<u64>().ok()?;
+    let seconds = minutes.checked_mul(60)?.checked_add(seconds)?;
+    Some(Duration::from_millis(
+        seconds.checked_mul(1000)?.checked_add(millis)?,
+    ))
+}
+
+/// Remove the times of words in enhanced LRC, like `<01:02.50>`.
+fn remove_word_times(text: &str) -> String {
+    let mut removed = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        removed += &rest[..start];
+        match rest[start..].find('>') {
+            Some(end) if parse_time(&rest[start + 1..start + end]).is_some() => {
+                rest = &rest[start + end + 1..];
+            }
+            _ => {
+                removed.push('<');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    removed += rest;
+    removed.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(millis: u64, text: &str) -> LyricLine {
+        LyricLine {
+            time: Duration::from_millis(millis),
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn test_parse_lrc() {
+        let lrc = "[ar:Artist]\n[offset:+100]\n[00:01.00]First <00:01.50>line\n[00:03.5][01:00.00]Chorus\n\n[00:02:250]Second";
+        let lyrics = Lyrics::parse_lrc(lrc);
+        assert_eq!(
+            lyrics,
+            Lyrics::Synced(vec![
+                line(900, "First line"),
+                line(2150, "Second"),
+                line(3400, "Chorus"),
+                line(59900, "Chorus"),
+            ])
+        );
+        assert_eq!(lyrics.current_line(Duration::from_millis(500)), None);
+        assert_eq!(lyrics.current_line(Duration::from_millis(2150)), Some(1));
+        assert_eq!(lyrics.current_line(Duration::from_secs(120)), Some(3));
+
+        assert_eq!(
+            Lyrics::parse_lrc("[ti:Title]\nJust <some> text\n\nwithout times\n"),
+            Lyrics::Unsynced("Just <some> text\n\nwithout times".into())
+        );
+        assert!(Lyrics::parse_lrc("[ti:Title]").is_empty());
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("01:02.5"), Some(Duration::from_millis(62_500)));
+        assert_eq!(parse_time("00:60.00"), None);
+        assert_eq!(parse_time(&format!("{}:00.00", u64::MAX / 60)), None);
+    }
+
+    #[test]
+    fn test_from_sylt() {
+        let mut sylt = SynchronisedLyrics {
+            lang: "eng".into(),
+            timestamp_format: TimestampFormat::Ms,
+            content_type: SynchronisedLyricsType::Lyrics,
+            description: String::new(),
+            content: vec![
+                (1000, "Hel".into()),
+                (1200, "lo".into()),
+                (3000, "\nWorld".into()),
+            ],
+        };
+        assert_eq!(
+            Lyrics::from_sylt(&sylt),
+            Lyrics::Synced(vec![line(1000, "Hello"), line(3000, "World")])
+        );
+        sylt.content = vec![(3000, "Second".into()), (1000, "First".into())];
+        assert_eq!(
+            Lyrics::from_sylt(&sylt),
+            Lyrics::Synced(vec![line(1000, "First"), line(3000, "Second")])
+        );
+    }
+
+    #[test]
+    fn test_sidecar() {
+        let dir = std::env::temp_dir().join("amuseing-test-lyrics");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("song.mp3");
+        fs::write(&path, b"not audio").unwrap();
+        fs::write(dir.join("song.lrc"), "\u{feff}[00:01.00]Line").unwrap();
+        let song = Song::new(0, "Song".into(), path, Duration::from_secs(3));
+        assert_eq!(
+            Lyrics::for_song(&song).unwrap(),
+            Some(Lyrics::Synced(vec![line(1000, "Line")]))
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
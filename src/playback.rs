@@ -19,7 +19,7 @@ use std::{
         Arc, Mutex, MutexGuard,
     },
     thread,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 use symphonia::core::{
     audio::Signal,
@@ -27,18 +27,50 @@ use symphonia::core::{
     errors::{Error, Result as SymphoniaResult},
     formats::{FormatOptions, FormatReader},
     io::MediaSourceStream,
+    meta::{MetadataOptions, MetadataRevision},
+    probe::{Hint, ProbeResult},
     units,
 };
-use symphonia_bundle_mp3::{MpaDecoder, MpaReader};
 use tracing::{debug, error, info, warn};
 use triple_buffer::{triple_buffer, Output};
 
 type SampleType = f64;
 /// The buffer stores `[f64; 2]` so the number of samples is double.
 const CHUNK_SIZE: usize = 512;
-
-use crate::errors::{OutOfBoundsError, PlayerStartError, SeekError, StreamSetupError};
+/// How many songs are added to the queue at once when autoplay continues it.
+const AUTOPLAY_BATCH_SIZE: usize = 10;
+/// How many of the last played songs are remembered, these are not picked by autoplay.
+const RECENTLY_PLAYED_LEN: usize = 50;
+/// Extensions of the audio files that can be played.
+pub const SUPPORTED_EXTENSIONS: &[&str] = &[
+    "mp3", "flac", "ogg", "oga", "wav", "m4a", "m4b", "mp4", "aac",
+];
+
+use crate::artwork;
+use crate::chapters::{self, Chapter};
+use crate::cue::{self, CueRange};
+use crate::errors::{
+    OutOfBoundsError, PlayContextError, PlayerStartError, PlaylistFileError, SeekError,
+    StreamSetupError,
+};
+use crate::library::Library;
+use crate::playlist_file::{self, PlaylistEntry, PlaylistFormat};
 use crate::queue::{Queue, RepeatMode};
+use crate::radio::SimilaritySource;
+use crate::session::{SavedQueue, Session};
+use crate::tags::Tags;
+use crate::undo::UndoStack;
+
+/// Check if `path` has one of the [`SUPPORTED_EXTENSIONS`], ignoring case.
+pub fn is_supported(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            SUPPORTED_EXTENSIONS
+                .iter()
+                .any(|supported| supported.eq_ignore_ascii_case(ext))
+        })
+}
 
 /// Represents a song from a [`Player`]s queue.
 ///
@@ -46,27 +78,67 @@ use crate::queue::{Queue, RepeatMode};
 ///
 /// Songs should be created with [`from_path`].
 ///
-/// The duration of the song is automatically calculated when created.
+/// The duration of the song is automatically calculated when created, and the [`Tags`] and album art are read from the file.
 ///
 /// [`from_path`]: Self::from_path
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct Song {
     id: usize,
     title: String,
     path: PathBuf,
     duration: Duration,
+    tags: Tags,
+    artwork: Option<PathBuf>,
+    /// Where the song is in its audio file if it's a track of a cue sheet.
+    #[serde(default)]
+    cue_range: Option<CueRange>,
+    /// Sorted by their start, see [`chapters::read`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    chapters: Vec<Chapter>,
 }
 
 impl Song {
-    fn new(id: usize, title: String, path: PathBuf, duration: Duration) -> Self {
+    pub(crate) fn new(id: usize, title: String, path: PathBuf, duration: Duration) -> Self {
         Self {
             id,
             title,
             path,
             duration,
+            tags: Tags::default(),
+            artwork: None,
+            cue_range: None,
+            chapters: Vec::new(),
+        }
+    }
+
+    /// A track of a cue sheet in this song's file, at the virtual `path` from [`cue::track_path`].
+    pub(crate) fn cue_track(
+        &self,
+        path: PathBuf,
+        title: String,
+        tags: Tags,
+        range: CueRange,
+        duration: Duration,
+    ) -> Self {
+        Self {
+            title,
+            path,
+            duration,
+            tags,
+            cue_range: Some(range),
+            chapters: Vec::new(),
+            ..self.clone()
         }
     }
 
+    /// Replace the song's tags, used where songs are made without a file.
+    #[cfg(test)]
+    pub(crate) fn with_tags(mut self, tags: Tags) -> Self {
+        self.tags = tags;
+        self
+    }
+
     pub fn id(&self) -> &usize {
         &self.id
     }
@@ -79,60 +151,181 @@ impl Song {
         self.path.as_path()
     }
 
+    /// The audio file the song is played from, which is only different from [`path`](Self::path) for tracks of cue sheets.
+    pub fn file(&self) -> &Path {
+        match &self.cue_range {
+            Some(range) => &range.file,
+            None => &self.path,
+        }
+    }
+
+    /// Where the song is in its audio file, None if it's the whole file.
+    pub fn cue_range(&self) -> Option<&CueRange> {
+        self.cue_range.as_ref()
+    }
+
+    /// The song's chapters, sorted by their start, empty if it has none.
+    pub fn chapters(&self) -> &[Chapter] {
+        &self.chapters
+    }
+
+    /// Where the song starts in its audio file.
+    fn start(&self) -> Duration {
+        self.cue_range
+            .as_ref()
+            .map(|range| range.start)
+            .unwrap_or_default()
+    }
+
+    /// Where the song ends in its audio file, None if it plays to the end of the file.
+    fn end(&self) -> Option<Duration> {
+        self.cue_range.as_ref().and_then(|range| range.end)
+    }
+
     pub fn duration(&self) -> &Duration {
         &self.duration
     }
 
-    /// Check if the song is a valid mp3 file.
+    /// The tags read from the song's file.
+    pub fn tags(&self) -> &Tags {
+        &self.tags
+    }
+
+    pub fn artist(&self) -> Option<&str> {
+        self.tags.artist.as_deref()
+    }
+
+    pub fn album(&self) -> Option<&str> {
+        self.tags.album.as_deref()
+    }
+
+    /// The path of the cached album art thumbnail, see [`artwork::song_thumbnail`].
+    pub fn artwork(&self) -> Option<&Path> {
+        self.artwork.as_deref()
+    }
+
+    /// The average bitrate of the song's file in kbit/s, from its size and duration, None if it can't be read.
+    ///
+    /// Tags and album art count towards the size, so this is a bit higher than the audio's bitrate.
+    pub fn average_bitrate(&self) -> Option<u64> {
+        let seconds = self.duration.as_secs_f64();
+        let size = self.file().metadata().ok()?.len();
+        (seconds > 0.).then(|| (size as f64 * 8. / seconds / 1000.).round() as u64)
+    }
+
+    /// Check if the song is a file with one of the [`SUPPORTED_EXTENSIONS`].
     // TODO: this should probably be done in `new`
     pub fn is_valid(&self) -> bool {
-        self.path.metadata().is_ok_and(|meta| meta.is_file())
-            && self.path.extension().is_some_and(|ext| ext == "mp3")
+        self.file().metadata().is_ok_and(|meta| meta.is_file()) && is_supported(self.file())
     }
 
-    /// Create a new Song from a mp3 file at `path`, and automatically calculate the duration from it.
-    pub fn from_path(title: String, path: PathBuf) -> SymphoniaResult<Song> {
+    /// Create a new Song from an audio file at `path`, and automatically calculate the duration and read the tags from it.
+    ///
+    /// `title` is only used if the file doesn't have a title tag.
+    /// The album art thumbnail is cached in `artwork_dir`, the song has no artwork if it's None.
+    pub fn from_path(
+        title: String,
+        path: PathBuf,
+        artwork_dir: Option<&Path>,
+    ) -> SymphoniaResult<Song> {
         let path = path.canonicalize()?;
-        let reader = Self::reader(&path)?;
-        let track = reader
+        let mut probed = Self::reader(&path)?;
+        let track = probed
+            .format
             .default_track()
-            .expect("Found mp3 file without a track, abort");
+            .ok_or(Error::Unsupported("file without a track"))?;
         let params = &track.codec_params;
         let time_base = params
             .time_base
-            .expect("Every mp3 track should have a time base");
-        let n_frames = params.n_frames.expect("Every mp3 track should have frames");
+            .ok_or(Error::Unsupported("track without a time base"))?;
+        let n_frames = params
+            .n_frames
+            .ok_or(Error::Unsupported("track without a frame count"))?;
         let duration = time_base.calc_time(n_frames).into();
-        Ok(Self::new(track.id as usize, title, path, duration))
+        let id = track.id as usize;
+
+        // Metadata in the container (Vorbis comments, MP4 atoms) takes precedence over metadata found while probing (ID3v2).
+        let mut tags = Tags::default();
+        let mut embedded_art = None;
+        let mut read_revision = |revision: &MetadataRevision| {
+            tags.add_revision(revision);
+            if embedded_art.is_none() {
+                embedded_art = artwork::front_cover(revision.visuals()).map(|v| v.data.clone());
+            }
+        };
+        if let Some(revision) = probed.format.metadata().current() {
+            read_revision(revision);
+        }
+        if let Some(metadata) = probed.metadata.get() {
+            if let Some(revision) = metadata.current() {
+                read_revision(revision);
+            }
+        }
+        let artwork = artwork_dir
+            .and_then(|dir| artwork::song_thumbnail(embedded_art.as_deref(), &path, dir));
+        let title = tags.title.clone().unwrap_or(title);
+        let chapters = chapters::read(&path).unwrap_or_else(|e| {
+            warn!("Could not read chapters of '{}': {}", path.display(), e);
+            Vec::new()
+        });
+        Ok(Self {
+            tags,
+            artwork,
+            chapters,
+            ..Self::new(id, title, path, duration)
+        })
     }
 
-    // Feels kinda dumb to have to get a reader for duration, and later for actually reading the data
-    /// Try to get an [`MpaReader`] for this song.
+    /// Same as [`from_path`], using the file name without the extension as the fallback title.
     ///
-    /// [`MpaReader`]: symphonia_bundle_mp3::MpaReader
-    fn reader(path: &PathBuf) -> SymphoniaResult<MpaReader> {
+    /// [`from_path`]: Self::from_path
+    pub fn from_file(path: PathBuf, artwork_dir: Option<&Path>) -> SymphoniaResult<Song> {
+        let title = path
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self::from_path(title, path, artwork_dir)
+    }
+
+    // Feels kinda dumb to have to get a reader for duration, and later for actually reading the data
+    /// Try to probe the file at `path` for a format reader, the format is guessed from the extension and contents.
+    pub(crate) fn reader(path: &Path) -> SymphoniaResult<ProbeResult> {
         let file = fs::File::open(path)?;
         let mss = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+            hint.with_extension(ext);
+        }
         let reader_options = FormatOptions {
             enable_gapless: true,
             ..Default::default()
         };
-        MpaReader::try_new(mss, &reader_options)
+        symphonia::default::get_probe().format(
+            &hint,
+            mss,
+            &reader_options,
+            &MetadataOptions::default(),
+        )
     }
 
     /// Try to get a reader and decoder for use in player to get audio samples
-    fn reader_decoder(&self) -> SymphoniaResult<(MpaReader, MpaDecoder)> {
-        let reader = Self::reader(&self.path)?;
+    pub(crate) fn reader_decoder(
+        &self,
+    ) -> SymphoniaResult<(Box<dyn FormatReader>, Box<dyn Decoder>)> {
+        let reader = Self::reader(self.file())?.format;
         let track = reader
             .default_track()
-            .expect("Every mp3 file should have a track");
-        let decoder = MpaDecoder::try_new(&track.codec_params, &Default::default())?;
+            .ok_or(Error::Unsupported("file without a track"))?;
+        let decoder =
+            symphonia::default::get_codecs().make(&track.codec_params, &Default::default())?;
         Ok((reader, decoder))
     }
 }
 
 /// Represents a playlist shown in the UI, playlists are created from the config file.
 ///
+/// The path is either a directory, whose songs are the playlist, or an M3U, PLS or XSPF [`playlist_file`].
+///
 /// This struct doesn't actually hold the `Song`s, instead they should be collected with [`songs`]
 ///
 /// [`songs`]: Self::songs
@@ -140,7 +333,9 @@ impl Song {
 pub struct Playlist {
     name: String,
     path: PathBuf,
-    #[serde(skip)]
+    /// An image shown as the playlist's icon, the album art in the playlist's directory is used if this isn't set.
+    #[serde(rename = "icon")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     icon_path: Option<PathBuf>,
 }
 
@@ -155,11 +350,12 @@ impl Playlist {
         })
     }
 
-    /// Check if the path exists and is a directory
+    /// Check if the path exists and is a directory or a playlist file
     ///
     /// NOTE: this calls [`Path::metadata`] which is a system call.
     pub fn is_valid(&self) -> bool {
-        let exists = self.path.metadata().is_ok_and(|meta| meta.is_dir());
+        let exists = self.path.metadata().is_ok_and(|meta| meta.is_dir())
+            || playlist_file::is_playlist_file(&self.path);
         if !exists {
             warn!(
                 "Playlist '{}' has invalid path: '{}'",
@@ -182,30 +378,218 @@ impl Playlist {
         self.icon_path.as_ref().map(|v| &**v)
     }
 
-    /// Try to gather a vector of `Song` structs from the playlist's path.
-    /// Files are skipped if the entry can't be read, have non-UTF-8 filenames, or the Song struct couldn't be created with `Song::from_path`.
-    pub fn songs(&self) -> std::io::Result<Vec<Song>> {
-        Ok(self
-            .path
-            .read_dir()
+    /// Check if the playlist is a playlist file instead of a directory.
+    pub fn is_file(&self) -> bool {
+        PlaylistFormat::from_path(&self.path).is_some()
+    }
+
+    /// Gets the directory playlists made in the app are saved to (`playlists` in [`Config::default_path`]).
+    ///
+    /// [`Config::default_path`]: crate::config::Config::default_path
+    pub fn user_dir() -> PathBuf {
+        let mut path = crate::config::Config::default_path();
+        path.push("playlists");
+        path
+    }
+
+    /// Create an empty playlist named `name`, saved as an M3U8 file in the [`user_dir`](Self::user_dir).
+    pub fn create(name: String) -> Result<Self, PlaylistFileError> {
+        Self::create_in(&Self::user_dir(), name)
+    }
+
+    fn create_in(dir: &Path, name: String) -> Result<Self, PlaylistFileError> {
+        fs::create_dir_all(dir)?;
+        // Keep the name readable, but without characters that aren't allowed in file names
+        let file_name: String = name
+            .chars()
+            .map(|c| match c {
+                c if c.is_alphanumeric() || " -_".contains(c) => c,
+                _ => '_',
+            })
+            .collect();
+        let file_name = match file_name.trim() {
+            "" => "Playlist",
+            file_name => file_name,
+        };
+        let path = (1..)
+            .map(|n| match n {
+                1 => dir.join(format!("{file_name}.m3u8")),
+                n => dir.join(format!("{file_name} {n}.m3u8")),
+            })
+            .find(|path| !path.exists())
+            .expect("There should always be a free file name");
+        playlist_file::write(&path, &[], &[])?;
+        Ok(Self::new(path, name, None)?)
+    }
+
+    /// Create a playlist named `name` with the songs of this playlist, see [`create`](Self::create).
+    ///
+    /// A directory is turned into a playlist file with the songs that are in it now.
+    pub fn duplicate(&self, name: String, library: &Library) -> Result<Self, PlaylistFileError> {
+        let songs = self.load(library)?;
+        let playlist = Self::create(name)?;
+        playlist.save(&songs)?;
+        Ok(playlist)
+    }
+
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Check if the playlist was made in the app, so its file can be deleted with the playlist.
+    pub fn is_user_playlist(&self) -> bool {
+        self.path.starts_with(Self::user_dir())
+    }
+
+    /// Write `songs` to the playlist's file, replacing the songs that were in it.
+    ///
+    /// Fails with [`PlaylistFileError::UnknownFormat`] if the playlist is a directory.
+    pub fn save(&self, songs: &PlaylistSongs) -> Result<(), PlaylistFileError> {
+        if !self.is_file() {
+            return Err(PlaylistFileError::UnknownFormat(self.path.clone()));
+        }
+        playlist_file::write(&self.path, &songs.songs, &songs.missing)
+    }
+
+    /// Point the entries of the playlist's file that are the song at `old` to `new` instead, keeping their order.
+    ///
+    /// Used when a song's file is renamed or replaced, directories don't list their songs so they're never changed.
+    /// Returns true if the file had the song and was written.
+    pub fn replace_song(&self, old: &Path, new: &Path) -> Result<bool, PlaylistFileError> {
+        if !self.is_file() {
+            return Ok(false);
+        }
+        // The entries' paths aren't canonical, and the old file might not exist anymore
+        let is_old = |path: &Path| {
+            path == old
+                || path
+                    .parent()
+                    .and_then(|dir| dir.canonicalize().ok())
+                    .zip(path.file_name())
+                    .is_some_and(|(dir, name)| dir.join(name) == old)
+        };
+        let mut entries = playlist_file::read(&self.path)?;
+        let mut replaced = false;
+        for entry in entries.iter_mut().filter(|entry| is_old(&entry.path)) {
+            entry.path = new.to_path_buf();
+            replaced = true;
+        }
+        if replaced {
+            playlist_file::write(&self.path, &[], &entries)?;
+        }
+        Ok(replaced)
+    }
+
+    /// Get a thumbnail of the playlist's icon, or of the [`artwork::folder_art`] in its directory if it doesn't have one.
+    ///
+    /// Errors are logged and treated as the playlist having no icon.
+    pub fn artwork(&self) -> Option<PathBuf> {
+        let image = match &self.icon_path {
+            Some(icon_path) => icon_path.clone(),
+            None if self.is_file() => artwork::folder_art(self.path.parent()?)?,
+            None => artwork::folder_art(&self.path)?,
+        };
+        artwork::file_thumbnail(&image, &artwork::cache_dir())
             .inspect_err(|e| {
-                error!(
-                    "Error while getting songs from playlist '{}' at path '{}': {}",
+                warn!(
+                    "Error reading icon of playlist '{}' at '{}': {}",
                     self.name,
-                    self.path.display(),
+                    image.display(),
                     e
                 )
-            })?
-            .filter_map(|f| {
-                let path = f.ok()?.path();
-                if path.extension()? == "mp3" {
-                    let title = path.file_name()?.to_str()?;
-                    return Song::from_path(title.into(), path).ok();
-                }
-                None
             })
-            .collect())
+            .ok()
+    }
+
+    /// Get the songs in the playlist's directory and its subdirectories from `library`, sorted by path,
+    /// or the songs listed in the playlist file in order.
+    ///
+    /// Errors reading the playlist file and missing songs are logged, use [`load`](Self::load) to get them.
+    pub fn songs(&self, library: &Library) -> Vec<Song> {
+        match self.load(library) {
+            Ok(songs) => songs.songs,
+            Err(e) => {
+                error!("Could not read playlist '{}': {}", self.name, e);
+                Vec::new()
+            }
+        }
     }
+
+    /// Get the playlist's songs, see [`songs`](Self::songs), and the entries of the playlist file that don't exist.
+    ///
+    /// Songs in directories are read from `library`, which should be kept up to date with a [`Scanner`].
+    /// Songs in playlist files that aren't in the library are read from their files.
+    ///
+    /// [`Scanner`]: crate::scanner::Scanner
+    pub fn load(&self, library: &Library) -> Result<PlaylistSongs, PlaylistFileError> {
+        if !self.is_file() {
+            return Ok(PlaylistSongs {
+                songs: library.songs_under(&self.path),
+                missing: Vec::new(),
+            });
+        }
+        let mut songs = PlaylistSongs::default();
+        for entry in playlist_file::read(&self.path)? {
+            // The library is keyed by canonical paths, and this checks that the file exists
+            let Ok(path) = cue::canonicalize(&entry.path) else {
+                songs.missing.push(entry);
+                continue;
+            };
+            if let Some(song) = library.get(&path) {
+                songs.songs.push(song.clone());
+                continue;
+            }
+            // A cue sheet is all of its tracks, a track of one is read from the sheet
+            if cue::is_cue_sheet(&path) || cue::sheet_of(&path).is_some() {
+                let sheet = cue::source_path(&path);
+                let sheet_songs = library
+                    .cached_sheet(sheet)
+                    .map_or_else(|| cue::read_songs(sheet, library.artwork_dir()), Ok);
+                match sheet_songs {
+                    Ok(sheet_songs) if sheet == path => songs.songs.extend(sheet_songs),
+                    Ok(sheet_songs) => {
+                        match sheet_songs.into_iter().find(|song| song.path() == path) {
+                            Some(song) => songs.songs.push(song),
+                            None => songs.missing.push(entry),
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Could not read '{}': {}", entry.path.display(), e);
+                        songs.missing.push(entry);
+                    }
+                }
+                continue;
+            }
+            let title = entry.title.clone().unwrap_or_else(|| {
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            });
+            match Song::from_path(title, path, library.artwork_dir()) {
+                Ok(song) => songs.songs.push(song),
+                Err(e) => {
+                    warn!("Could not read '{}': {}", entry.path.display(), e);
+                    songs.missing.push(entry);
+                }
+            }
+        }
+        if !songs.missing.is_empty() {
+            warn!(
+                "{} songs in playlist '{}' are missing",
+                songs.missing.len(),
+                self.name
+            );
+        }
+        Ok(songs)
+    }
+}
+
+/// The songs of a [`Playlist`], returned by [`Playlist::load`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlaylistSongs {
+    pub songs: Vec<Song>,
+    /// The entries of the playlist file that don't exist or can't be read.
+    pub missing: Vec<PlaylistEntry>,
 }
 
 /// Possible states the Player can be in.
@@ -360,7 +744,22 @@ pub enum PlayerUpdate {
     /// The song has been changed.
     ///
     /// If Some, the first element is the index in the `Queue`, and the second the `Song` itself. If None, the player is or has exited.
-    SongChange { song_info: Option<(usize, Song)> },
+    SongChange {
+        song_info: Option<(usize, Box<Song>)>,
+    },
+    /// A song that `started` playing at that time stopped at `position`, after it was listened to for `played`.
+    ///
+    /// `played` doesn't count the parts that were skipped by seeking, or time spent paused.
+    /// `finished` is false if it was stopped early, e.g. by [`Player::fast_forward`], or if the player quit.
+    SongEnd {
+        song: Box<Song>,
+        started: SystemTime,
+        position: Duration,
+        played: Duration,
+        finished: bool,
+    },
+    /// The `Queue` was changed through [`Player::edit_queue`], or a method using it.
+    QueueChange,
     /// The device was disconnected.
     DeviceDisconnect,
     // DeviceChange(),
@@ -372,7 +771,34 @@ impl PlayerUpdate {
     ///
     /// [`SongChange`]: Self::SongChange
     fn song_change(song_info: Option<(usize, Song)>) -> Self {
-        Self::SongChange { song_info }
+        Self::SongChange {
+            song_info: song_info.map(|(index, song)| (index, Box::new(song))),
+        }
+    }
+}
+
+/// A named queue that's not being played, stored by a [`Player`].
+///
+/// See [`Player::switch_queue`].
+#[derive(Clone, Debug)]
+pub struct PlayContext {
+    name: String,
+    queue: Queue<Song>,
+    /// How far into the current song playback was when the queue was switched away from.
+    position: Duration,
+}
+
+impl PlayContext {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn queue(&self) -> &Queue<Song> {
+        &self.queue
+    }
+
+    pub fn position(&self) -> &Duration {
+        &self.position
     }
 }
 
@@ -388,10 +814,24 @@ pub struct Player {
     state: Arc<Mutex<PlayerState>>,
     /// None if the player hasn't started yes, the player's state is `PlayerState::NotStarted` in this case
     sender: Option<mpsc::Sender<PlayerMessage>>,
+    /// None if the player hasn't started yet, used to send updates from outside the decoder thread
+    update_sender: Option<mpsc::Sender<PlayerUpdate>>,
     time_playing: Arc<AtomicMilliseconds>,
     volume: Arc<AtomicVolume>,
     /// If a song has been playing longer than this duration, only rewind to the beginning of it
     rewind_threshold: Duration,
+    /// The queue's songs from before each edit made with `edit_queue`
+    queue_history: Arc<Mutex<UndoStack<Vec<Song>>>>,
+    /// The name of the queue being played
+    queue_name: Arc<Mutex<String>>,
+    /// The named queues that aren't being played
+    contexts: Arc<Mutex<Vec<PlayContext>>>,
+    /// Where to seek to in the first song after the player is started
+    start_position: Option<Duration>,
+    /// If set, used to add songs to the queue when it runs out
+    autoplay: Arc<Mutex<Option<Box<dyn SimilaritySource>>>>,
+    /// The last `RECENTLY_PLAYED_LEN` songs that were started, oldest first
+    recently_played: Arc<Mutex<VecDeque<Song>>>,
 }
 
 // TODO: turn into builder pattern
@@ -402,21 +842,376 @@ impl Player {
             queue: Mutex::new(Queue::new(RepeatMode::All)).into(),
             state: Mutex::new(PlayerState::NotStarted).into(),
             sender: None,
+            update_sender: None,
             time_playing: AtomicMilliseconds::default().into(),
             volume: AtomicVolume::from_percent(volume).into(),
             rewind_threshold: Duration::from_secs(3),
+            queue_history: Mutex::new(UndoStack::new(100)).into(),
+            queue_name: Mutex::new("Default".to_string()).into(),
+            contexts: Mutex::new(Vec::new()).into(),
+            start_position: None,
+            autoplay: Mutex::new(None).into(),
+            recently_played: Mutex::new(VecDeque::new()).into(),
         }
     }
 
+    /// Set the source of songs for autoplay, or disable it with None.
+    ///
+    /// With autoplay enabled, songs similar to the last one in the queue are added to it when it runs out,
+    /// instead of finishing playback. Recently played songs and songs already in the queue are not added.
+    pub fn set_autoplay(&mut self, source: Option<Box<dyn SimilaritySource>>) {
+        *self.autoplay.lock().unwrap() = source;
+    }
+
+    /// Return true if autoplay has a source set, see [`set_autoplay`].
+    ///
+    /// [`set_autoplay`]: Self::set_autoplay
+    pub fn is_autoplay_enabled(&self) -> bool {
+        self.autoplay.lock().unwrap().is_some()
+    }
+
+    /// Return clones of the most recently started songs, oldest first.
+    pub fn recently_played(&self) -> Vec<Song> {
+        self.recently_played
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// The name of the queue being played.
+    pub fn queue_name(&self) -> String {
+        self.queue_name.lock().unwrap().clone()
+    }
+
+    /// The names of every queue, including the one being played, sorted alphabetically.
+    pub fn queue_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .contexts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|context| context.name.clone())
+            .collect();
+        names.push(self.queue_name());
+        names.sort();
+        names
+    }
+
+    /// Return clones of the queues that aren't being played.
+    pub fn contexts(&self) -> Vec<PlayContext> {
+        self.contexts.lock().unwrap().clone()
+    }
+
+    /// Create a new empty queue named `name`, with the same repeat mode as the one being played.
+    pub fn create_queue(&mut self, name: String) -> Result<(), PlayContextError> {
+        if self.queue_names().contains(&name) {
+            return Err(PlayContextError::AlreadyExists(name));
+        }
+        let repeat_mode = self.queue.lock().unwrap().repeat_mode;
+        self.contexts.lock().unwrap().push(PlayContext {
+            name,
+            queue: Queue::new(repeat_mode),
+            position: Duration::ZERO,
+        });
+        Ok(())
+    }
+
+    /// Remove the queue named `name`, returning it.
+    ///
+    /// The queue being played can't be removed, switch to another one first.
+    pub fn remove_queue(&mut self, name: &str) -> Result<PlayContext, PlayContextError> {
+        if self.queue_name() == name {
+            return Err(PlayContextError::Active(name.into()));
+        }
+        let mut contexts = self.contexts.lock().unwrap();
+        let position = contexts
+            .iter()
+            .position(|context| context.name == name)
+            .ok_or_else(|| PlayContextError::NotFound(name.into()))?;
+        Ok(contexts.remove(position))
+    }
+
+    /// Play the queue named `name`, storing the current one with its position so it can be switched back to.
+    ///
+    /// Playback continues from the current song of the new queue, at the position it was at when it was switched away from,
+    /// with the queue's own repeat mode. Switching to an empty queue finishes playback.
+    ///
+    /// The queue edit history is cleared, as it belongs to the previous queue.
+    pub fn switch_queue(&mut self, name: &str) -> Result<(), PlayContextError> {
+        let context = {
+            let mut contexts = self.contexts.lock().unwrap();
+            let position = contexts
+                .iter()
+                .position(|context| context.name == name)
+                .ok_or_else(|| PlayContextError::NotFound(name.into()))?;
+            contexts.remove(position)
+        };
+        let PlayContext {
+            name,
+            mut queue,
+            position,
+        } = context;
+        // Guarantee the new queue's current song is played next
+        queue
+            .jump(queue.index())
+            .expect("A queue's own index should always be a valid jump");
+        let current_position = self.time_playing().into();
+        let old_queue = std::mem::replace(&mut *self.queue.lock().unwrap(), queue);
+        let old_name = std::mem::replace(&mut *self.queue_name.lock().unwrap(), name);
+        self.contexts.lock().unwrap().push(PlayContext {
+            name: old_name,
+            queue: old_queue,
+            position: current_position,
+        });
+        self.queue_history.lock().unwrap().clear();
+        if self.is_active() {
+            self.stop();
+            if !position.is_zero() {
+                self.send_message(PlayerMessage::Seek(position));
+            }
+        } else {
+            self.start_position = Some(position);
+        }
+        self.send_update(PlayerUpdate::QueueChange);
+        Ok(())
+    }
+
+    /// Save every queue and the position in their current songs, so they can be restored with [`restore_session`].
+    ///
+    /// [`restore_session`]: Self::restore_session
+    pub fn session(&self) -> Session {
+        let saved = |name: &str, queue: &Queue<Song>, position: &Duration| SavedQueue {
+            name: name.to_string(),
+            queue: queue.clone().map(|song| song.path),
+            position_millis: position.as_millis() as u64,
+        };
+        let mut queues = vec![saved(
+            &self.queue_name(),
+            &self.queue.lock().unwrap(),
+            &self.time_playing().into(),
+        )];
+        for context in self.contexts.lock().unwrap().iter() {
+            queues.push(saved(&context.name, &context.queue, &context.position));
+        }
+        Session {
+            active_queue: self.queue_name(),
+            queues,
+        }
+    }
+
+    /// Replace every queue with the ones saved in `session`, see [`session`].
+    ///
+    /// Songs are read again, caching their album art in `artwork_dir`, and the ones that can't be read anymore are skipped.
+    /// This should be called before the player is started with [`run`], which then continues from where the active queue was saved.
+    ///
+    /// [`session`]: Self::session
+    /// [`run`]: Self::run
+    pub fn restore_session(&mut self, session: Session, artwork_dir: Option<&Path>) {
+        let mut contexts: Vec<PlayContext> = session
+            .queues
+            .into_iter()
+            .map(|saved| {
+                let mut queue = saved.queue.map(|path| {
+                    Song::from_file(path.clone(), artwork_dir)
+                        .inspect_err(|e| warn!("Skipping saved song '{}': {}", path.display(), e))
+                        .ok()
+                });
+                queue.retain(Option::is_some);
+                let mut queue = queue.map(|song| song.expect("Songs that failed were removed"));
+                // Guarantee the saved current song is played next
+                queue
+                    .jump(queue.index())
+                    .expect("A queue's own index should always be a valid jump");
+                PlayContext {
+                    name: saved.name,
+                    queue,
+                    position: Duration::from_millis(saved.position_millis),
+                }
+            })
+            .collect();
+        let active = match contexts
+            .iter()
+            .position(|context| context.name == session.active_queue)
+        {
+            Some(active) => contexts.remove(active),
+            None => {
+                warn!("Session has no queue named '{}'", session.active_queue);
+                // Keep the other queues, and play the first one or an empty one instead
+                match contexts.is_empty() {
+                    false => contexts.remove(0),
+                    true => PlayContext {
+                        name: session.active_queue,
+                        queue: Queue::new(self.queue.lock().unwrap().repeat_mode),
+                        position: Duration::ZERO,
+                    },
+                }
+            }
+        };
+        *self.queue.lock().unwrap() = active.queue;
+        *self.queue_name.lock().unwrap() = active.name;
+        *self.contexts.lock().unwrap() = contexts;
+        self.queue_history.lock().unwrap().clear();
+        self.start_position = Some(active.position);
+        self.send_update(PlayerUpdate::QueueChange);
+    }
+
     /// Return a MutexGuard for the Player's `Queue`.
     ///
     /// Avoid any other methods that lock the queue until this Guard is dropped or it will result in a deadlock
     ///
+    /// Changes made through this guard don't send a [`PlayerUpdate::QueueChange`], use [`edit_queue`] for that.
+    ///
     /// [`Queue`]: Queue
+    /// [`edit_queue`]: Self::edit_queue
     pub fn queue_mut(&mut self) -> MutexGuard<Queue<Song>> {
         self.queue.lock().unwrap()
     }
 
+    /// Return a MutexGuard for reading the Player's `Queue`.
+    ///
+    /// Avoid any other methods that lock the queue until this Guard is dropped or it will result in a deadlock
+    pub fn queue(&self) -> MutexGuard<'_, Queue<Song>> {
+        self.queue.lock().unwrap()
+    }
+
+    /// Lock the queue and call `f` with it, then send a [`PlayerUpdate::QueueChange`] if the player is running.
+    ///
+    /// If `f` changed the queue's songs, the edit can be reverted with [`undo_queue`].
+    ///
+    /// Returns the value returned by `f`.
+    ///
+    /// [`undo_queue`]: Self::undo_queue
+    pub fn edit_queue<R>(&mut self, f: impl FnOnce(&mut Queue<Song>) -> R) -> R {
+        let ret = {
+            let mut queue_lock = self.queue.lock().unwrap();
+            let previous = queue_lock.items().to_vec();
+            let ret = f(&mut queue_lock);
+            if previous != queue_lock.items() {
+                self.queue_history.lock().unwrap().push(previous);
+            }
+            ret
+        };
+        self.send_update(PlayerUpdate::QueueChange);
+        ret
+    }
+
+    /// Revert the last edit made with [`edit_queue`].
+    ///
+    /// The current song keeps playing, and stays the current song if it's in the restored queue.
+    /// Returns false if there was nothing to undo.
+    ///
+    /// [`edit_queue`]: Self::edit_queue
+    pub fn undo_queue(&mut self) -> bool {
+        self.restore_queue(UndoStack::undo)
+    }
+
+    /// Restore the last edit reverted with [`undo_queue`].
+    ///
+    /// Returns false if there was nothing to redo.
+    ///
+    /// [`undo_queue`]: Self::undo_queue
+    pub fn redo_queue(&mut self) -> bool {
+        self.restore_queue(UndoStack::redo)
+    }
+
+    /// Shared implementation of [`undo_queue`] and [`redo_queue`].
+    ///
+    /// [`undo_queue`]: Self::undo_queue
+    /// [`redo_queue`]: Self::redo_queue
+    fn restore_queue(
+        &mut self,
+        restore: impl FnOnce(&mut UndoStack<Vec<Song>>, Vec<Song>) -> Option<Vec<Song>>,
+    ) -> bool {
+        {
+            let mut queue_lock = self.queue.lock().unwrap();
+            let current = queue_lock.items().to_vec();
+            let Some(songs) = restore(&mut self.queue_history.lock().unwrap(), current) else {
+                return false;
+            };
+            queue_lock.replace_items(songs);
+        }
+        self.send_update(PlayerUpdate::QueueChange);
+        true
+    }
+
+    /// Set how many queue edits can be undone with [`undo_queue`].
+    ///
+    /// [`undo_queue`]: Self::undo_queue
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.queue_history.lock().unwrap().set_depth(depth);
+    }
+
+    /// Send an update to the receiver returned by [`run`], if the player was started.
+    ///
+    /// [`run`]: Self::run
+    fn send_update(&self, update: PlayerUpdate) {
+        if let Some(tx) = &self.update_sender {
+            let _ = tx.send(update);
+        }
+    }
+
+    /// Move the song at `from` to `to` in the queue, see [`Queue::move_item`].
+    pub fn move_song(&mut self, from: usize, to: usize) -> Result<(), OutOfBoundsError<usize>> {
+        self.edit_queue(|queue| queue.move_item(from, to))
+    }
+
+    /// Remove the song at `index` from the queue.
+    ///
+    /// If it is the song that's currently playing, it keeps playing until it ends or is stopped.
+    pub fn remove_song(&mut self, index: usize) -> Result<Song, OutOfBoundsError<usize>> {
+        self.edit_queue(|queue| queue.remove_range(index..index + 1))
+            .map(|mut removed| removed.remove(0))
+    }
+
+    /// Remove the songs at or under `path` from the queue, for when their files were deleted.
+    ///
+    /// This isn't recorded in the undo history like [`edit_queue`] is, the songs can't be played anymore.
+    /// If the current song is removed, it keeps playing until it ends or is stopped.
+    /// Returns how many songs were removed.
+    ///
+    /// [`edit_queue`]: Self::edit_queue
+    pub fn remove_missing(&mut self, path: &Path) -> usize {
+        let removed = {
+            let mut queue_lock = self.queue.lock().unwrap();
+            let before = queue_lock.items().len();
+            queue_lock.retain(|song| !song.path().starts_with(path));
+            before - queue_lock.items().len()
+        };
+        if removed > 0 {
+            self.send_update(PlayerUpdate::QueueChange);
+        }
+        removed
+    }
+
+    /// Replace the songs at `old_path` in the queue and the other named queues with `song`, for when its tags were edited or it was renamed.
+    ///
+    /// Like [`remove_missing`], this isn't recorded in the undo history.
+    /// Returns how many songs were replaced.
+    ///
+    /// [`remove_missing`]: Self::remove_missing
+    pub fn update_song(&mut self, old_path: &Path, song: &Song) -> usize {
+        let replace = |queue: &mut Queue<Song>| {
+            let mut replaced = 0;
+            for item in queue.items_mut() {
+                if item.path() == old_path {
+                    *item = song.clone();
+                    replaced += 1;
+                }
+            }
+            replaced
+        };
+        let replaced = replace(&mut self.queue.lock().unwrap());
+        for context in self.contexts.lock().unwrap().iter_mut() {
+            replace(&mut context.queue);
+        }
+        if replaced > 0 {
+            self.send_update(PlayerUpdate::QueueChange);
+        }
+        replaced
+    }
+
     /// Set the player's volume.
     pub fn set_volume(&mut self, volume: &AtomicVolume) {
         self.volume.set(volume);
@@ -517,9 +1312,26 @@ impl Player {
     /// [`queue`]: crate::queue::Queue
     /// [`stop`]: Self::stop
     pub fn set_songs(&mut self, songs: Vec<Song>) {
-        let mut queue_lock = self.queue.lock().unwrap();
-        queue_lock.clear();
-        queue_lock.extend(songs.into_iter());
+        self.edit_queue(|queue| {
+            queue.clear();
+            queue.extend(songs);
+        });
+    }
+
+    /// Randomly reorder the songs after the current one, songs with a higher `weight` are more likely to come first.
+    ///
+    /// See [`Queue::shuffle_weighted`], this is a queue edit like [`shuffle`](Self::shuffle).
+    pub fn shuffle_weighted(&mut self, weight: impl Fn(&Song) -> f64) {
+        self.edit_queue(|queue| queue.shuffle_weighted(&mut rand::thread_rng(), weight));
+    }
+
+    /// Randomly reorder the songs after the current one, see [`Queue::shuffle`].
+    ///
+    /// This is a queue edit, so it can be reverted with [`undo_queue`].
+    ///
+    /// [`undo_queue`]: Self::undo_queue
+    pub fn shuffle(&mut self) {
+        self.edit_queue(|queue| queue.shuffle(&mut rand::thread_rng()));
     }
 
     /// Shortcut for changing the repeat mode of the [`queue`].
@@ -553,11 +1365,17 @@ impl Player {
         let player_state = self.state.clone();
         let time_playing = self.time_playing.clone();
         let volume = self.volume.clone();
+        let autoplay = self.autoplay.clone();
+        let recently_played = self.recently_played.clone();
 
         let (control_tx, control_rx) = mpsc::channel::<PlayerMessage>();
         self.sender = Some(control_tx.clone());
+        if let Some(position) = self.start_position.take().filter(|p| !p.is_zero()) {
+            let _ = control_tx.send(PlayerMessage::Seek(position));
+        }
 
         let (player_update_tx, player_update_rx) = mpsc::channel::<PlayerUpdate>();
+        self.update_sender = Some(player_update_tx.clone());
 
         // DECODER THREAD
         thread::spawn(move || {
@@ -572,11 +1390,23 @@ impl Player {
                     .inspect_err(|e| error!("Error setting up stream: {}", e))
                     .unwrap();
             stream.play().unwrap();
+            // Songs that couldn't be opened in a row, so a queue of only missing files isn't repeated forever
+            let mut failed_songs = 0;
+            // The file of the last track of a cue sheet, if it played to its end
+            let mut open_file: Option<OpenFile> = None;
             'main_loop: loop {
                 // Get a song and its reader and decoder, if the song is empty we break out of the main_loop.
                 let song = {
                     let mut queue_lock = queue.lock().unwrap();
-                    let next_song = queue_lock.next_item().cloned();
+                    let mut next_song = queue_lock.next_item().cloned();
+                    if next_song.is_none() {
+                        let recent = recently_played.lock().unwrap();
+                        next_song =
+                            autoplay_next(&mut queue_lock, &mut autoplay.lock().unwrap(), &recent);
+                        if next_song.is_some() {
+                            let _ = player_update_tx.send(PlayerUpdate::QueueChange);
+                        }
+                    }
                     let index = queue_lock.index();
                     let song_info = Some(index).zip(next_song.clone());
                     let _ = player_update_tx.send(PlayerUpdate::song_change(song_info));
@@ -588,12 +1418,59 @@ impl Player {
                         song.title(),
                         song.path().display()
                     );
+                    let mut recent = recently_played.lock().unwrap();
+                    if recent.len() == RECENTLY_PLAYED_LEN {
+                        recent.pop_front();
+                    }
+                    recent.push_back(song.clone());
                     song
                 };
-                let (mut reader, mut decoder) = song.reader_decoder().unwrap();
+                // The next track of a cue sheet continues where the last one ended
+                let open = open_file
+                    .take()
+                    .filter(|open| open.file == song.file() && open.end == song.start());
+                let (mut reader, mut decoder, carried) = match open {
+                    Some(open) => (open.reader, open.decoder, open.samples),
+                    None => {
+                        let Ok((reader, decoder)) = song.reader_decoder().inspect_err(|e| {
+                            error!("Skipping song '{}': {}", song.path().display(), e)
+                        }) else {
+                            failed_songs += 1;
+                            if failed_songs > queue.lock().unwrap().items().len() {
+                                break 'main_loop;
+                            }
+                            continue;
+                        };
+                        (reader, decoder, Vec::new())
+                    }
+                };
+                failed_songs = 0;
                 let track = reader.default_track().unwrap();
                 let track_id = track.id;
                 let time_base = track.codec_params.time_base.unwrap();
+                let song_sample_rate = track.codec_params.sample_rate.unwrap();
+                // Tracks of cue sheets only play a part of the file, samples outside of it are dropped
+                let start = song.start();
+                let start_ts = time_base.calc_timestamp(start.into());
+                let end_ts = song.end().map(|end| time_base.calc_timestamp(end.into()));
+                if carried.is_empty() && !start.is_zero() {
+                    use symphonia::core::formats::{SeekMode, SeekTo};
+                    let seeked = reader.seek(
+                        SeekMode::Accurate,
+                        SeekTo::TimeStamp {
+                            ts: start_ts,
+                            track_id,
+                        },
+                    );
+                    if let Err(e) = seeked {
+                        warn!(
+                            "Could not seek to the start of '{}': {}",
+                            song.path().display(),
+                            e
+                        );
+                    }
+                    decoder.reset();
+                }
                 // Reset the time_playing to 0 because we're playing a new song. Set the state to Playing
                 time_playing.set_millis(0);
                 {
@@ -602,7 +1479,6 @@ impl Player {
                 }
 
                 // If the song's sample rate is different from the previous one, update the cpal thread's song sample rate.
-                let song_sample_rate = track.codec_params.sample_rate.unwrap();
                 if last_song_sample_rate != song_sample_rate {
                     sample_rate_update_input.write(song_sample_rate);
                     last_song_sample_rate = song_sample_rate;
@@ -621,8 +1497,16 @@ impl Player {
                 // 4. If the `sample_deque` buffer is not empty, write samples to the producer while it has space.
                 //    If it is empty, decode an audio packet and write the samples to the producer. The leftover samples are written to the deque.
                 // 5. Sleep so the CPU doesn't burn.
-                let mut sample_deque = VecDeque::new();
+                let mut sample_deque = VecDeque::from(carried);
+                // The samples after the end of a cue sheet track, once it's been decoded
+                let mut past_end: Option<Vec<[SampleType; 2]>> = None;
                 let mut playing = true;
+                let mut finished = true;
+                let started = SystemTime::now();
+                // How long the song was listened to, counted as playback moves between packets
+                let mut played = Duration::ZERO;
+                // Where the last packet was, None right after a seek so the skipped part isn't counted
+                let mut last_position = Some(Duration::ZERO);
                 'song_loop: loop {
                     match stream_error_rx.try_recv() {
                         // Currently we recreate the device and audio stream for any error, but I'm not sure if that's stupid
@@ -643,13 +1527,36 @@ impl Player {
                             let _ = player_update_tx.send(PlayerUpdate::DeviceDisconnect);
                         }
                         // This means the stream died, should probably send that through the player update channel
-                        Err(mpsc::TryRecvError::Disconnected) => break 'main_loop,
+                        Err(mpsc::TryRecvError::Disconnected) => {
+                            send_song_end(
+                                &player_update_tx,
+                                &song,
+                                started,
+                                &time_playing,
+                                played,
+                                false,
+                            );
+                            break 'main_loop;
+                        }
                         _ => (),
                     }
                     for message in control_rx.try_iter() {
                         match message {
-                            PlayerMessage::Quit => break 'main_loop,
-                            PlayerMessage::Stop => break 'song_loop,
+                            PlayerMessage::Quit => {
+                                send_song_end(
+                                    &player_update_tx,
+                                    &song,
+                                    started,
+                                    &time_playing,
+                                    played,
+                                    false,
+                                );
+                                break 'main_loop;
+                            }
+                            PlayerMessage::Stop => {
+                                finished = false;
+                                break 'song_loop;
+                            }
                             PlayerMessage::Pause => {
                                 let mut state_lock = player_state.lock().unwrap();
                                 if *state_lock != PlayerState::Paused {
@@ -676,7 +1583,7 @@ impl Player {
                             }
                             PlayerMessage::Seek(dur) => {
                                 use symphonia::core::formats::{SeekMode, SeekTo};
-                                let time: units::Time = dur.into();
+                                let time: units::Time = (start + dur).into();
                                 // FormatReader is seekable depending on the MediaSourceStream.is_seekable() method
                                 // I'm fairly certain this should always be true for mp3 files
                                 // TODO: The bool `seekable` should be used to check if we can seek, I don't know how to handle that yet
@@ -690,15 +1597,17 @@ impl Player {
                                     Ok(seeked_to) => {
                                         let duration: Duration =
                                             time_base.calc_time(seeked_to.actual_ts).into();
-                                        duration.as_millis() as u64
+                                        duration.saturating_sub(start).as_millis() as u64
                                     }
                                     Err(e) => match e {
                                         // IoError from seeking (I think) only happens when the format reader reaches EOF, at which point we can skip to the next song
-                                        Error::IoError(_) => continue 'main_loop,
+                                        Error::IoError(_) => break 'song_loop,
                                         e => panic!("{}", e),
                                     },
                                 };
                                 time_playing.set_millis(millis);
+                                last_position = None;
+                                past_end = None;
                                 // Reset the decoder after seeking, the docs say this is a necessary step after seeking
                                 decoder.reset();
                             }
@@ -719,18 +1628,52 @@ impl Player {
                         }
                     }
                     if sample_deque.is_empty() {
+                        if let Some(samples) = past_end.take() {
+                            open_file = Some(OpenFile {
+                                file: song.file().to_path_buf(),
+                                end: song.end().unwrap_or_default(),
+                                reader,
+                                decoder,
+                                samples,
+                            });
+                            break 'song_loop;
+                        }
                         // FIXME: Err(ResetRequired) can be handled gracefully
                         let Ok(packet) = reader.next_packet() else {
                             break 'song_loop;
                         };
+                        if packet.track_id() != track_id {
+                            continue;
+                        }
                         let audio_buf_ref = decoder.decode(&packet).unwrap();
                         let mut audio_buf = audio_buf_ref.make_equivalent();
                         audio_buf_ref.convert(&mut audio_buf);
-                        let mut sample_iter = audio_buf
+                        // Mono songs play the same samples on both channels
+                        let right = if audio_buf.spec().channels.count() > 1 {
+                            1
+                        } else {
+                            0
+                        };
+                        let mut samples: Vec<[SampleType; 2]> = audio_buf
                             .chan(0)
                             .iter()
-                            .zip(audio_buf.chan(1))
-                            .map(|t| [*t.0, *t.1]);
+                            .zip(audio_buf.chan(right))
+                            .map(|t| [*t.0, *t.1])
+                            .collect();
+                        let frames = |ts: u64| {
+                            ts_to_frames(
+                                ts.saturating_sub(packet.ts()),
+                                time_base,
+                                last_song_sample_rate,
+                            )
+                            .min(samples.len())
+                        };
+                        let skip = frames(start_ts);
+                        if let Some(end_ts) = end_ts.filter(|end| *end < packet.ts() + packet.dur())
+                        {
+                            past_end = Some(samples.split_off(frames(end_ts).max(skip)));
+                        }
+                        let mut sample_iter = samples.into_iter().skip(skip);
                         while producer.vacant_len() > 2 {
                             let Some(pair) = sample_iter.next() else {
                                 break;
@@ -739,13 +1682,26 @@ impl Player {
                         }
                         sample_deque.extend(sample_iter);
                         let dur: Duration = time_base.calc_time(packet.ts()).into();
-                        time_playing.set_millis(dur.as_millis() as u64);
+                        let position = dur.saturating_sub(start);
+                        if let Some(last_position) = last_position {
+                            played += position.saturating_sub(last_position);
+                        }
+                        last_position = Some(position);
+                        time_playing.set_millis(position.as_millis() as u64);
                     }
 
                     // This sleep ensures the loop doesn't run too fast to kill the CPU.
                     // It is also part of the reason low ring buffer sizes cause jittery audio.
                     std::thread::sleep(Duration::from_millis(1));
                 }
+                send_song_end(
+                    &player_update_tx,
+                    &song,
+                    started,
+                    &time_playing,
+                    played,
+                    finished,
+                );
             }
             {
                 let mut state_lock = player_state.lock().unwrap();
@@ -785,6 +1741,43 @@ impl Player {
         }
     }
 
+    /// Seek to the start of the current song's next chapter.
+    ///
+    /// Returns false if there is no chapter after the current position.
+    pub fn next_chapter(&mut self) -> bool {
+        let Some(song) = self.current() else {
+            return false;
+        };
+        let position: Duration = self.time_playing().into();
+        let next = chapters::chapter_at(song.chapters(), position).map_or(0, |i| i + 1);
+        match song.chapters().get(next) {
+            Some(chapter) => self.seek(chapter.start).is_ok_and(|sent| sent),
+            None => false,
+        }
+    }
+
+    /// Seek to the start of the current chapter if it has been playing long enough (`Player.rewind_threshold`),
+    /// otherwise to the start of the previous chapter.
+    ///
+    /// Returns false if the current song has no chapters.
+    pub fn previous_chapter(&mut self) -> bool {
+        let Some(song) = self.current() else {
+            return false;
+        };
+        let position: Duration = self.time_playing().into();
+        let Some(current) = chapters::chapter_at(song.chapters(), position) else {
+            return !song.chapters().is_empty() && self.seek(Duration::ZERO).is_ok_and(|sent| sent);
+        };
+        let into_chapter = position - song.chapters()[current].start;
+        let chapter = if into_chapter > self.rewind_threshold {
+            current
+        } else {
+            current.saturating_sub(1)
+        };
+        self.seek(song.chapters()[chapter].start)
+            .is_ok_and(|sent| sent)
+    }
+
     /// Is the player active (`Paused` or `Playing`).
     ///
     /// [`Paused`]: PlayerState::Paused
@@ -802,6 +1795,53 @@ impl Drop for Player {
     }
 }
 
+/// The audio file of a track of a cue sheet that played to its end, kept open for the next track in the file.
+///
+/// Continuing with the same reader and decoder, instead of opening and seeking the file again, makes the tracks gapless.
+struct OpenFile {
+    file: PathBuf,
+    /// Where the track that played ended, and so where the next track has to start.
+    end: Duration,
+    reader: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    /// Samples decoded past the end of the track, which belong to the next one.
+    samples: Vec<[SampleType; 2]>,
+}
+
+/// The number of audio frames in `ts`, a timestamp in `time_base` units.
+fn ts_to_frames(ts: u64, time_base: units::TimeBase, sample_rate: u32) -> usize {
+    (ts as u128 * time_base.numer as u128 * sample_rate as u128 / time_base.denom as u128) as usize
+}
+
+/// Add songs from the autoplay `source` to the queue after it ran out, and return the next song.
+///
+/// The songs are similar to the last song in the queue, or the last played song if the queue is empty.
+/// Returns None if autoplay is disabled or the source had no songs to add.
+fn autoplay_next(
+    queue: &mut Queue<Song>,
+    source: &mut Option<Box<dyn SimilaritySource>>,
+    recently_played: &VecDeque<Song>,
+) -> Option<Song> {
+    let source = source.as_mut()?;
+    let seed = queue.items().last().or(recently_played.back())?.clone();
+    let exclude = |song: &Song| {
+        recently_played.iter().any(|s| s.path == song.path)
+            || queue.items().iter().any(|s| s.path == song.path)
+    };
+    let songs = source.similar_songs(&seed, &exclude, AUTOPLAY_BATCH_SIZE);
+    if songs.is_empty() {
+        return None;
+    }
+    debug!("Autoplay adding {} songs", songs.len());
+    let first_new = queue.items().len();
+    queue.extend(songs);
+    // The queue's index may have moved past the end, make sure the first added song is next
+    queue
+        .jump(first_new)
+        .expect("Jumping to the first added song shouldn't fail");
+    queue.next_item().cloned()
+}
+
 fn init_cpal() -> Option<(cpal::Device, cpal::SupportedStreamConfig)> {
     let device = cpal::default_host().default_output_device();
     let stream_config = device.clone()?.default_output_config().ok();
@@ -844,8 +1884,6 @@ fn create_stream<T>(
 where
     T: SizedSample + cpal::FromSample<SampleType>,
 {
-    // FIXME: This could possibly break if the mp3 file is mono.
-    // This can probably be fixed by pushing the same sample twice in the decoder thread if it is.
     let channel_factor = stream_config.channels / 2;
 
     let sample_rate_in = *sample_rate_update.read() as usize;
@@ -917,6 +1955,24 @@ where
     device.build_output_stream(stream_config, callback, err_fn, None)
 }
 
+/// Tell the receiver of the player's updates that `song` stopped, see [`PlayerUpdate::SongEnd`].
+fn send_song_end(
+    player_update_tx: &mpsc::Sender<PlayerUpdate>,
+    song: &Song,
+    started: SystemTime,
+    time_playing: &AtomicMilliseconds,
+    played: Duration,
+    finished: bool,
+) {
+    let _ = player_update_tx.send(PlayerUpdate::SongEnd {
+        song: Box::new(song.clone()),
+        started,
+        position: time_playing.into(),
+        played,
+        finished,
+    });
+}
+
 // TODO: improve this so changing arguments doesn't require adding extra parameters
 macro_rules! impl_create_stream {
     (
@@ -997,3 +2053,206 @@ fn stream_setup(
     )?;
     Ok((stream, stream_rx, producer))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_playlist_load() {
+        let dir = std::env::temp_dir().join("amuseing-test-playlist-file");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        // Not real audio, so it can only be read from the library
+        fs::write(dir.join("a.mp3"), b"not audio").unwrap();
+        let song_path = dir.join("a.mp3").canonicalize().unwrap();
+        let mut library = Library::default();
+        library
+            .insert(Song::new(0, "A".into(), song_path, Duration::from_secs(1)))
+            .unwrap();
+        fs::write(
+            dir.join("list.m3u8"),
+            "#EXTM3U\na.mp3\n#EXTINF:5,Gone\ngone.mp3\n",
+        )
+        .unwrap();
+
+        let playlist = Playlist::new(dir.join("list.m3u8"), "List".into(), None).unwrap();
+        assert!(playlist.is_file() && playlist.is_valid());
+        let songs = playlist.load(&library).unwrap();
+        assert_eq!(songs.songs.len(), 1);
+        assert_eq!(songs.songs[0].title(), "A");
+        assert_eq!(songs.missing.len(), 1);
+        assert_eq!(songs.missing[0].title.as_deref(), Some("Gone"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_playlist_replace_song() {
+        let dir = std::env::temp_dir().join("amuseing-test-playlist-replace");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("b.mp3"), b"not audio").unwrap();
+        fs::write(
+            dir.join("list.m3u8"),
+            "#EXTM3U\n#EXTINF:5,A\na.mp3\nb.mp3\n",
+        )
+        .unwrap();
+        let playlist = Playlist::new(dir.join("list.m3u8"), "List".into(), None).unwrap();
+        // a.mp3 was renamed to c.mp3, so only its directory exists
+        let canonical_dir = dir.canonicalize().unwrap();
+        let renamed = canonical_dir.join("c.mp3");
+        assert!(playlist
+            .replace_song(&canonical_dir.join("a.mp3"), &renamed)
+            .unwrap());
+        let paths: Vec<PathBuf> = playlist_file::read(playlist.path())
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        assert_eq!(paths, vec![renamed, canonical_dir.join("b.mp3")]);
+        assert!(!playlist
+            .replace_song(&canonical_dir.join("a.mp3"), &dir.join("d.mp3"))
+            .unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn songs(paths: &[&str]) -> Vec<Song> {
+        paths
+            .iter()
+            .enumerate()
+            .map(|(id, path)| Song::new(id, path.to_string(), path.into(), Duration::ZERO))
+            .collect()
+    }
+
+    fn queue_paths(queue: &Queue<Song>) -> Vec<&Path> {
+        queue.items().iter().map(Song::path).collect()
+    }
+
+    #[test]
+    fn test_switch_queue() {
+        let mut player = Player::new(100.);
+        player.set_songs(songs(&["a.mp3", "b.mp3"]));
+        player.set_repeat_mode(RepeatMode::Off);
+        player.create_queue("Other".into()).unwrap();
+        assert!(matches!(
+            player.create_queue("Default".into()),
+            Err(PlayContextError::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            player.switch_queue("Missing"),
+            Err(PlayContextError::NotFound(_))
+        ));
+
+        player.time_playing().set_millis(1500);
+        player.switch_queue("Other").unwrap();
+        assert_eq!(player.queue_name(), "Other");
+        assert!(player.queue().is_empty());
+        assert_eq!(player.queue().repeat_mode, RepeatMode::Off);
+        assert_eq!(player.queue_names(), ["Default", "Other"]);
+        player.set_repeat_mode(RepeatMode::Single);
+
+        player.switch_queue("Default").unwrap();
+        assert_eq!(queue_paths(&player.queue()), ["a.mp3", "b.mp3"]);
+        assert_eq!(player.queue().repeat_mode, RepeatMode::Off);
+        assert_eq!(player.start_position, Some(Duration::from_millis(1500)));
+        let other = &player.contexts()[0];
+        assert_eq!(other.name(), "Other");
+        assert_eq!(other.queue().repeat_mode, RepeatMode::Single);
+    }
+
+    #[test]
+    fn test_remove_queue() {
+        let mut player = Player::new(100.);
+        player.create_queue("Other".into()).unwrap();
+        assert!(matches!(
+            player.remove_queue("Default"),
+            Err(PlayContextError::Active(_))
+        ));
+        assert_eq!(player.remove_queue("Other").unwrap().name(), "Other");
+        assert!(matches!(
+            player.remove_queue("Other"),
+            Err(PlayContextError::NotFound(_))
+        ));
+        assert_eq!(player.queue_names(), ["Default"]);
+    }
+
+    #[test]
+    fn test_session_round_trip() {
+        let dir = std::env::temp_dir().join("amuseing-test-player-session");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        // Silent MPEG-1 layer 3 frames, restoring a session reads the songs again
+        let mut frame = vec![0xff, 0xfb, 0x90, 0x64];
+        frame.resize(417, 0);
+        let songs: Vec<Song> = ["a.mp3", "b.mp3", "c.mp3"]
+            .iter()
+            .map(|name| {
+                fs::write(dir.join(name), frame.repeat(40)).unwrap();
+                Song::from_file(dir.join(name), None).unwrap()
+            })
+            .collect();
+
+        let mut player = Player::new(100.);
+        player.set_songs(songs[..2].to_vec());
+        player.set_repeat_mode(RepeatMode::Single);
+        player.create_queue("Other".into()).unwrap();
+        player.switch_queue("Other").unwrap();
+        player.set_songs(songs[2..].to_vec());
+        player.set_repeat_mode(RepeatMode::Off);
+        player.time_playing().set_millis(700);
+        let session = player.session();
+        let session: Session = toml::from_str(&toml::to_string_pretty(&session).unwrap()).unwrap();
+
+        let mut restored = Player::new(100.);
+        restored.restore_session(session, None);
+        assert_eq!(restored.queue_name(), "Other");
+        assert_eq!(queue_paths(&restored.queue()), [songs[2].path()]);
+        assert_eq!(restored.queue().repeat_mode, RepeatMode::Off);
+        assert_eq!(restored.start_position, Some(Duration::from_millis(700)));
+        let default = &restored.contexts()[0];
+        assert_eq!(default.name(), "Default");
+        assert_eq!(
+            queue_paths(default.queue()),
+            [songs[0].path(), songs[1].path()]
+        );
+        assert_eq!(default.queue().repeat_mode, RepeatMode::Single);
+
+        restored.switch_queue("Default").unwrap();
+        assert_eq!(restored.queue().repeat_mode, RepeatMode::Single);
+
+        // Without the active queue, the first saved one is played and the others are kept
+        let mut session = player.session();
+        session.active_queue = "Missing".into();
+        let mut restored = Player::new(100.);
+        restored.restore_session(session, None);
+        assert_eq!(restored.queue_name(), "Other");
+        assert_eq!(restored.queue_names(), ["Default", "Other"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_playlist_create() {
+        let dir = std::env::temp_dir().join("amuseing-test-playlist-create");
+        let _ = fs::remove_dir_all(&dir);
+        let playlist = Playlist::create_in(&dir, "Road/trip".into()).unwrap();
+        let other = Playlist::create_in(&dir, "Road/trip".into()).unwrap();
+        assert_eq!(playlist.name(), "Road/trip");
+        assert!(playlist.path().ends_with("Road_trip.m3u8"));
+        assert!(other.path().ends_with("Road_trip 2.m3u8"));
+
+        let library = Library::default();
+        assert_eq!(playlist.load(&library).unwrap(), PlaylistSongs::default());
+        let missing = PlaylistEntry {
+            path: dir.join("gone.mp3"),
+            title: Some("Gone".into()),
+            duration: None,
+        };
+        let songs = PlaylistSongs {
+            songs: Vec::new(),
+            missing: vec![missing],
+        };
+        playlist.save(&songs).unwrap();
+        assert_eq!(playlist.load(&library).unwrap(), songs);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
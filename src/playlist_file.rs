@@ -0,0 +1,499 @@
+//! Reading and writing M3U/M3U8, PLS and XSPF playlist files.
+//!
+//! Relative paths in a playlist file are relative to the file's directory, and songs under that directory are written with relative paths,
+//! so a directory with its playlist files can be moved or copied to another device.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use quick_xml::{escape::escape, events::Event, Reader};
+
+use crate::{errors::PlaylistFileError, playback::Song};
+
+/// Characters that are percent encoded in XSPF locations, `/` is kept so paths stay readable.
+const LOCATION_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b'\\')
+    .add(b']')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+/// A playlist file format, picked by the file's extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    /// `.m3u` and `.m3u8`, extended M3U with `#EXTINF` lines. Both are written as UTF-8.
+    M3u,
+    /// `.pls`, the INI-like format used by Winamp and internet radio.
+    Pls,
+    /// `.xspf`, the XML Shareable Playlist Format.
+    Xspf,
+}
+
+impl PlaylistFormat {
+    /// The extensions of all the formats, for file pickers.
+    pub const EXTENSIONS: &[&str] = &["m3u8", "m3u", "pls", "xspf"];
+
+    /// The format of the playlist file at `path`, None if its extension isn't one of the [`EXTENSIONS`](Self::EXTENSIONS).
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "m3u" | "m3u8" => Some(Self::M3u),
+            "pls" => Some(Self::Pls),
+            "xspf" => Some(Self::Xspf),
+            _ => None,
+        }
+    }
+}
+
+/// A song listed in a playlist file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlaylistEntry {
+    /// The absolute path of the song, it might not exist.
+    pub path: PathBuf,
+    /// The title from the playlist file, e.g. from `#EXTINF`.
+    pub title: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+/// Check if `path` is a file with one of the playlist file extensions.
+pub fn is_playlist_file(path: &Path) -> bool {
+    PlaylistFormat::from_path(path).is_some() && path.is_file()
+}
+
+/// Read the entries of the playlist file at `path`.
+pub fn read(path: &Path) -> Result<Vec<PlaylistEntry>, PlaylistFileError> {
+    let format = PlaylistFormat::from_path(path)
+        .ok_or_else(|| PlaylistFileError::UnknownFormat(path.to_path_buf()))?;
+    // Old .m3u files aren't always UTF-8, a few replaced characters are better than not reading the file at all
+    let text = String::from_utf8_lossy(&fs::read(path)?).into_owned();
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    parse(format, &text, base_dir)
+}
+
+/// Parse the `text` of a playlist file, relative paths are resolved against `base_dir`.
+pub fn parse(
+    format: PlaylistFormat,
+    text: &str,
+    base_dir: &Path,
+) -> Result<Vec<PlaylistEntry>, PlaylistFileError> {
+    let text = text.trim_start_matches('\u{feff}');
+    match format {
+        PlaylistFormat::M3u => Ok(parse_m3u(text, base_dir)),
+        PlaylistFormat::Pls => Ok(parse_pls(text, base_dir)),
+        PlaylistFormat::Xspf => parse_xspf(text, base_dir),
+    }
+}
+
+/// Write `songs` to a playlist file at `path`, in the format of its extension.
+///
+/// The `missing` entries of the playlist are written after the songs, so they aren't lost when a playlist is edited.
+pub fn write(
+    path: &Path,
+    songs: &[Song],
+    missing: &[PlaylistEntry],
+) -> Result<(), PlaylistFileError> {
+    let format = PlaylistFormat::from_path(path)
+        .ok_or_else(|| PlaylistFileError::UnknownFormat(path.to_path_buf()))?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    Ok(fs::write(
+        path,
+        format_songs(format, songs, missing, base_dir),
+    )?)
+}
+
+/// Write `songs` and then the `missing` entries as the text of a playlist file in `base_dir`.
+pub fn format_songs(
+    format: PlaylistFormat,
+    songs: &[Song],
+    missing: &[PlaylistEntry],
+    base_dir: &Path,
+) -> String {
+    let tracks: Vec<Track> = songs
+        .iter()
+        .map(Track::from)
+        .chain(missing.iter().map(Track::from))
+        .collect();
+    let mut text = String::new();
+    match format {
+        PlaylistFormat::M3u => {
+            text.push_str("#EXTM3U\n");
+            for track in &tracks {
+                let _ = writeln!(
+                    text,
+                    "#EXTINF:{},{}\n{}",
+                    track.seconds(),
+                    track.display_title(),
+                    relative_path(track.path, base_dir).display()
+                );
+            }
+        }
+        PlaylistFormat::Pls => {
+            text.push_str("[playlist]\n");
+            for (i, track) in tracks.iter().enumerate() {
+                let n = i + 1;
+                let _ = writeln!(
+                    text,
+                    "File{n}={}\nTitle{n}={}\nLength{n}={}",
+                    relative_path(track.path, base_dir).display(),
+                    track.display_title(),
+                    track.seconds()
+                );
+            }
+            let _ = writeln!(text, "NumberOfEntries={}\nVersion=2", tracks.len());
+        }
+        PlaylistFormat::Xspf => {
+            text.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+            text.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+            text.push_str("  <trackList>\n");
+            for track in &tracks {
+                text.push_str("    <track>\n");
+                let path = relative_path(track.path, base_dir);
+                let path = path.to_string_lossy();
+                let location = utf8_percent_encode(&path, LOCATION_ENCODE_SET).to_string();
+                let location = if Path::new(path.as_ref()).is_absolute() {
+                    format!("file://{location}")
+                } else {
+                    location
+                };
+                let _ = writeln!(text, "      <location>{}</location>", escape(location));
+                if let Some(title) = track.title {
+                    let _ = writeln!(text, "      <title>{}</title>", escape(title));
+                }
+                if let Some(artist) = track.artist {
+                    let _ = writeln!(text, "      <creator>{}</creator>", escape(artist));
+                }
+                if let Some(album) = track.album {
+                    let _ = writeln!(text, "      <album>{}</album>", escape(album));
+                }
+                if let Some(duration) = track.duration {
+                    let _ = writeln!(text, "      <duration>{}</duration>", duration.as_millis());
+                }
+                text.push_str("    </track>\n");
+            }
+            text.push_str("  </trackList>\n");
+            text.push_str("</playlist>\n");
+        }
+    }
+    text
+}
+
+/// What's written for a song or a missing entry.
+struct Track<'a> {
+    path: &'a Path,
+    title: Option<&'a str>,
+    artist: Option<&'a str>,
+    album: Option<&'a str>,
+    duration: Option<Duration>,
+}
+
+impl<'a> From<&'a Song> for Track<'a> {
+    fn from(song: &'a Song) -> Self {
+        Self {
+            path: song.path(),
+            title: Some(song.title()),
+            artist: song.artist(),
+            album: song.album(),
+            duration: Some(*song.duration()),
+        }
+    }
+}
+
+impl<'a> From<&'a PlaylistEntry> for Track<'a> {
+    fn from(entry: &'a PlaylistEntry) -> Self {
+        Self {
+            path: &entry.path,
+            title: entry.title.as_deref(),
+            artist: None,
+            album: None,
+            duration: entry.duration,
+        }
+    }
+}
+
+impl Track<'_> {
+    /// "Artist - Title", the title other players expect in `#EXTINF` lines.
+    fn display_title(&self) -> String {
+        match (self.artist, self.title) {
+            (Some(artist), Some(title)) => format!("{artist} - {title}"),
+            (_, title) => title.unwrap_or_default().to_string(),
+        }
+    }
+
+    /// The duration in seconds, -1 if it's unknown.
+    fn seconds(&self) -> i64 {
+        self.duration
+            .map_or(-1, |duration| duration.as_secs() as i64)
+    }
+}
+
+/// `path` relative to `base_dir` if it's under it.
+fn relative_path<'a>(path: &'a Path, base_dir: &Path) -> &'a Path {
+    path.strip_prefix(base_dir).unwrap_or(path)
+}
+
+/// Turn a path or `file://` URL from a playlist file into an absolute path.
+fn resolve(location: &str, base_dir: &Path) -> PathBuf {
+    let location = match location.strip_prefix("file://") {
+        // file:///path and file://localhost/path
+        Some(url) => {
+            let path = url.strip_prefix("localhost").unwrap_or(url);
+            percent_decode_str(path).decode_utf8_lossy().into_owned()
+        }
+        None => location.to_string(),
+    };
+    // Playlists made on windows use backslashes
+    let location = if cfg!(windows) {
+        location
+    } else {
+        location.replace('\\', "/")
+    };
+    base_dir.join(location)
+}
+
+fn parse_m3u(text: &str, base_dir: &Path) -> Vec<PlaylistEntry> {
+    let mut entries = Vec::new();
+    // The title and duration of the next entry
+    let mut info = None;
+    for line in text.lines().map(str::trim) {
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            // #EXTINF:<seconds> <attributes>,<title>, the duration is -1 if it's unknown
+            let (duration, title) = extinf.split_once(',').unwrap_or((extinf, ""));
+            let duration = duration
+                .split_whitespace()
+                .next()
+                .and_then(|seconds| seconds.parse::<f64>().ok())
+                .filter(|seconds| *seconds >= 0.)
+                .map(Duration::from_secs_f64);
+            let title = Some(title.trim().to_string()).filter(|title| !title.is_empty());
+            info = Some((title, duration));
+        } else if !line.is_empty() && !line.starts_with('#') {
+            let (title, duration) = info.take().unwrap_or_default();
+            entries.push(PlaylistEntry {
+                path: resolve(line, base_dir),
+                title,
+                duration,
+            });
+        }
+    }
+    entries
+}
+
+/// The keys of one numbered entry in a PLS file, which can be in any order.
+#[derive(Default)]
+struct PlsEntry {
+    file: Option<String>,
+    title: Option<String>,
+    length: Option<Duration>,
+}
+
+fn parse_pls(text: &str, base_dir: &Path) -> Vec<PlaylistEntry> {
+    // The entries by their number, they don't have to be in order
+    let mut entries: BTreeMap<u32, PlsEntry> = BTreeMap::new();
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim();
+        let split = key.find(|c: char| c.is_ascii_digit()).unwrap_or(key.len());
+        let Ok(n) = key[split..].parse() else {
+            continue;
+        };
+        let entry = entries.entry(n).or_default();
+        match &key[..split] {
+            "file" => entry.file = Some(value.to_string()),
+            "title" => entry.title = Some(value.to_string()),
+            "length" => {
+                entry.length = value
+                    .parse::<i64>()
+                    .ok()
+                    .and_then(|seconds| u64::try_from(seconds).ok())
+                    .map(Duration::from_secs)
+            }
+            _ => {}
+        }
+    }
+    entries
+        .into_values()
+        .filter_map(|entry| {
+            Some(PlaylistEntry {
+                path: resolve(&entry.file?, base_dir),
+                title: entry.title,
+                duration: entry.length,
+            })
+        })
+        .collect()
+}
+
+fn parse_xspf(text: &str, base_dir: &Path) -> Result<Vec<PlaylistEntry>, PlaylistFileError> {
+    let mut reader = Reader::from_str(text);
+    reader.config_mut().trim_text(true);
+    let mut entries = Vec::new();
+    // The element whose text is being read, and the track it's in
+    let mut element = Vec::new();
+    let mut track: Option<(Option<String>, Option<String>, Option<Duration>)> = None;
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) => {
+                element = start.local_name().as_ref().to_vec();
+                if element == b"track" {
+                    track = Some(Default::default());
+                }
+            }
+            Event::End(end) => {
+                if end.local_name().as_ref() == b"track" {
+                    if let Some((Some(location), title, duration)) = track.take() {
+                        // Locations are URLs, relative ones are percent encoded too
+                        let location = match location.contains("://") {
+                            true => location,
+                            false => percent_decode_str(&location)
+                                .decode_utf8_lossy()
+                                .into_owned(),
+                        };
+                        entries.push(PlaylistEntry {
+                            path: resolve(&location, base_dir),
+                            title,
+                            duration,
+                        });
+                    }
+                }
+                element.clear();
+            }
+            Event::Text(text) => {
+                let Some((location, title, duration)) = track.as_mut() else {
+                    continue;
+                };
+                let text = text.unescape()?.into_owned();
+                match element.as_slice() {
+                    // Only the first location is used, the others are alternatives
+                    b"location" if location.is_none() => *location = Some(text),
+                    b"title" => *title = Some(text),
+                    b"duration" => *duration = text.parse().ok().map(Duration::from_millis),
+                    _ => {}
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tags::Tags;
+
+    fn song(path: &str, title: &str, artist: Option<&str>, seconds: u64) -> Song {
+        let tags = Tags {
+            artist: artist.map(Into::into),
+            ..Default::default()
+        };
+        Song::new(
+            0,
+            title.into(),
+            PathBuf::from(path),
+            Duration::from_secs(seconds),
+        )
+        .with_tags(tags)
+    }
+
+    #[test]
+    fn test_parse() {
+        let base = Path::new("/music/lists");
+        let m3u = "\u{feff}#EXTM3U\n#EXTINF:123 tvg-id=\"x\",Artist - Title\n../a b.mp3\n\n#EXTINF:-1,\n/abs/c.flac\nsub\\d.ogg\n";
+        assert_eq!(
+            parse(PlaylistFormat::M3u, m3u, base).unwrap(),
+            vec![
+                PlaylistEntry {
+                    path: PathBuf::from("/music/lists/../a b.mp3"),
+                    title: Some("Artist - Title".into()),
+                    duration: Some(Duration::from_secs(123)),
+                },
+                PlaylistEntry {
+                    path: PathBuf::from("/abs/c.flac"),
+                    title: None,
+                    duration: None,
+                },
+                PlaylistEntry {
+                    path: PathBuf::from("/music/lists/sub/d.ogg"),
+                    title: None,
+                    duration: None,
+                },
+            ]
+        );
+        let pls = "[playlist]\nFile2=b.mp3\nFile1=a.mp3\nTitle1=A\nLength1=-1\nNumberOfEntries=2\n";
+        let paths: Vec<PathBuf> = parse(PlaylistFormat::Pls, pls, base)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/music/lists/a.mp3"),
+                PathBuf::from("/music/lists/b.mp3")
+            ]
+        );
+        let xspf = r#"<playlist version="1" xmlns="http://xspf.org/ns/0/"><trackList>
+            <track><location>file:///music/A%20%26%20B.mp3</location><title>A &amp; B</title><duration>1500</duration></track>
+            <track><title>No location</title></track>
+        </trackList></playlist>"#;
+        assert_eq!(
+            parse(PlaylistFormat::Xspf, xspf, base).unwrap(),
+            vec![PlaylistEntry {
+                path: PathBuf::from("/music/A & B.mp3"),
+                title: Some("A & B".into()),
+                duration: Some(Duration::from_millis(1500)),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let base = Path::new("/music");
+        let songs = [
+            song("/music/x/1 #1.mp3", "One & Two", Some("Band"), 61),
+            song("/elsewhere/2.flac", "Two", None, 2),
+        ];
+        for format in [
+            PlaylistFormat::M3u,
+            PlaylistFormat::Pls,
+            PlaylistFormat::Xspf,
+        ] {
+            let text = format_songs(format, &songs, &[], base);
+            let entries = parse(format, &text, base).unwrap();
+            let paths: Vec<&Path> = entries.iter().map(|entry| entry.path.as_path()).collect();
+            assert_eq!(
+                paths,
+                vec![
+                    Path::new("/music/x/1 #1.mp3"),
+                    Path::new("/elsewhere/2.flac")
+                ],
+                "{format:?}"
+            );
+            assert_eq!(
+                entries[0].duration.map(|duration| duration.as_secs()),
+                Some(61)
+            );
+        }
+        assert!(format_songs(PlaylistFormat::M3u, &songs, &[], base)
+            .contains("#EXTINF:61,Band - One & Two\nx/1 #1.mp3\n"));
+    }
+}
@@ -1,15 +1,33 @@
-use std::fmt::Display;
+use std::{
+    cmp::Ordering,
+    fmt::Display,
+    ops::{Bound, Range, RangeBounds},
+    str::FromStr,
+};
 
-use crate::errors::OutOfBoundsError;
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{OutOfBoundsError, ParseRepeatModeError};
 
 /// Controls the behaviour of the [`Queue::next_item`] method.
-#[derive(Copy, Clone, Debug, PartialEq)]
+///
+/// Serialized as the same lowercase string as the [`Display`] implementation, `"off"`, `"one"` or `"all"`.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum RepeatMode {
     /// The queue does not repeat. Calls to [`next_item`] return None until the RepeatMode is changed.
     ///
     /// [`next_item`]: Queue::next_item
     Off,
-    /// The queue returns the same item on repeat, this includes None if the current index is larger than the length of the items.
+    /// The queue returns the same item on repeat.
+    ///
+    /// If there is no current item (the index is past the end, or the current item was removed), the item at the index wraps around like with [`All`].
+    /// This means [`next_item`] only returns None if the queue is empty.
+    ///
+    /// [`All`]: RepeatMode::All
+    /// [`next_item`]: Queue::next_item
+    #[serde(rename = "one", alias = "single")]
     Single,
     /// The queue loops back to the start after reaching the end.
     All,
@@ -36,7 +54,19 @@ impl Display for RepeatMode {
     }
 }
 
-// TODO: turn into builder pattern
+impl FromStr for RepeatMode {
+    type Err = ParseRepeatModeError;
+
+    /// Parse the output of the [`Display`] implementation, `"single"` is also accepted. The case is ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "one" | "single" => Ok(Self::Single),
+            "all" => Ok(Self::All),
+            _ => Err(ParseRepeatModeError(s.into())),
+        }
+    }
+}
 
 /// A queue of items with variable iteration rules, depending on the repeat_mode field.
 ///
@@ -44,8 +74,28 @@ impl Display for RepeatMode {
 ///
 /// See [`next_item`] for an explanation on how the repeat mode changes iteration.
 ///
+/// # Invariants
+///
+/// - The index is never past the end of the queue, `index() <= items().len()`.
+/// - If the queue has advanced (an item was returned since the last jump), the index points to an item,
+///   so [`current`] is the last returned item. Otherwise [`current`] is the item that will be returned next, if there is one.
+/// - Edits (inserting, removing, moving, sorting) keep the index on the same item.
+///   If the current item is removed, the item after it is guaranteed to be the next item.
+/// - After the queue ran out with [`Off`], items added to the end are returned by the next call to [`next_item`].
+///
+/// These are checked against a reference model in the `model_tests` module.
+///
+/// # Serialization
+///
+/// Queues are serialized as a struct with the fields `items`, `index`, `repeat-mode` and `has-advanced`,
+/// so a deserialized queue continues exactly where it was. Deserializing fails if the fields break the invariants.
+///
 /// [`next_item`]: Self::next_item
-#[derive(Clone, Debug)]
+/// [`current`]: Self::current
+/// [`Off`]: RepeatMode::Off
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(try_from = "QueueData<T>")]
 pub struct Queue<T> {
     items: Vec<T>,
     index: usize,
@@ -68,6 +118,35 @@ impl<T> Queue<T> {
         }
     }
 
+    /// Convert every item with `f`, keeping the position and repeat mode.
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Queue<U> {
+        Queue {
+            items: self.items.into_iter().map(f).collect(),
+            index: self.index,
+            repeat_mode: self.repeat_mode,
+            has_advanced: self.has_advanced,
+        }
+    }
+
+    /// Return a [`QueueBuilder`] for creating a queue with items and a starting position.
+    pub fn builder() -> QueueBuilder<T> {
+        QueueBuilder::new()
+    }
+
+    /// Return the index and `has_advanced` the queue would have after a call to [`next_item`] from the given state.
+    ///
+    /// [`next_item`]: Self::next_item
+    fn next_position(&self, mut index: usize, has_advanced: bool) -> (usize, bool) {
+        if self.repeat_mode != RepeatMode::Single && has_advanced && index < self.items.len() {
+            index += 1;
+        }
+        if self.repeat_mode != RepeatMode::Off {
+            index %= self.items.len();
+        }
+        // Past the end there is no current item, so the next call shouldn't advance past items added later
+        (index, index < self.items.len())
+    }
+
     /// Return the next item in the queue, depending on the [`RepeatMode`].
     ///
     /// If the queue is not empty, the first call always gives the first item, regardless of the repeat mode.
@@ -76,7 +155,8 @@ impl<T> Queue<T> {
     /// This means that [`jump`]ing to index 5, and calling [`next_item`] will return the 6th item, or None if that's outside the queue.
     ///
     /// This method updates the index on call.
-    /// The index is not updated if the repeat mode is [`Off`] and the method returns None.
+    /// If the repeat mode is [`Off`] and the method returns None, the index stays at the end of the queue,
+    /// so the next call returns the first item added after that.
     ///
     /// [`jump`]: Self::jump
     /// [`skip`]: Self::skip
@@ -86,7 +166,7 @@ impl<T> Queue<T> {
     /// # Iteration rules
     ///
     /// If the repeat mode is [`All`], the queue will wrap around to the beginning when it reached the end.
-    /// If it is [`Single`], the same value will be returned every time, even if it is None.
+    /// If it is [`Single`], the same value will be returned every time.
     /// If it is [`Off`], the queue will return None after reaching the end.
     ///
     /// [`All`]: RepeatMode::All
@@ -96,19 +176,41 @@ impl<T> Queue<T> {
         if self.items.is_empty() {
             return None;
         }
-        if self.repeat_mode != RepeatMode::Single
-            && self.has_advanced
-            && self.index < self.items.len()
-        {
-            self.index += 1;
-        }
-        if self.repeat_mode != RepeatMode::Off {
-            self.index %= self.items.len();
-        }
-        self.has_advanced = true;
+        (self.index, self.has_advanced) = self.next_position(self.index, self.has_advanced);
         self.items.get(self.index)
     }
 
+    /// Return an iterator over the items [`next_item`] would return, in order, without changing the queue.
+    ///
+    /// The iterator ends where [`next_item`] would return None,
+    /// so it never ends if the repeat mode is [`All`] or [`Single`] and the queue isn't empty.
+    /// Use [`Iterator::take`] to limit it.
+    ///
+    /// [`next_item`]: Self::next_item
+    /// [`All`]: RepeatMode::All
+    /// [`Single`]: RepeatMode::Single
+    pub fn upcoming(&self) -> Upcoming<'_, T> {
+        Upcoming {
+            queue: self,
+            index: self.index,
+            has_advanced: self.has_advanced,
+        }
+    }
+
+    /// Return the item the `n + 1`th call to [`next_item`] would return, without changing the queue.
+    ///
+    /// `peek_next(0)` is the item [`next_item`] would return now.
+    ///
+    /// [`next_item`]: Self::next_item
+    pub fn peek_next(&self, n: usize) -> Option<&T> {
+        self.upcoming().nth(n)
+    }
+
+    /// Return the position of the first item for which `predicate` returns true.
+    pub fn position_of(&self, predicate: impl FnMut(&T) -> bool) -> Option<usize> {
+        self.items.iter().position(predicate)
+    }
+
     /// Return a slice of the items in the queue.
     pub fn items(&self) -> &[T] {
         &self.items
@@ -144,23 +246,238 @@ impl<T> Queue<T> {
     /// Remove the value at position `index`, calling [`Vec::remove`] internally.
     ///
     /// Rewinds the queue by 1 if the given index is less than the internal one.
+    /// If the current item is removed, the item after it is guaranteed to be the next item.
     pub fn remove(&mut self, index: usize) {
         self.items.remove(index);
-        if index < self.index {
-            self.index -= 1;
+        match index.cmp(&self.index) {
+            Ordering::Less => self.index -= 1,
+            Ordering::Equal => self.has_advanced = false,
+            Ordering::Greater => {}
         }
     }
 
-    /// Inserts an item at position `index`, calling [`Vec::remove`] internally.
+    /// Inserts an item at position `index`, calling [`Vec::insert`] internally.
     ///
     /// Advanced the queue by 1 if `index` is less or equal to the internal one, for consistent iteration.
+    /// Inserting at the end of the queue while the index is past the end doesn't advance it, so the new item is returned next.
     pub fn insert(&mut self, index: usize, item: T) {
+        let past_end = self.index >= self.items.len();
         self.items.insert(index, item);
-        if index <= self.index {
+        if index < self.index || (index == self.index && !past_end) {
             self.index += 1;
         }
     }
 
+    /// Move the item at position `from` to position `to`, shifting the items in between.
+    ///
+    /// The index follows the moved items, so the current item stays the same.
+    pub fn move_item(&mut self, from: usize, to: usize) -> Result<(), OutOfBoundsError<usize>> {
+        if from >= self.items.len() {
+            return Err(OutOfBoundsError::high(from, self.items.len()));
+        }
+        self.move_range(from..from + 1, to)
+    }
+
+    /// Move the items in `range` so the first of them ends up at position `to`, keeping their order.
+    ///
+    /// The index follows the moved items, so the current item stays the same.
+    pub fn move_range(
+        &mut self,
+        range: Range<usize>,
+        to: usize,
+    ) -> Result<(), OutOfBoundsError<usize>> {
+        let Range { start, end } = range;
+        if end > self.items.len() {
+            return Err(OutOfBoundsError::high(end, self.items.len()));
+        }
+        if start > end {
+            return Err(OutOfBoundsError::high(start, end));
+        }
+        let n = end - start;
+        if to > self.items.len() - n {
+            return Err(OutOfBoundsError::high(to, self.items.len() - n));
+        }
+        if to < start {
+            self.items[to..end].rotate_right(n);
+        } else {
+            self.items[start..to + n].rotate_left(n);
+        }
+        let i = self.index;
+        if i < self.items.len() {
+            self.index = if (start..end).contains(&i) {
+                i - start + to
+            } else if (to..start).contains(&i) {
+                i + n
+            } else if (end..to + n).contains(&i) {
+                i - n
+            } else {
+                i
+            };
+        }
+        Ok(())
+    }
+
+    /// Remove the items in `range`, returning them.
+    ///
+    /// If the current item is removed, the first item after the range is guaranteed to be the next item.
+    pub fn remove_range(
+        &mut self,
+        range: impl RangeBounds<usize>,
+    ) -> Result<Vec<T>, OutOfBoundsError<usize>> {
+        let start = match range.start_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&n) => n + 1,
+            Bound::Excluded(&n) => n,
+            Bound::Unbounded => self.items.len(),
+        };
+        if end > self.items.len() {
+            return Err(OutOfBoundsError::high(end, self.items.len()));
+        }
+        if start > end {
+            return Err(OutOfBoundsError::high(start, end));
+        }
+        let keep: Vec<bool> = (0..self.items.len())
+            .map(|i| !(start..end).contains(&i))
+            .collect();
+        self.update_index_retained(&keep);
+        Ok(self.items.drain(start..end).collect())
+    }
+
+    /// Keep only the items for which `f` returns true, see [`Vec::retain`].
+    ///
+    /// If the current item is removed, the first kept item after it is guaranteed to be the next item.
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        let keep = self.items.iter().map(&mut f).collect();
+        self.retain_mask(keep);
+    }
+
+    /// Remove consecutive items for which `same_bucket` returns true, see [`Vec::dedup_by`].
+    ///
+    /// `same_bucket` is called with the item being checked and the last kept item before it, in that order.
+    pub fn dedup_by(&mut self, mut same_bucket: impl FnMut(&T, &T) -> bool) {
+        let mut keep = vec![true; self.items.len()];
+        let mut last_kept = 0;
+        for (i, keep) in keep.iter_mut().enumerate().skip(1) {
+            if same_bucket(&self.items[i], &self.items[last_kept]) {
+                *keep = false;
+            } else {
+                last_kept = i;
+            }
+        }
+        self.retain_mask(keep);
+    }
+
+    /// Sort the items with the comparator function, see [`slice::sort_by`].
+    ///
+    /// The sort is stable, and the index follows the current item to its new position.
+    pub fn sort_by(&mut self, mut compare: impl FnMut(&T, &T) -> Ordering) {
+        let mut order: Vec<usize> = (0..self.items.len()).collect();
+        order.sort_by(|&a, &b| compare(&self.items[a], &self.items[b]));
+        if self.index < self.items.len() {
+            self.index = order
+                .iter()
+                .position(|&i| i == self.index)
+                .expect("Every index should be in the sorted order");
+        }
+        let mut items: Vec<Option<T>> = self.items.drain(..).map(Some).collect();
+        self.items = order
+            .into_iter()
+            .map(|i| items[i].take().expect("Every item should be taken once"))
+            .collect();
+    }
+
+    /// Randomly reorder the items that come after the current one.
+    ///
+    /// The items before the current one and the current one itself keep their positions.
+    /// If nothing was returned since the last jump, the item at the index is shuffled too.
+    pub fn shuffle(&mut self, rng: &mut impl Rng) {
+        let start = if self.has_advanced {
+            self.index + 1
+        } else {
+            self.index
+        };
+        if start < self.items.len() {
+            self.items[start..].shuffle(rng);
+        }
+    }
+
+    /// Randomly reorder the items that come after the current one, like [`shuffle`](Self::shuffle),
+    /// but items with a higher `weight` are more likely to come first.
+    ///
+    /// Weights should be positive, an item with twice the weight of another is twice as likely to come before it.
+    pub fn shuffle_weighted(&mut self, rng: &mut impl Rng, weight: impl Fn(&T) -> f64) {
+        let start = if self.has_advanced {
+            self.index + 1
+        } else {
+            self.index
+        };
+        if start >= self.items.len() {
+            return;
+        }
+        // Sorting by exponentially distributed keys with the weights as rates gives a weighted random order
+        let keys: Vec<f64> = self.items[start..]
+            .iter()
+            .map(|item| -rng.gen::<f64>().ln() / weight(item).max(f64::MIN_POSITIVE))
+            .collect();
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|&a, &b| keys[a].total_cmp(&keys[b]));
+        let mut items: Vec<Option<T>> = self.items.drain(start..).map(Some).collect();
+        self.items.extend(
+            order
+                .into_iter()
+                .map(|i| items[i].take().expect("Every item should be taken once")),
+        );
+    }
+
+    /// Replace all items in the queue, keeping the index on the current item if it's in `items`.
+    ///
+    /// If the current item isn't in `items`, the item at the same position is guaranteed to be the next item.
+    pub fn replace_items(&mut self, items: Vec<T>)
+    where
+        T: PartialEq,
+    {
+        let new_index = self
+            .current()
+            .and_then(|current| items.iter().position(|item| item == current));
+        match new_index {
+            Some(new_index) => self.index = new_index,
+            None => {
+                self.index = self.index.min(items.len());
+                self.has_advanced = false;
+            }
+        }
+        self.items = items;
+    }
+
+    /// Remove the items where `keep` is false, and update the index with [`update_index_retained`].
+    ///
+    /// [`update_index_retained`]: Self::update_index_retained
+    fn retain_mask(&mut self, keep: Vec<bool>) {
+        self.update_index_retained(&keep);
+        let mut keep = keep.into_iter();
+        self.items.retain(|_| keep.next().unwrap_or(true));
+    }
+
+    /// Update the index before removing the items where `keep` is false, so it points to the same item after.
+    ///
+    /// If the current item is going to be removed, the index points to the first kept item after it, and the queue is marked as not advanced.
+    /// An index past the end of the queue stays past the end.
+    fn update_index_retained(&mut self, keep: &[bool]) {
+        let kept_len = keep.iter().filter(|k| **k).count();
+        if self.index >= self.items.len() {
+            self.index = kept_len;
+            return;
+        }
+        if !keep[self.index] {
+            self.has_advanced = false;
+        }
+        self.index = keep[..self.index].iter().filter(|k| **k).count();
+    }
+
     /// Jump to index `n` in the queue.
     ///
     /// This method guarantees the next item is at index `n`.
@@ -201,13 +518,20 @@ impl<T> Queue<T> {
     /// Rewind n items.
     ///
     /// This method guarantees the next item is `n` behind the current item.
+    ///
+    /// If the repeat mode is [`Off`], rewinding beyond the start of the queue will set the index to 0, otherwise wrap around to the end.
+    ///
+    /// [`Off`]: RepeatMode::Off
     pub fn rewind(&mut self, n: usize) {
+        let len = self.items.len();
         let new_index = if self.items.is_empty() {
             0
         } else if n <= self.index {
             self.index - n
+        } else if self.repeat_mode == RepeatMode::Off {
+            0
         } else {
-            self.items.len() - (n - self.index)
+            (len - (n - self.index) % len) % len
         };
         self.jump(new_index)
             .expect("Calculated jump from rewind shouldn't fail");
@@ -225,6 +549,116 @@ impl<T> Extend<T> for Queue<T> {
     }
 }
 
+/// The fields of a serialized [`Queue`], checked before they're turned into one.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct QueueData<T> {
+    items: Vec<T>,
+    index: usize,
+    repeat_mode: RepeatMode,
+    has_advanced: bool,
+}
+
+impl<T> TryFrom<QueueData<T>> for Queue<T> {
+    type Error = OutOfBoundsError<usize>;
+
+    fn try_from(data: QueueData<T>) -> Result<Self, Self::Error> {
+        let len = data.items.len();
+        // An advanced queue has to point to an item, otherwise the index can be at the end
+        let max = if data.has_advanced {
+            len.saturating_sub(1)
+        } else {
+            len
+        };
+        if data.index > max || (data.has_advanced && len == 0) {
+            return Err(OutOfBoundsError::high(data.index, max));
+        }
+        Ok(Self {
+            items: data.items,
+            index: data.index,
+            repeat_mode: data.repeat_mode,
+            has_advanced: data.has_advanced,
+        })
+    }
+}
+
+/// Iterator over the items a [`Queue`] would return from [`Queue::next_item`], see [`Queue::upcoming`].
+#[derive(Clone, Debug)]
+pub struct Upcoming<'a, T> {
+    queue: &'a Queue<T>,
+    index: usize,
+    has_advanced: bool,
+}
+
+impl<'a, T> Iterator for Upcoming<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.queue.items.is_empty() {
+            return None;
+        }
+        (self.index, self.has_advanced) = self.queue.next_position(self.index, self.has_advanced);
+        self.queue.items.get(self.index)
+    }
+}
+
+/// Builder for a [`Queue`], created with [`Queue::builder`].
+///
+/// The repeat mode defaults to [`RepeatMode::Off`].
+#[derive(Clone, Debug)]
+pub struct QueueBuilder<T> {
+    items: Vec<T>,
+    repeat_mode: RepeatMode,
+    start: usize,
+}
+
+impl<T> QueueBuilder<T> {
+    pub fn new() -> Self {
+        Self {
+            items: Vec::new(),
+            repeat_mode: RepeatMode::Off,
+            start: 0,
+        }
+    }
+
+    /// Set the repeat mode of the queue.
+    pub fn repeat_mode(mut self, repeat_mode: RepeatMode) -> Self {
+        self.repeat_mode = repeat_mode;
+        self
+    }
+
+    /// Add the items to the queue.
+    pub fn items(mut self, items: impl IntoIterator<Item = T>) -> Self {
+        self.items.extend(items);
+        self
+    }
+
+    /// Start the queue at index `start`, see [`Queue::jump`].
+    pub fn start_at(mut self, start: usize) -> Self {
+        self.start = start;
+        self
+    }
+
+    /// Create the queue.
+    ///
+    /// Fails if the start index is past the end of the items.
+    pub fn build(self) -> Result<Queue<T>, OutOfBoundsError<usize>> {
+        let mut queue = Queue::new(self.repeat_mode);
+        queue.items = self.items;
+        queue.jump(self.start)?;
+        Ok(queue)
+    }
+}
+
+impl<T> Default for QueueBuilder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod model_tests;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -293,4 +727,195 @@ mod tests {
         assert_eq!(&queue.items, &[6, 3, 2]);
         assert_eq!(queue.index, 1);
     }
+
+    #[test]
+    fn test_move_item() {
+        let mut queue = Queue::new(RepeatMode::Off);
+        queue.items = vec![1, 2, 3, 4, 5];
+        queue.jump(1).unwrap();
+        assert_eq!(queue.next_item(), Some(&2));
+        queue.move_item(1, 3).unwrap();
+        assert_eq!(&queue.items, &[1, 3, 4, 2, 5]);
+        assert_eq!(queue.current(), Some(&2));
+        queue.move_item(4, 0).unwrap();
+        assert_eq!(&queue.items, &[5, 1, 3, 4, 2]);
+        assert_eq!(queue.current(), Some(&2));
+        assert_eq!(queue.next_item(), None);
+        assert!(queue.move_item(5, 0).is_err());
+        assert!(queue.move_item(0, 5).is_err());
+    }
+
+    #[test]
+    fn test_move_range() {
+        let mut queue = Queue::new(RepeatMode::All);
+        queue.items = vec![1, 2, 3, 4, 5, 6];
+        queue.jump(4).unwrap();
+        assert_eq!(queue.next_item(), Some(&5));
+        queue.move_range(0..2, 3).unwrap();
+        assert_eq!(&queue.items, &[3, 4, 5, 1, 2, 6]);
+        assert_eq!(queue.current(), Some(&5));
+        queue.move_range(3..5, 0).unwrap();
+        assert_eq!(&queue.items, &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(queue.current(), Some(&5));
+        assert!(queue.move_range(2..4, 5).is_err());
+    }
+
+    #[test]
+    fn test_remove_range() {
+        let mut queue = Queue::new(RepeatMode::Off);
+        queue.items = vec![1, 2, 3, 4, 5, 6];
+        queue.jump(2).unwrap();
+        assert_eq!(queue.next_item(), Some(&3));
+        assert_eq!(queue.remove_range(2..=3).unwrap(), vec![3, 4]);
+        assert_eq!(&queue.items, &[1, 2, 5, 6]);
+        // The current item was removed, the one after the range should be next
+        assert_eq!(queue.next_item(), Some(&5));
+        assert_eq!(queue.remove_range(..1).unwrap(), vec![1]);
+        assert_eq!(queue.current(), Some(&5));
+        assert!(queue.remove_range(1..4).is_err());
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut queue = Queue::new(RepeatMode::Off);
+        queue.items = vec![1, 2, 3, 4, 5, 6];
+        queue.jump(3).unwrap();
+        assert_eq!(queue.next_item(), Some(&4));
+        queue.retain(|n| n % 2 == 1);
+        assert_eq!(&queue.items, &[1, 3, 5]);
+        assert_eq!(queue.next_item(), Some(&5));
+        queue.retain(|n| *n != 5);
+        assert_eq!(queue.next_item(), None);
+    }
+
+    #[test]
+    fn test_dedup_by() {
+        let mut queue = Queue::new(RepeatMode::All);
+        queue.items = vec![1, 1, 2, 2, 2, 3, 1];
+        queue.jump(5).unwrap();
+        assert_eq!(queue.next_item(), Some(&3));
+        queue.dedup_by(|a, b| a == b);
+        assert_eq!(&queue.items, &[1, 2, 3, 1]);
+        assert_eq!(queue.current(), Some(&3));
+    }
+
+    #[test]
+    fn test_replace_items() {
+        let mut queue = Queue::new(RepeatMode::Off);
+        queue.items = vec![1, 2, 3];
+        queue.jump(1).unwrap();
+        assert_eq!(queue.next_item(), Some(&2));
+        queue.replace_items(vec![3, 2, 1]);
+        assert_eq!(queue.current(), Some(&2));
+        assert_eq!(queue.next_item(), Some(&1));
+        queue.replace_items(vec![4, 5, 6]);
+        assert_eq!(queue.next_item(), Some(&6));
+    }
+
+    #[test]
+    fn test_upcoming() {
+        let mut queue = Queue::builder()
+            .items([1, 2, 3])
+            .repeat_mode(RepeatMode::All)
+            .build()
+            .unwrap();
+        assert_eq!(
+            queue.upcoming().take(4).collect::<Vec<_>>(),
+            [&1, &2, &3, &1]
+        );
+        queue.next_item();
+        queue.next_item();
+        assert_eq!(queue.peek_next(0), Some(&3));
+        assert_eq!(queue.peek_next(1), Some(&1));
+        queue.repeat_mode = RepeatMode::Off;
+        assert_eq!(queue.upcoming().collect::<Vec<_>>(), [&3]);
+        queue.repeat_mode = RepeatMode::Single;
+        assert_eq!(queue.upcoming().take(2).collect::<Vec<_>>(), [&2, &2]);
+        assert_eq!(queue.position_of(|n| *n == 3), Some(2));
+    }
+
+    #[test]
+    fn test_builder() {
+        let mut queue = Queue::builder()
+            .items([1, 2, 3])
+            .start_at(2)
+            .build()
+            .unwrap();
+        assert_eq!(queue.next_item(), Some(&3));
+        assert_eq!(queue.next_item(), None);
+        assert!(Queue::builder().items([1]).start_at(2).build().is_err());
+    }
+
+    #[test]
+    fn test_shuffle_weighted() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut queue = Queue::builder().items(0..100).build().unwrap();
+        queue.shuffle_weighted(&mut StdRng::seed_from_u64(0), |&item| {
+            if item == 99 {
+                1e9
+            } else {
+                1.
+            }
+        });
+        assert_eq!(queue.items[0], 99);
+        let mut sorted = queue.items.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_shuffle() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut queue = Queue::builder().items(0..100).build().unwrap();
+        queue.jump(10).unwrap();
+        queue.next_item();
+        queue.shuffle(&mut StdRng::seed_from_u64(0));
+        assert_eq!(&queue.items[..11], (0..11).collect::<Vec<_>>());
+        assert_ne!(&queue.items[11..], (11..100).collect::<Vec<_>>());
+        let mut sorted = queue.items.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_repeat_mode_from_str() {
+        for mode in [RepeatMode::Off, RepeatMode::Single, RepeatMode::All] {
+            assert_eq!(mode.to_string().parse::<RepeatMode>().unwrap(), mode);
+        }
+        assert_eq!("Single".parse::<RepeatMode>().unwrap(), RepeatMode::Single);
+        assert!("twice".parse::<RepeatMode>().is_err());
+    }
+
+    #[test]
+    fn test_serde() {
+        let mut queue = Queue::builder()
+            .items([1, 2, 3])
+            .repeat_mode(RepeatMode::Single)
+            .build()
+            .unwrap();
+        queue.next_item();
+        let serialized = toml::to_string(&queue).unwrap();
+        assert_eq!(
+            serialized,
+            "items = [1, 2, 3]\nindex = 0\nrepeat-mode = \"one\"\nhas-advanced = true\n"
+        );
+        let mut deserialized: Queue<u32> = toml::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.next_item(), queue.next_item());
+
+        let past_end = "items = [1]\nindex = 1\nrepeat-mode = \"off\"\nhas-advanced = true\n";
+        assert!(toml::from_str::<Queue<u32>>(past_end).is_err());
+    }
+
+    #[test]
+    fn test_sort_by() {
+        let mut queue = Queue::new(RepeatMode::All);
+        queue.items = vec![4, 1, 3, 2];
+        assert_eq!(queue.next_item(), Some(&4));
+        queue.sort_by(|a, b| a.cmp(b));
+        assert_eq!(&queue.items, &[1, 2, 3, 4]);
+        assert_eq!(queue.current(), Some(&4));
+        assert_eq!(queue.next_item(), Some(&1));
+    }
 }
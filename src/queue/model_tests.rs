@@ -0,0 +1,505 @@
+//! Checks [`Queue`] against a simple reference model with randomly generated operations.
+//!
+//! The model tracks a [`Cursor`] instead of an index and the `has_advanced` flag,
+//! and implements every operation in the most direct way, without the optimizations the real queue uses.
+//! After every operation, the return values, items, [`Queue::index`] and [`Queue::current`] of both have to match,
+//! and the real queue has to uphold the invariants documented on [`Queue`].
+
+use quickcheck::{Arbitrary, Gen, QuickCheck};
+
+use super::*;
+
+/// Where the model queue is.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Cursor {
+    /// Nothing was returned since the last jump, the next item is (before repeat rules) at this position.
+    Before(usize),
+    /// The item at this position was the last returned one.
+    At(usize),
+}
+
+#[derive(Clone, Debug)]
+struct Model {
+    items: Vec<u8>,
+    cursor: Cursor,
+    repeat_mode: RepeatMode,
+}
+
+impl Model {
+    fn new(repeat_mode: RepeatMode) -> Self {
+        Self {
+            items: Vec::new(),
+            cursor: Cursor::Before(0),
+            repeat_mode,
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self.cursor {
+            Cursor::Before(i) | Cursor::At(i) => i,
+        }
+    }
+
+    fn current(&self) -> Option<&u8> {
+        self.items.get(self.index())
+    }
+
+    fn next_item(&mut self) -> Option<u8> {
+        if self.items.is_empty() {
+            return None;
+        }
+        let target = match (self.repeat_mode, self.cursor) {
+            (RepeatMode::Single, Cursor::At(i)) => i,
+            (_, Cursor::At(i)) => i + 1,
+            (_, Cursor::Before(i)) => i,
+        };
+        let target = match self.repeat_mode {
+            RepeatMode::Off => target,
+            _ => target % self.items.len(),
+        };
+        if target < self.items.len() {
+            self.cursor = Cursor::At(target);
+            Some(self.items[target])
+        } else {
+            self.cursor = Cursor::Before(self.items.len());
+            None
+        }
+    }
+
+    fn jump(&mut self, n: usize) -> bool {
+        if n > self.items.len() {
+            return false;
+        }
+        self.cursor = Cursor::Before(n);
+        true
+    }
+
+    fn skip(&mut self, n: usize) {
+        let upcoming = match self.cursor {
+            Cursor::At(i) => i + 1,
+            Cursor::Before(i) => i,
+        };
+        let len = self.items.len();
+        self.cursor = Cursor::Before(if len == 0 {
+            0
+        } else if self.repeat_mode == RepeatMode::Off {
+            (upcoming + n).min(len)
+        } else {
+            (upcoming + n) % len
+        });
+    }
+
+    fn rewind(&mut self, n: usize) {
+        let index = self.index();
+        let len = self.items.len();
+        self.cursor = Cursor::Before(if len == 0 {
+            0
+        } else if n <= index {
+            index - n
+        } else if self.repeat_mode == RepeatMode::Off {
+            0
+        } else {
+            (index as isize - n as isize).rem_euclid(len as isize) as usize
+        });
+    }
+
+    fn insert(&mut self, position: usize, item: u8) {
+        let len = self.items.len();
+        self.items.insert(position, item);
+        self.cursor = match self.cursor {
+            Cursor::At(i) if position <= i => Cursor::At(i + 1),
+            Cursor::Before(i) if position < i || (position == i && i < len) => {
+                Cursor::Before(i + 1)
+            }
+            cursor => cursor,
+        };
+    }
+
+    /// Remove every item at a position in `removed`.
+    fn remove_positions(&mut self, removed: &[usize]) -> Vec<u8> {
+        let shift = |i: usize| i - removed.iter().filter(|r| **r < i).count();
+        self.cursor = match self.cursor {
+            Cursor::At(i) if removed.contains(&i) => Cursor::Before(shift(i)),
+            Cursor::At(i) => Cursor::At(shift(i)),
+            Cursor::Before(i) => Cursor::Before(shift(i)),
+        };
+        let mut taken = Vec::new();
+        let mut kept = Vec::new();
+        for (i, item) in self.items.drain(..).enumerate() {
+            if removed.contains(&i) {
+                taken.push(item);
+            } else {
+                kept.push(item);
+            }
+        }
+        self.items = kept;
+        taken
+    }
+
+    /// Reorder the items so the item at position `order[i]` ends up at `i`.
+    fn permute(&mut self, order: &[usize]) {
+        let new_position = |i: usize| order.iter().position(|o| *o == i).unwrap();
+        self.cursor = match self.cursor {
+            Cursor::At(i) => Cursor::At(new_position(i)),
+            Cursor::Before(i) if i < self.items.len() => Cursor::Before(new_position(i)),
+            cursor => cursor,
+        };
+        self.items = order.iter().map(|i| self.items[*i]).collect();
+    }
+
+    fn move_range(&mut self, start: usize, end: usize, to: usize) -> bool {
+        let len = self.items.len();
+        if end > len || start > end || to > len - (end - start) {
+            return false;
+        }
+        let mut order: Vec<usize> = (0..len).filter(|i| !(start..end).contains(i)).collect();
+        for (offset, i) in (start..end).enumerate() {
+            order.insert(to + offset, i);
+        }
+        self.permute(&order);
+        true
+    }
+
+    fn replace_items(&mut self, items: Vec<u8>) {
+        let new_position = self
+            .current()
+            .and_then(|current| items.iter().position(|item| item == current));
+        self.cursor = match (self.cursor, new_position) {
+            (Cursor::At(_), Some(i)) => Cursor::At(i),
+            (Cursor::Before(_), Some(i)) => Cursor::Before(i),
+            (_, None) => Cursor::Before(self.index().min(items.len())),
+        };
+        self.items = items;
+    }
+}
+
+/// An operation applied to both the real and the model queue.
+#[derive(Clone, Debug)]
+enum Op {
+    NextItem,
+    Push(u8),
+    Insert(usize, u8),
+    Remove(usize),
+    Clear,
+    Jump(usize),
+    Skip(usize),
+    Rewind(usize),
+    SetRepeatMode(RepeatMode),
+    MoveItem(usize, usize),
+    MoveRange(usize, usize, usize),
+    RemoveRange(usize, usize),
+    /// Keep items that aren't a multiple of `n`
+    Retain(u8),
+    Dedup,
+    Sort,
+    ReplaceItems(Vec<u8>),
+}
+
+impl Arbitrary for RepeatMode {
+    fn arbitrary(g: &mut Gen) -> Self {
+        *g.choose(&[RepeatMode::Off, RepeatMode::Single, RepeatMode::All])
+            .unwrap()
+    }
+}
+
+impl Arbitrary for Op {
+    fn arbitrary(g: &mut Gen) -> Self {
+        // Small values, so positions and items are likely to collide
+        let mut small = || usize::arbitrary(g) % 8;
+        let (a, b, c) = (small(), small(), small());
+        let item = (a * 3 + b) as u8 % 5;
+        // `next_item` is the most common operation in practice, so it's picked more often
+        match u8::arbitrary(g) % 20 {
+            0..=4 => Op::NextItem,
+            5 | 6 => Op::Push(item),
+            7 => Op::Insert(a, item),
+            8 => Op::Remove(a),
+            9 => Op::Clear,
+            10 => Op::Jump(a),
+            11 => Op::Skip(a),
+            12 => Op::Rewind(a),
+            13 => Op::SetRepeatMode(RepeatMode::arbitrary(g)),
+            14 => Op::MoveItem(a, b),
+            15 => Op::MoveRange(a, b, c),
+            16 => Op::RemoveRange(a, b),
+            17 => Op::Retain(a as u8 % 3 + 2),
+            18 => match b % 2 {
+                0 => Op::Dedup,
+                _ => Op::Sort,
+            },
+            _ => Op::ReplaceItems(Vec::<u8>::arbitrary(g).iter().map(|n| n % 5).collect()),
+        }
+    }
+}
+
+/// Check the invariants documented on [`Queue`].
+fn check_invariants(queue: &Queue<u8>) -> Result<(), String> {
+    if queue.index > queue.items.len() {
+        return Err(format!(
+            "index {} is past the end of {} items",
+            queue.index,
+            queue.items.len()
+        ));
+    }
+    if queue.has_advanced && queue.index >= queue.items.len() {
+        return Err(format!(
+            "queue has advanced but index {} has no item",
+            queue.index
+        ));
+    }
+    Ok(())
+}
+
+/// Apply `op` to both queues, returning a description of the divergence if there was one.
+fn apply(queue: &mut Queue<u8>, model: &mut Model, op: &Op) -> Result<(), String> {
+    let len = queue.items.len();
+    let (real, expected) = match op.clone() {
+        Op::NextItem => (
+            format!("{:?}", queue.next_item().copied()),
+            format!("{:?}", model.next_item()),
+        ),
+        Op::Push(item) => {
+            queue.push(item);
+            model.items.push(item);
+            Default::default()
+        }
+        Op::Insert(i, item) => {
+            let i = i % (len + 1);
+            queue.insert(i, item);
+            model.insert(i, item);
+            Default::default()
+        }
+        Op::Remove(i) => {
+            if len > 0 {
+                queue.remove(i % len);
+                model.remove_positions(&[i % len]);
+            }
+            Default::default()
+        }
+        Op::Clear => {
+            queue.clear();
+            model.items.clear();
+            model.cursor = Cursor::Before(0);
+            Default::default()
+        }
+        Op::Jump(n) => (
+            format!("{}", queue.jump(n).is_ok()),
+            format!("{}", model.jump(n)),
+        ),
+        Op::Skip(n) => {
+            queue.skip(n);
+            model.skip(n);
+            Default::default()
+        }
+        Op::Rewind(n) => {
+            queue.rewind(n);
+            model.rewind(n);
+            Default::default()
+        }
+        Op::SetRepeatMode(repeat_mode) => {
+            queue.repeat_mode = repeat_mode;
+            model.repeat_mode = repeat_mode;
+            Default::default()
+        }
+        Op::MoveItem(from, to) => {
+            let expected = from < len && to < len && model.move_range(from, from + 1, to);
+            (
+                format!("{}", queue.move_item(from, to).is_ok()),
+                format!("{}", expected),
+            )
+        }
+        Op::MoveRange(start, end, to) => (
+            format!("{}", queue.move_range(start..end, to).is_ok()),
+            format!("{}", model.move_range(start, end, to)),
+        ),
+        Op::RemoveRange(start, end) => {
+            let expected = if end > len || start > end {
+                None
+            } else {
+                Some(model.remove_positions(&(start..end).collect::<Vec<_>>()))
+            };
+            (
+                format!("{:?}", queue.remove_range(start..end).ok()),
+                format!("{:?}", expected),
+            )
+        }
+        Op::Retain(n) => {
+            queue.retain(|item| !item.is_multiple_of(n));
+            let removed: Vec<usize> = (0..len)
+                .filter(|i| model.items[*i].is_multiple_of(n))
+                .collect();
+            model.remove_positions(&removed);
+            Default::default()
+        }
+        Op::Dedup => {
+            queue.dedup_by(|a, b| a == b);
+            let mut removed = Vec::new();
+            let mut last_kept = 0;
+            for i in 1..len {
+                if model.items[i] == model.items[last_kept] {
+                    removed.push(i);
+                } else {
+                    last_kept = i;
+                }
+            }
+            model.remove_positions(&removed);
+            Default::default()
+        }
+        Op::Sort => {
+            queue.sort_by(|a, b| a.cmp(b));
+            let mut order: Vec<usize> = (0..len).collect();
+            order.sort_by_key(|i| model.items[*i]);
+            model.permute(&order);
+            Default::default()
+        }
+        Op::ReplaceItems(items) => {
+            queue.replace_items(items.clone());
+            model.replace_items(items);
+            Default::default()
+        }
+    };
+    if real != expected {
+        return Err(format!("returned {real}, expected {expected}"));
+    }
+    if queue.items != model.items {
+        return Err(format!(
+            "items are {:?}, expected {:?}",
+            queue.items, model.items
+        ));
+    }
+    if queue.index() != model.index() {
+        return Err(format!(
+            "index is {}, expected {}",
+            queue.index(),
+            model.index()
+        ));
+    }
+    if queue.current() != model.current() {
+        return Err(format!(
+            "current is {:?}, expected {:?}",
+            queue.current(),
+            model.current()
+        ));
+    }
+    let mut copy = queue.clone();
+    let expected: Vec<u8> = (0..5).map_while(|_| copy.next_item().copied()).collect();
+    let upcoming: Vec<u8> = queue.upcoming().take(5).copied().collect();
+    if upcoming != expected {
+        return Err(format!("upcoming is {upcoming:?}, expected {expected:?}"));
+    }
+    check_invariants(queue)
+}
+
+/// Apply every operation to a new real and model queue, failing at the first divergence.
+fn run_ops(repeat_mode: RepeatMode, ops: &[Op]) -> Result<(), String> {
+    let mut queue = Queue::new(repeat_mode);
+    let mut model = Model::new(repeat_mode);
+    for (i, op) in ops.iter().enumerate() {
+        apply(&mut queue, &mut model, op).map_err(|e| format!("op {i} ({op:?}): {e}"))?;
+    }
+    Ok(())
+}
+
+/// Run the generated operations with a fixed repeat mode, ignoring `SetRepeatMode`.
+fn fixed_repeat_mode(repeat_mode: RepeatMode, ops: Vec<Op>) -> bool {
+    let ops: Vec<Op> = ops
+        .into_iter()
+        .filter(|op| !matches!(op, Op::SetRepeatMode(_)))
+        .collect();
+    run_ops(repeat_mode, &ops).is_ok()
+}
+
+fn quickcheck() -> QuickCheck {
+    QuickCheck::new().tests(2000).rng(Gen::new(60))
+}
+
+#[test]
+fn model_repeat_off() {
+    fn prop(ops: Vec<Op>) -> bool {
+        fixed_repeat_mode(RepeatMode::Off, ops)
+    }
+    quickcheck().quickcheck(prop as fn(Vec<Op>) -> bool);
+}
+
+#[test]
+fn model_repeat_single() {
+    fn prop(ops: Vec<Op>) -> bool {
+        fixed_repeat_mode(RepeatMode::Single, ops)
+    }
+    quickcheck().quickcheck(prop as fn(Vec<Op>) -> bool);
+}
+
+#[test]
+fn model_repeat_all() {
+    fn prop(ops: Vec<Op>) -> bool {
+        fixed_repeat_mode(RepeatMode::All, ops)
+    }
+    quickcheck().quickcheck(prop as fn(Vec<Op>) -> bool);
+}
+
+#[test]
+fn model_changing_repeat_mode() {
+    fn prop(repeat_mode: RepeatMode, ops: Vec<Op>) -> bool {
+        run_ops(repeat_mode, &ops).is_ok()
+    }
+    quickcheck().quickcheck(prop as fn(RepeatMode, Vec<Op>) -> bool);
+}
+
+/// Divergences found by the model, kept as regular tests.
+#[test]
+fn model_regressions() {
+    let cases: &[(RepeatMode, &[Op])] = &[
+        // Pushing after the queue ran out has to play the new item
+        (
+            RepeatMode::Off,
+            &[
+                Op::Push(1),
+                Op::NextItem,
+                Op::NextItem,
+                Op::Push(2),
+                Op::NextItem,
+            ],
+        ),
+        // Removing the current item must not skip the one after it
+        (
+            RepeatMode::All,
+            &[
+                Op::Push(1),
+                Op::Push(2),
+                Op::Push(3),
+                Op::NextItem,
+                Op::Remove(0),
+                Op::NextItem,
+            ],
+        ),
+        // Appending with `insert` after the queue ran out has to play the new item
+        (
+            RepeatMode::Off,
+            &[
+                Op::Push(1),
+                Op::NextItem,
+                Op::NextItem,
+                Op::Insert(1, 2),
+                Op::NextItem,
+            ],
+        ),
+        // Rewinding further than the queue is long must not overflow
+        (RepeatMode::All, &[Op::Push(1), Op::Push(2), Op::Rewind(5)]),
+        // Rewinding past the start doesn't wrap without repeat
+        (
+            RepeatMode::Off,
+            &[
+                Op::Push(1),
+                Op::Push(2),
+                Op::Push(3),
+                Op::Rewind(1),
+                Op::NextItem,
+            ],
+        ),
+    ];
+    for (repeat_mode, ops) in cases {
+        if let Err(e) = run_ops(*repeat_mode, ops) {
+            panic!("{repeat_mode:?} {ops:?}: {e}");
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::fmt::Debug;
+
+use crate::{playback::Song, tags::Tags};
+
+/// A source of songs for autoplay, used by a [`Player`] when its queue runs out.
+///
+/// See [`Player::set_autoplay`].
+///
+/// [`Player`]: crate::playback::Player
+/// [`Player::set_autoplay`]: crate::playback::Player::set_autoplay
+pub trait SimilaritySource: Send + Debug {
+    /// Return up to `limit` songs similar to `seed`, most similar first.
+    ///
+    /// Songs for which `exclude` returns true (recently played or already queued) should not be returned.
+    fn similar_songs(
+        &mut self,
+        seed: &Song,
+        exclude: &dyn Fn(&Song) -> bool,
+        limit: usize,
+    ) -> Vec<Song>;
+}
+
+/// A [`SimilaritySource`] picking songs from a list of local songs.
+///
+/// Songs are ranked by the tags they share with the seed: the album, the artist or album artist, the genre,
+/// a close year and a close BPM. Songs with the same tag score are ranked by how close they are to the seed
+/// in the directory tree, so with a `Artist/Album/song.mp3` layout songs from the same album come first,
+/// then ones from the same artist, which also ranks songs without tags.
+/// Songs that aren't related to the seed at all are never returned.
+#[derive(Clone, Debug, Default)]
+pub struct LibraryRadio {
+    songs: Vec<Song>,
+}
+
+impl LibraryRadio {
+    pub fn new(songs: Vec<Song>) -> Self {
+        Self { songs }
+    }
+
+    pub fn songs(&self) -> &[Song] {
+        &self.songs
+    }
+
+    /// How similar `song` is to `seed`, by their tags and then their directories, `(0, 0)` means they aren't related.
+    fn score(seed: &Song, song: &Song) -> (u32, u32) {
+        (
+            Self::tag_score(seed.tags(), song.tags()),
+            Self::directory_score(seed, song),
+        )
+    }
+
+    fn tag_score(seed: &Tags, tags: &Tags) -> u32 {
+        let artist = |tags: &Tags| tags.album_artist.clone().or_else(|| tags.artist.clone());
+        let mut score = 0;
+        if same_text(&seed.album, &tags.album) && same_text(&artist(seed), &artist(tags)) {
+            score += 8;
+        }
+        if same_text(&seed.artist, &tags.artist)
+            || same_text(&seed.album_artist, &tags.album_artist)
+        {
+            score += 4;
+        }
+        if same_text(&seed.genre, &tags.genre) {
+            score += 2;
+        }
+        if seed
+            .year
+            .zip(tags.year)
+            .is_some_and(|(a, b)| a.abs_diff(b) <= 3)
+        {
+            score += 1;
+        }
+        // Within about 5%, so the tempo doesn't jump
+        if seed
+            .bpm
+            .zip(tags.bpm)
+            .is_some_and(|(a, b)| a.abs_diff(b) * 20 <= a.max(b))
+        {
+            score += 1;
+        }
+        score
+    }
+
+    fn directory_score(seed: &Song, song: &Song) -> u32 {
+        let album = seed.path().parent();
+        let artist = album.and_then(|album| album.parent());
+        if album.is_some() && song.path().parent() == album {
+            2
+        } else if artist.is_some() && song.path().parent().and_then(|p| p.parent()) == artist {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+/// Whether both tags are set and equal, ignoring case and surrounding whitespace.
+fn same_text(a: &Option<String>, b: &Option<String>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.trim().to_lowercase() == b.trim().to_lowercase(),
+        _ => false,
+    }
+}
+
+impl SimilaritySource for LibraryRadio {
+    fn similar_songs(
+        &mut self,
+        seed: &Song,
+        exclude: &dyn Fn(&Song) -> bool,
+        limit: usize,
+    ) -> Vec<Song> {
+        let mut scored: Vec<((u32, u32), &Song)> = self
+            .songs
+            .iter()
+            .filter(|song| song.path() != seed.path() && !exclude(song))
+            .map(|song| (Self::score(seed, song), song))
+            .filter(|(score, _)| *score > (0, 0))
+            .collect();
+        // Stable, so songs with the same score keep the library order
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        scored
+            .into_iter()
+            .take(limit)
+            .map(|(_, song)| song.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use super::*;
+
+    fn song(path: &str) -> Song {
+        Song::new(0, path.into(), PathBuf::from(path), Duration::ZERO)
+    }
+
+    #[test]
+    fn test_library_radio() {
+        let mut radio = LibraryRadio::new(vec![
+            song("/music/other/album/1.mp3"),
+            song("/music/artist/single/1.mp3"),
+            song("/music/artist/album/1.mp3"),
+            song("/music/artist/album/2.mp3"),
+            song("/music/artist/album/3.mp3"),
+        ]);
+        let seed = song("/music/artist/album/1.mp3");
+        let exclude = |song: &Song| song.path().ends_with("album/2.mp3");
+        let similar: Vec<PathBuf> = radio
+            .similar_songs(&seed, &exclude, 10)
+            .iter()
+            .map(|song| song.path().to_path_buf())
+            .collect();
+        assert_eq!(
+            similar,
+            vec![
+                PathBuf::from("/music/artist/album/3.mp3"),
+                PathBuf::from("/music/artist/single/1.mp3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tag_score() {
+        let tagged = |path: &str, artist: &str, album: &str, genre: &str, bpm: u32| {
+            song(path).with_tags(Tags {
+                artist: Some(artist.into()),
+                album: Some(album.into()),
+                genre: Some(genre.into()),
+                bpm: Some(bpm),
+                ..Default::default()
+            })
+        };
+        let mut radio = LibraryRadio::new(vec![
+            tagged("/downloads/a.mp3", "Other", "Else", "Jazz", 90),
+            tagged("/downloads/b.mp3", "Other", "Else", "rock ", 122),
+            tagged("/downloads/c.mp3", "artist", "Single", "Rock", 60),
+            tagged("/library/music/d.mp3", "Artist", "Album", "Rock", 120),
+            song("/library/music/e.mp3"),
+        ]);
+        let seed = tagged("/library/music/seed.mp3", "Artist", "Album", "Rock", 120);
+        let similar: Vec<PathBuf> = radio
+            .similar_songs(&seed, &|_| false, 10)
+            .iter()
+            .map(|song| song.path().to_path_buf())
+            .collect();
+        assert_eq!(
+            similar,
+            vec![
+                PathBuf::from("/library/music/d.mp3"),
+                PathBuf::from("/downloads/c.mp3"),
+                PathBuf::from("/downloads/b.mp3"),
+                PathBuf::from("/library/music/e.mp3"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use tracing::debug;
+
+use crate::{
+    cue::{self, CueSheet},
+    library::Library,
+    playback::{is_supported, Song},
+};
+
+/// Sent by a running scan, see [`Scanner::start`].
+#[derive(Debug)]
+pub enum ScanEvent {
+    /// A song was read, or found unchanged in the library.
+    ///
+    /// A cue sheet sends a song for each of its tracks.
+    Song(Box<Song>),
+    /// A file or directory couldn't be read.
+    Error { path: PathBuf, error: String },
+    /// How many files were read so far, out of how many supported files were found so far.
+    Progress { scanned: usize, found: usize },
+    /// The scan is done, or stopped early because of [`ScanHandle::cancel`].
+    ///
+    /// This is always the last event.
+    Finished { cancelled: bool },
+}
+
+/// Walks directory trees in the background, reading every file with one of the [`SUPPORTED_EXTENSIONS`] into a [`Song`].
+///
+/// Cue sheets are read into a song for each of their tracks, and the audio files they split aren't read on their own.
+///
+/// Symlinks are followed, and every directory is only walked once, so symlink loops are harmless.
+///
+/// # Examples
+///
+/// ```no_run
+/// use amuseing::{library::Library, scanner::{ScanEvent, Scanner}};
+///
+/// let (_handle, events) = Scanner::new(["/home/me/Music".into()])
+///     .ignore(".*")
+///     .start(Library::default());
+/// for event in events {
+///     if let ScanEvent::Song(song) = event {
+///         println!("{}", song.title());
+///     }
+/// }
+/// ```
+///
+/// [`SUPPORTED_EXTENSIONS`]: crate::playback::SUPPORTED_EXTENSIONS
+#[derive(Clone, Debug)]
+pub struct Scanner {
+    roots: Vec<PathBuf>,
+    ignore: Vec<String>,
+    threads: usize,
+}
+
+impl Scanner {
+    /// Create a scanner for the directories in `roots`, reading files on as many threads as the system has cores.
+    pub fn new(roots: impl IntoIterator<Item = PathBuf>) -> Self {
+        Self {
+            roots: roots.into_iter().collect(),
+            ignore: Vec::new(),
+            threads: thread::available_parallelism().map_or(4, |n| n.get()),
+        }
+    }
+
+    /// Skip files and directories whose name matches `pattern`, where `*` matches any characters and `?` matches one.
+    pub fn ignore(mut self, pattern: impl Into<String>) -> Self {
+        self.ignore.push(pattern.into());
+        self
+    }
+
+    /// Set how many threads read files, at least 1.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Start scanning in the background, the [`ScanEvent`]s are sent to the returned receiver.
+    ///
+    /// Songs that didn't change since they were read into `cache` aren't read again.
+    pub fn start(self, cache: Library) -> (ScanHandle, Receiver<ScanEvent>) {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (event_tx, event_rx) = mpsc::channel();
+        let thread = {
+            let cancel = cancel.clone();
+            thread::spawn(move || self.run(&cache, &cancel, event_tx))
+        };
+        (ScanHandle::new(cancel, thread), event_rx)
+    }
+
+    fn run(&self, cache: &Library, cancel: &AtomicBool, events: Sender<ScanEvent>) {
+        let (path_tx, path_rx) = mpsc::channel::<PathBuf>();
+        let path_rx = Mutex::new(path_rx);
+        let found = AtomicUsize::new(0);
+        let scanned = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for _ in 0..self.threads {
+                let events = events.clone();
+                let (path_rx, found, scanned) = (&path_rx, &found, &scanned);
+                scope.spawn(move || loop {
+                    let Ok(path) = path_rx.lock().unwrap().recv() else {
+                        break;
+                    };
+                    if cancel.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    if cue::is_cue_sheet(&path) {
+                        let songs = cache
+                            .cached_sheet(&path)
+                            .map_or_else(|| cue::read_songs(&path, cache.artwork_dir()), Ok);
+                        match songs {
+                            Ok(songs) => {
+                                for song in songs {
+                                    let _ = events.send(ScanEvent::Song(Box::new(song)));
+                                }
+                            }
+                            Err(e) => {
+                                let _ = events.send(ScanEvent::Error {
+                                    path,
+                                    error: e.to_string(),
+                                });
+                            }
+                        }
+                    } else {
+                        let event = match cache.cached(&path) {
+                            Some(song) => ScanEvent::Song(Box::new(song)),
+                            None => match Song::from_file(path.clone(), cache.artwork_dir()) {
+                                Ok(song) => ScanEvent::Song(Box::new(song)),
+                                Err(e) => ScanEvent::Error {
+                                    path,
+                                    error: e.to_string(),
+                                },
+                            },
+                        };
+                        let _ = events.send(event);
+                    }
+                    let _ = events.send(ScanEvent::Progress {
+                        scanned: scanned.fetch_add(1, Ordering::Relaxed) + 1,
+                        found: found.load(Ordering::Relaxed),
+                    });
+                });
+            }
+            self.walk(cancel, path_tx, &events, &found);
+        });
+        let cancelled = cancel.load(Ordering::Relaxed);
+        debug!(
+            "Scan finished, read {} of {} files",
+            scanned.into_inner(),
+            found.into_inner()
+        );
+        let _ = events.send(ScanEvent::Finished { cancelled });
+    }
+
+    /// Walk the directory trees, sending the paths of supported files and cue sheets to `paths` until done or cancelled.
+    ///
+    /// Files split by a cue sheet in the same directory are skipped, since the sheet's tracks are read instead.
+    fn walk(
+        &self,
+        cancel: &AtomicBool,
+        paths: Sender<PathBuf>,
+        events: &Sender<ScanEvent>,
+        found: &AtomicUsize,
+    ) {
+        let mut visited = HashSet::new();
+        let mut stack = self.roots.clone();
+        while let Some(dir) = stack.pop() {
+            if cancel.load(Ordering::Relaxed) {
+                return;
+            }
+            let entries = match dir.canonicalize().and_then(|canonical| {
+                let entries = canonical.read_dir()?;
+                Ok((canonical, entries))
+            }) {
+                Ok((canonical, entries)) => {
+                    if !visited.insert(canonical) {
+                        continue;
+                    }
+                    entries
+                }
+                Err(e) => {
+                    let _ = events.send(ScanEvent::Error {
+                        path: dir,
+                        error: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+            let mut paths_in_dir = Vec::new();
+            for entry in entries {
+                match entry {
+                    Ok(entry) if !self.is_ignored(&entry.path()) => paths_in_dir.push(entry.path()),
+                    Ok(_) => {}
+                    Err(e) => {
+                        let _ = events.send(ScanEvent::Error {
+                            path: dir.clone(),
+                            error: e.to_string(),
+                        });
+                    }
+                }
+            }
+            let split_files: HashSet<PathBuf> = paths_in_dir
+                .iter()
+                .filter(|path| cue::is_cue_sheet(path))
+                .filter_map(|path| CueSheet::from_path(path).ok())
+                .flat_map(|sheet| sheet.tracks.into_iter().map(|track| track.file))
+                .collect();
+            for path in paths_in_dir {
+                if path.is_dir() {
+                    stack.push(path);
+                } else if cue::is_cue_sheet(&path)
+                    || (is_supported(&path) && !split_files.contains(&path))
+                {
+                    found.fetch_add(1, Ordering::Relaxed);
+                    if paths.send(path).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_ignored(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            return false;
+        };
+        self.ignore
+            .iter()
+            .any(|pattern| wildcard_match(pattern, name))
+    }
+}
+
+/// A running scan, see [`Scanner::start`].
+#[derive(Debug)]
+pub struct ScanHandle {
+    cancel: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ScanHandle {
+    pub(crate) fn new(cancel: Arc<AtomicBool>, thread: JoinHandle<()>) -> Self {
+        Self {
+            cancel,
+            thread: Some(thread),
+        }
+    }
+
+    /// Stop the scan, files that are being read are finished first.
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_none_or(|thread| thread.is_finished())
+    }
+
+    /// Wait for the scan to finish.
+    pub fn join(mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Check if `name` matches `pattern`, where `*` matches any characters and `?` matches one.
+pub(crate) fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where to retry from if the last `*` should have matched more characters
+    let mut retry = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            retry = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = retry {
+            p = star + 1;
+            n = matched + 1;
+            retry = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.tmp", "song.tmp"));
+        assert!(wildcard_match(".*", ".hidden"));
+        assert!(wildcard_match("a*b*c", "aXXbYYc"));
+        assert!(wildcard_match("so?g", "song"));
+        assert!(!wildcard_match("*.tmp", "song.mp3"));
+        assert!(!wildcard_match("so?g", "sog"));
+    }
+
+    #[test]
+    fn test_scan() {
+        let dir = std::env::temp_dir().join("amuseing-test-scan");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("artist/album")).unwrap();
+        fs::create_dir_all(dir.join(".hidden")).unwrap();
+        fs::write(dir.join("artist/album/1.mp3"), b"not audio").unwrap();
+        fs::write(dir.join("artist/cover.jpg"), b"").unwrap();
+        fs::write(dir.join(".hidden/2.mp3"), b"not audio").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&dir, dir.join("artist/loop")).unwrap();
+
+        let (handle, events) = Scanner::new([dir.clone()])
+            .ignore(".*")
+            .threads(2)
+            .start(Library::default());
+        let events: Vec<ScanEvent> = events.iter().collect();
+        handle.join();
+        let errors: Vec<&PathBuf> = events
+            .iter()
+            .filter_map(|event| match event {
+                ScanEvent::Error { path, .. } => Some(path),
+                _ => None,
+            })
+            .collect();
+        // Only the one supported file is found, once, and fails to be read
+        assert_eq!(errors, vec![&dir.join("artist/album/1.mp3")]);
+        assert!(matches!(
+            events.last(),
+            Some(ScanEvent::Finished { cancelled: false })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
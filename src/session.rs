@@ -0,0 +1,103 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::{config::Config, errors::SessionError, queue::Queue};
+
+/// A queue saved in a [`Session`], see [`Player::session`].
+///
+/// The songs are stored as their paths, and read again when the session is restored.
+///
+/// [`Player::session`]: crate::playback::Player::session
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SavedQueue {
+    pub name: String,
+    #[serde(flatten)]
+    pub queue: Queue<PathBuf>,
+    /// How far into the current song playback was.
+    pub position_millis: u64,
+}
+
+/// The state of the player that's kept between runs of the app.
+///
+/// Sessions are stored in `session.toml`, next to the config file.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Session {
+    /// The name of the queue that was being played.
+    pub active_queue: String,
+    #[serde(rename = "queue")]
+    #[serde(default)]
+    pub queues: Vec<SavedQueue>,
+}
+
+impl Session {
+    /// Gets the default session path (`session.toml` in [`Config::default_path`]).
+    pub fn default_path() -> PathBuf {
+        let mut path = Config::default_path();
+        path.push("session.toml");
+        path
+    }
+
+    /// Get the session from the [`default_path`].
+    ///
+    /// [`default_path`]: Self::default_path
+    pub fn from_default_path() -> Result<Self, SessionError> {
+        Self::from_path(&Self::default_path())
+    }
+
+    /// Get the session from the file at `path`.
+    pub fn from_path(path: &Path) -> Result<Self, SessionError> {
+        let toml_str = fs::read_to_string(path)?;
+        Ok(toml::from_str(&toml_str).inspect_err(|e| error!("Error parsing session file: {e}"))?)
+    }
+
+    /// Try to write the session to the [`default_path`], creating the directory if it doesn't exist.
+    ///
+    /// [`default_path`]: Self::default_path
+    pub fn write(&self) -> Result<(), SessionError> {
+        self.write_to(&Self::default_path())
+    }
+
+    /// Try to write the session to `path`, creating the parent directory if it doesn't exist.
+    pub fn write_to(&self, path: &Path) -> Result<(), SessionError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(fs::write(path, toml::to_string_pretty(self)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_round_trip() {
+        let mut queue = Queue::builder()
+            .items([PathBuf::from("a.mp3"), PathBuf::from("b.mp3")])
+            .build()
+            .unwrap();
+        queue.next_item();
+        let session = Session {
+            active_queue: "Default".into(),
+            queues: vec![SavedQueue {
+                name: "Default".into(),
+                queue,
+                position_millis: 1500,
+            }],
+        };
+        let serialized = toml::to_string_pretty(&session).unwrap();
+        let deserialized: Session = toml::from_str(&serialized).unwrap();
+        let saved = &deserialized.queues[0];
+        assert_eq!(saved.name, "Default");
+        assert_eq!(saved.position_millis, 1500);
+        assert_eq!(saved.queue.items(), session.queues[0].queue.items());
+        assert_eq!(saved.queue.current(), Some(&PathBuf::from("a.mp3")));
+    }
+}
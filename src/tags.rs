@@ -0,0 +1,119 @@
+mod edit;
+mod mp4;
+mod vorbis;
+mod write;
+
+use serde::{Deserialize, Serialize};
+use symphonia::core::meta::{MetadataRevision, StandardTagKey, Value};
+
+pub use edit::{apply_edits, file_name_from_tags, tags_from_file_name, TagEdit, TagField};
+pub use write::write_tags;
+
+/// Tags read from a song's file, see [`Song::tags`].
+///
+/// The tags are read through Symphonia, so ID3v2 (mp3), Vorbis comments (flac, ogg) and MP4 atoms (m4a) are all supported.
+/// Any tag missing from the file is None.
+///
+/// [`Song::tags`]: crate::playback::Song::tags
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    pub composer: Option<String>,
+    pub comment: Option<String>,
+    /// Beats per minute.
+    pub bpm: Option<u32>,
+}
+
+impl Tags {
+    /// Fill in the tags found in `revision`, without overwriting tags that are already set.
+    pub fn add_revision(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            if let Some(key) = tag.std_key {
+                self.add(key, &tag.value);
+            }
+        }
+    }
+
+    /// Set the tag for `key` from `value` if it isn't set yet, ignoring keys that aren't stored.
+    fn add(&mut self, key: StandardTagKey, value: &Value) {
+        let text = || Some(value.to_string().trim().to_string()).filter(|s| !s.is_empty());
+        match key {
+            StandardTagKey::TrackTitle => self.title = self.title.take().or_else(text),
+            StandardTagKey::Artist => self.artist = self.artist.take().or_else(text),
+            StandardTagKey::Album => self.album = self.album.take().or_else(text),
+            StandardTagKey::AlbumArtist => {
+                self.album_artist = self.album_artist.take().or_else(text)
+            }
+            StandardTagKey::Genre => self.genre = self.genre.take().or_else(text),
+            StandardTagKey::Composer => self.composer = self.composer.take().or_else(text),
+            StandardTagKey::Comment => self.comment = self.comment.take().or_else(text),
+            StandardTagKey::TrackNumber => {
+                self.track_number = self.track_number.or_else(|| leading_number(&text()?))
+            }
+            StandardTagKey::DiscNumber => {
+                self.disc_number = self.disc_number.or_else(|| leading_number(&text()?))
+            }
+            StandardTagKey::Bpm => self.bpm = self.bpm.or_else(|| leading_number(&text()?)),
+            StandardTagKey::Date | StandardTagKey::ReleaseDate | StandardTagKey::OriginalDate => {
+                self.year = self.year.or_else(|| leading_number(&text()?))
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Parse the number at the start of `s`, so "3/12" gives 3 and "2004-05-01" gives 2004.
+fn leading_number<N: std::str::FromStr>(s: &str) -> Option<N> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    s[..end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use symphonia::core::meta::{MetadataBuilder, Tag};
+
+    #[test]
+    fn test_add_revision() {
+        let mut builder = MetadataBuilder::new();
+        builder
+            .add_tag(Tag::new(
+                Some(StandardTagKey::TrackTitle),
+                "TIT2",
+                Value::from("Song"),
+            ))
+            .add_tag(Tag::new(
+                Some(StandardTagKey::TrackNumber),
+                "TRCK",
+                Value::from("3/12"),
+            ))
+            .add_tag(Tag::new(
+                Some(StandardTagKey::Date),
+                "TDRC",
+                Value::from("2004-05-01"),
+            ))
+            .add_tag(Tag::new(
+                Some(StandardTagKey::Artist),
+                "TPE1",
+                Value::from(" "),
+            ));
+        let mut tags = Tags {
+            title: Some("Already set".into()),
+            ..Default::default()
+        };
+        tags.add_revision(&builder.metadata());
+        assert_eq!(tags.title.as_deref(), Some("Already set"));
+        assert_eq!(tags.track_number, Some(3));
+        assert_eq!(tags.year, Some(2004));
+        assert_eq!(tags.artist, None);
+    }
+}
@@ -0,0 +1,292 @@
+use super::{leading_number, Tags};
+use crate::errors::TagPatternError;
+
+/// A tag that can be edited, see [`apply_edits`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TagField {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    TrackNumber,
+    DiscNumber,
+    Year,
+    Genre,
+    Composer,
+    Comment,
+    Bpm,
+}
+
+/// Set a tag to a value, or remove it if the value is None.
+pub type TagEdit = (TagField, Option<String>);
+
+impl TagField {
+    pub const ALL: [TagField; 11] = [
+        TagField::Title,
+        TagField::Artist,
+        TagField::Album,
+        TagField::AlbumArtist,
+        TagField::TrackNumber,
+        TagField::DiscNumber,
+        TagField::Year,
+        TagField::Genre,
+        TagField::Composer,
+        TagField::Comment,
+        TagField::Bpm,
+    ];
+
+    /// The name of the field in file name patterns, e.g. `{artist}`.
+    pub fn name(self) -> &'static str {
+        match self {
+            TagField::Title => "title",
+            TagField::Artist => "artist",
+            TagField::Album => "album",
+            TagField::AlbumArtist => "album-artist",
+            TagField::TrackNumber => "track",
+            TagField::DiscNumber => "disc",
+            TagField::Year => "year",
+            TagField::Genre => "genre",
+            TagField::Composer => "composer",
+            TagField::Comment => "comment",
+            TagField::Bpm => "bpm",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|field| field.name() == name)
+    }
+
+    /// Check if the field holds a number, so only the number at the start of a value is kept.
+    pub fn is_number(self) -> bool {
+        matches!(
+            self,
+            TagField::TrackNumber | TagField::DiscNumber | TagField::Year | TagField::Bpm
+        )
+    }
+
+    /// The value of the field in `tags` as text.
+    pub fn get(self, tags: &Tags) -> Option<String> {
+        match self {
+            TagField::Title => tags.title.clone(),
+            TagField::Artist => tags.artist.clone(),
+            TagField::Album => tags.album.clone(),
+            TagField::AlbumArtist => tags.album_artist.clone(),
+            TagField::TrackNumber => tags.track_number.map(|n| n.to_string()),
+            TagField::DiscNumber => tags.disc_number.map(|n| n.to_string()),
+            TagField::Year => tags.year.map(|year| year.to_string()),
+            TagField::Genre => tags.genre.clone(),
+            TagField::Composer => tags.composer.clone(),
+            TagField::Comment => tags.comment.clone(),
+            TagField::Bpm => tags.bpm.map(|bpm| bpm.to_string()),
+        }
+    }
+
+    /// Set the field in `tags` to `value`, like the tag was read from a file with that value.
+    ///
+    /// Blank values and values that don't start with a number for number fields remove the tag.
+    pub fn set(self, tags: &mut Tags, value: Option<&str>) {
+        let text = value
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(String::from);
+        let number = text.as_deref().and_then(leading_number::<u32>);
+        match self {
+            TagField::Title => tags.title = text,
+            TagField::Artist => tags.artist = text,
+            TagField::Album => tags.album = text,
+            TagField::AlbumArtist => tags.album_artist = text,
+            TagField::TrackNumber => tags.track_number = number,
+            TagField::DiscNumber => tags.disc_number = number,
+            TagField::Year => tags.year = text.as_deref().and_then(leading_number),
+            TagField::Genre => tags.genre = text,
+            TagField::Composer => tags.composer = text,
+            TagField::Comment => tags.comment = text,
+            TagField::Bpm => tags.bpm = number,
+        }
+    }
+
+    /// Check if a tag that's `value` in a file already has the field's value in `tags`, so it doesn't have to be rewritten.
+    ///
+    /// For example the track number "3/12" is kept if the track number is 3.
+    pub(crate) fn is_unchanged(self, value: &str, tags: &Tags) -> bool {
+        let mut read = Tags::default();
+        self.set(&mut read, Some(value));
+        self.get(&read) == self.get(tags)
+    }
+}
+
+/// Apply `edits` to `tags`, in order.
+pub fn apply_edits(tags: &mut Tags, edits: &[TagEdit]) {
+    for (field, value) in edits {
+        field.set(tags, value.as_deref());
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Field(TagField),
+}
+
+/// Split `pattern` into text and `{field}`s.
+fn parse_pattern(pattern: &str) -> Result<Vec<Segment>, TagPatternError> {
+    let mut segments = Vec::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            segments.push(Segment::Text(rest[..start].to_string()));
+        }
+        let end = rest[start..].find('}').ok_or(TagPatternError::Unclosed)? + start;
+        let name = &rest[start + 1..end];
+        let field = TagField::from_name(name)
+            .ok_or_else(|| TagPatternError::UnknownField(name.to_string()))?;
+        if matches!(segments.last(), Some(Segment::Field(_))) {
+            return Err(TagPatternError::AdjacentFields);
+        }
+        segments.push(Segment::Field(field));
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest.to_string()));
+    }
+    Ok(segments)
+}
+
+/// Read tags from a file name without its extension, with a `pattern` like `{track} - {artist} - {title}`.
+///
+/// Each field takes the shortest text that lets the rest of the name match, and number fields only match digits.
+/// Returns None if the name doesn't match the pattern.
+pub fn tags_from_file_name(
+    pattern: &str,
+    file_name: &str,
+) -> Result<Option<Vec<TagEdit>>, TagPatternError> {
+    let segments = parse_pattern(pattern)?;
+    let mut edits = Vec::new();
+    Ok(match_segments(&segments, file_name, &mut edits).then_some(edits))
+}
+
+fn match_segments(segments: &[Segment], name: &str, edits: &mut Vec<TagEdit>) -> bool {
+    let Some((segment, rest)) = segments.split_first() else {
+        return name.is_empty();
+    };
+    match segment {
+        Segment::Text(text) => name
+            .strip_prefix(text.as_str())
+            .is_some_and(|name| match_segments(rest, name, edits)),
+        Segment::Field(field) => {
+            let is_valid = |value: &str| {
+                !value.trim().is_empty()
+                    && (!field.is_number() || value.trim().chars().all(|c| c.is_ascii_digit()))
+            };
+            // A field is always followed by text or the end of the name
+            let ends: Vec<usize> = match rest.first() {
+                Some(Segment::Text(text)) => {
+                    name.match_indices(text.as_str()).map(|(i, _)| i).collect()
+                }
+                _ => vec![name.len()],
+            };
+            for end in ends {
+                let value = &name[..end];
+                if !is_valid(value) {
+                    continue;
+                }
+                edits.push((*field, Some(value.trim().to_string())));
+                if match_segments(rest, &name[end..], edits) {
+                    return true;
+                }
+                edits.pop();
+            }
+            false
+        }
+    }
+}
+
+/// Make a file name without an extension from `tags`, with a `pattern` like `{artist} - {title}`.
+///
+/// Track and disc numbers are padded to two digits, and characters that can't be in file names are replaced.
+/// Returns None if one of the pattern's tags isn't set.
+pub fn file_name_from_tags(pattern: &str, tags: &Tags) -> Result<Option<String>, TagPatternError> {
+    let mut name = String::new();
+    for segment in parse_pattern(pattern)? {
+        match segment {
+            Segment::Text(text) => name += &text,
+            Segment::Field(field) => {
+                let Some(value) = field.get(tags) else {
+                    return Ok(None);
+                };
+                match field {
+                    TagField::TrackNumber | TagField::DiscNumber => name += &format!("{value:0>2}"),
+                    _ => name += &value,
+                }
+            }
+        }
+    }
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let name = name.trim().trim_start_matches('.');
+    Ok((!name.is_empty()).then(|| name.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags_from_file_name() {
+        let edits = tags_from_file_name("{track} - {artist} - {title}", "03 - Artist - A - B")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            edits,
+            vec![
+                (TagField::TrackNumber, Some("03".into())),
+                (TagField::Artist, Some("Artist".into())),
+                (TagField::Title, Some("A - B".into())),
+            ]
+        );
+        let mut tags = Tags::default();
+        apply_edits(&mut tags, &edits);
+        assert_eq!(tags.track_number, Some(3));
+
+        // The track number has to be a number
+        assert_eq!(
+            tags_from_file_name("{track} - {title}", "Intro - Song").unwrap(),
+            None
+        );
+        assert!(matches!(
+            tags_from_file_name("{artist}{title}", ""),
+            Err(TagPatternError::AdjacentFields)
+        ));
+        assert!(matches!(
+            tags_from_file_name("{artist", ""),
+            Err(TagPatternError::Unclosed)
+        ));
+        assert!(matches!(
+            tags_from_file_name("{band}", ""),
+            Err(TagPatternError::UnknownField(_))
+        ));
+    }
+
+    #[test]
+    fn test_file_name_from_tags() {
+        let tags = Tags {
+            title: Some("What?/Why".into()),
+            artist: Some("Artist".into()),
+            track_number: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(
+            file_name_from_tags("{track} {artist} - {title}", &tags).unwrap(),
+            Some("03 Artist - What__Why".into())
+        );
+        assert_eq!(file_name_from_tags("{album}", &tags).unwrap(), None);
+        assert!(TagField::TrackNumber.is_unchanged("3/12", &tags));
+        assert!(!TagField::Artist.is_unchanged("Other", &tags));
+    }
+}
@@ -0,0 +1,434 @@
+use std::ops::Range;
+
+use super::{TagField, Tags};
+use crate::errors::TagWriteError;
+
+type AtomKind = [u8; 4];
+
+/// An atom in a slice of atoms, with the position of its header and body in the slice.
+#[derive(Clone, Debug)]
+struct Atom {
+    kind: AtomKind,
+    start: usize,
+    body: Range<usize>,
+}
+
+impl Atom {
+    fn range(&self) -> Range<usize> {
+        self.start..self.body.end
+    }
+}
+
+fn malformed() -> TagWriteError {
+    TagWriteError::Malformed("MP4")
+}
+
+/// Split `data` into the atoms it's made of.
+fn parse_atoms(data: &[u8]) -> Result<Vec<Atom>, TagWriteError> {
+    let mut atoms = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data.get(pos..pos + 8).ok_or_else(malformed)?;
+        let kind: AtomKind = header[4..8].try_into().unwrap();
+        let (header_len, size) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            // The atom goes on until the end of the file
+            0 => (8, data.len() - pos),
+            1 => {
+                let size = data.get(pos + 8..pos + 16).ok_or_else(malformed)?;
+                let size = u64::from_be_bytes(size.try_into().unwrap());
+                (16, usize::try_from(size).map_err(|_| malformed())?)
+            }
+            size => (8, size as usize),
+        };
+        if size < header_len || size > data.len() - pos {
+            return Err(malformed());
+        }
+        atoms.push(Atom {
+            kind,
+            start: pos,
+            body: pos + header_len..pos + size,
+        });
+        pos += size;
+    }
+    Ok(atoms)
+}
+
+/// An atom with a header for `body`.
+fn atom_bytes(kind: &AtomKind, body: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(body.len() + 16);
+    match u32::try_from(body.len() + 8) {
+        Ok(size) => {
+            bytes.extend_from_slice(&size.to_be_bytes());
+            bytes.extend_from_slice(kind);
+        }
+        Err(_) => {
+            bytes.extend_from_slice(&1u32.to_be_bytes());
+            bytes.extend_from_slice(kind);
+            bytes.extend_from_slice(&(body.len() as u64 + 16).to_be_bytes());
+        }
+    }
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+/// The item atom in the `ilst` atom holding the field.
+fn item_kind(field: TagField) -> &'static AtomKind {
+    match field {
+        TagField::Title => b"\xa9nam",
+        TagField::Artist => b"\xa9ART",
+        TagField::Album => b"\xa9alb",
+        TagField::AlbumArtist => b"aART",
+        TagField::TrackNumber => b"trkn",
+        TagField::DiscNumber => b"disk",
+        TagField::Year => b"\xa9day",
+        TagField::Genre => b"\xa9gen",
+        TagField::Composer => b"\xa9wrt",
+        TagField::Comment => b"\xa9cmt",
+        TagField::Bpm => b"tmpo",
+    }
+}
+
+/// The well-known types of `data` atoms.
+const DATA_TYPE_BINARY: u32 = 0;
+const DATA_TYPE_UTF8: u32 = 1;
+const DATA_TYPE_INTEGER: u32 = 21;
+
+/// The payload of the first `data` atom in an item's body, after its type and locale.
+fn item_payload(item: &[u8]) -> Option<&[u8]> {
+    let atoms = parse_atoms(item).ok()?;
+    let data = atoms.iter().find(|atom| &atom.kind == b"data")?;
+    item.get(data.body.start + 8..data.body.end)
+}
+
+/// The value of an item as text, the way it would be read into [`Tags`].
+fn item_value(field: TagField, item: &[u8]) -> Option<String> {
+    let payload = item_payload(item)?;
+    let value = match field {
+        TagField::TrackNumber | TagField::DiscNumber => {
+            u16::from_be_bytes(payload.get(2..4)?.try_into().unwrap()).to_string()
+        }
+        TagField::Bpm => match payload {
+            [bpm] => bpm.to_string(),
+            [high, low, ..] => u16::from_be_bytes([*high, *low]).to_string(),
+            [] => return None,
+        },
+        _ => String::from_utf8_lossy(payload).into_owned(),
+    };
+    Some(value)
+}
+
+/// The body of an item atom holding `value`, keeping the track or disc total from the `old` item.
+fn item_body(field: TagField, value: &str, old: Option<&[u8]>) -> Vec<u8> {
+    let number = || value.parse::<u16>().unwrap_or(u16::MAX).to_be_bytes();
+    let (data_type, payload) = match field {
+        TagField::TrackNumber | TagField::DiscNumber => {
+            let total = old
+                .and_then(item_payload)
+                .and_then(|payload| payload.get(4..6))
+                .unwrap_or(&[0, 0]);
+            let mut payload = vec![0, 0];
+            payload.extend_from_slice(&number());
+            payload.extend_from_slice(total);
+            if field == TagField::TrackNumber {
+                payload.extend_from_slice(&[0, 0]);
+            }
+            (DATA_TYPE_BINARY, payload)
+        }
+        TagField::Bpm => (DATA_TYPE_INTEGER, number().to_vec()),
+        _ => (DATA_TYPE_UTF8, value.as_bytes().to_vec()),
+    };
+    let mut data = data_type.to_be_bytes().to_vec();
+    data.extend_from_slice(&[0; 4]);
+    data.extend(payload);
+    atom_bytes(b"data", &data)
+}
+
+/// Write `tags` to the items of an `ilst` atom's body, keeping items that aren't part of [`Tags`].
+fn write_ilst(ilst: &[u8], tags: &Tags) -> Result<Vec<u8>, TagWriteError> {
+    let mut items: Vec<(AtomKind, Vec<u8>)> = parse_atoms(ilst)?
+        .into_iter()
+        .map(|atom| (atom.kind, ilst[atom.body].to_vec()))
+        .collect();
+    for field in TagField::ALL {
+        let kind = item_kind(field);
+        let position = items.iter().position(|(k, _)| k == kind);
+        let old = position.map(|i| items[i].1.clone());
+        let existing = old.as_deref().and_then(|item| item_value(field, item));
+        let value = field.get(tags);
+        match &existing {
+            Some(existing) if field.is_unchanged(existing, tags) => continue,
+            None if value.is_none() => continue,
+            _ => {}
+        }
+        items.retain(|(k, _)| k != kind && !(field == TagField::Genre && k == b"gnre"));
+        if let Some(value) = value {
+            let item = (*kind, item_body(field, &value, old.as_deref()));
+            let position = position.unwrap_or(items.len()).min(items.len());
+            items.insert(position, item);
+        }
+    }
+    Ok(items
+        .iter()
+        .flat_map(|(kind, body)| atom_bytes(kind, body))
+        .collect())
+}
+
+/// Write `tags` to a `meta` atom's body, adding an `ilst` atom if there isn't one.
+fn write_meta(meta: &[u8], tags: &Tags) -> Result<Vec<u8>, TagWriteError> {
+    // QuickTime files leave out the version and flags the ISO format has before the children
+    let children_start = if meta.get(4..8) == Some(b"hdlr") {
+        0
+    } else {
+        4
+    };
+    let children = meta.get(children_start..).ok_or_else(malformed)?;
+    let mut written = meta[..children_start].to_vec();
+    let mut has_ilst = false;
+    for atom in parse_atoms(children)? {
+        if &atom.kind == b"ilst" {
+            has_ilst = true;
+            written.extend(atom_bytes(
+                b"ilst",
+                &write_ilst(&children[atom.body], tags)?,
+            ));
+        } else {
+            written.extend_from_slice(&children[atom.range()]);
+        }
+    }
+    if !has_ilst {
+        written.extend(atom_bytes(b"ilst", &write_ilst(&[], tags)?));
+    }
+    Ok(written)
+}
+
+/// The body of a new `meta` atom, with the handler iTunes style tags need.
+fn new_meta(tags: &Tags) -> Result<Vec<u8>, TagWriteError> {
+    let mut hdlr = vec![0; 8];
+    hdlr.extend_from_slice(b"mdirappl");
+    hdlr.extend_from_slice(&[0; 9]);
+    let mut meta = vec![0; 4];
+    meta.extend(atom_bytes(b"hdlr", &hdlr));
+    meta.extend(atom_bytes(b"ilst", &write_ilst(&[], tags)?));
+    Ok(meta)
+}
+
+/// Write `tags` to a `udta` atom's body, adding a `meta` atom if there isn't one.
+fn write_udta(udta: &[u8], tags: &Tags) -> Result<Vec<u8>, TagWriteError> {
+    let mut written = Vec::with_capacity(udta.len());
+    let mut has_meta = false;
+    for atom in parse_atoms(udta)? {
+        if &atom.kind == b"meta" {
+            has_meta = true;
+            written.extend(atom_bytes(b"meta", &write_meta(&udta[atom.body], tags)?));
+        } else {
+            written.extend_from_slice(&udta[atom.range()]);
+        }
+    }
+    if !has_meta {
+        written.extend(atom_bytes(b"meta", &new_meta(tags)?));
+    }
+    Ok(written)
+}
+
+/// Move the chunk offsets in the `stco` and `co64` atoms under `atoms` that point at or after `from` by `delta`.
+fn shift_chunk_offsets(atoms: &mut [u8], from: u64, delta: i64) -> Result<(), TagWriteError> {
+    for atom in parse_atoms(atoms)? {
+        let body = &mut atoms[atom.body];
+        let offset_len = match &atom.kind {
+            b"trak" | b"mdia" | b"minf" | b"stbl" => {
+                shift_chunk_offsets(body, from, delta)?;
+                continue;
+            }
+            b"stco" => 4,
+            b"co64" => 8,
+            _ => continue,
+        };
+        let count = body.get(4..8).ok_or_else(malformed)?;
+        let count = u32::from_be_bytes(count.try_into().unwrap()) as usize;
+        let offsets = body
+            .get_mut(8..8 + count * offset_len)
+            .ok_or_else(malformed)?;
+        for offset in offsets.chunks_exact_mut(offset_len) {
+            let old = match offset_len {
+                4 => u32::from_be_bytes((&*offset).try_into().unwrap()) as u64,
+                _ => u64::from_be_bytes((&*offset).try_into().unwrap()),
+            };
+            if old < from {
+                continue;
+            }
+            let new = old.checked_add_signed(delta).ok_or_else(malformed)?;
+            match offset_len {
+                4 => offset.copy_from_slice(
+                    &u32::try_from(new)
+                        .map_err(|_| TagWriteError::Malformed("MP4 with 32 bit offsets"))?
+                        .to_be_bytes(),
+                ),
+                _ => offset.copy_from_slice(&new.to_be_bytes()),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write `tags` to the `moov/udta/meta/ilst` atom of an m4a file, adding the atoms that are missing.
+///
+/// When the `moov` atom comes before the audio data, the offsets of the audio chunks are moved by how much it grew.
+pub(super) fn write_mp4(data: &[u8], tags: &Tags) -> Result<Vec<u8>, TagWriteError> {
+    let atoms = parse_atoms(data)?;
+    let moov = atoms
+        .iter()
+        .find(|atom| &atom.kind == b"moov")
+        .ok_or_else(malformed)?;
+    let moov_body = &data[moov.body.clone()];
+
+    let mut new_moov = Vec::with_capacity(moov_body.len());
+    let mut has_udta = false;
+    for atom in parse_atoms(moov_body)? {
+        if &atom.kind == b"udta" {
+            has_udta = true;
+            new_moov.extend(atom_bytes(
+                b"udta",
+                &write_udta(&moov_body[atom.body], tags)?,
+            ));
+        } else {
+            new_moov.extend_from_slice(&moov_body[atom.range()]);
+        }
+    }
+    if !has_udta {
+        new_moov.extend(atom_bytes(b"udta", &write_udta(&[], tags)?));
+    }
+    let mut new_moov = atom_bytes(b"moov", &new_moov);
+    let delta = new_moov.len() as i64 - moov.range().len() as i64;
+    if delta != 0 {
+        let body_start = if new_moov[..4] == 1u32.to_be_bytes() {
+            16
+        } else {
+            8
+        };
+        shift_chunk_offsets(&mut new_moov[body_start..], moov.body.end as u64, delta)?;
+    }
+
+    let mut written = Vec::with_capacity(data.len() + new_moov.len());
+    written.extend_from_slice(&data[..moov.start]);
+    written.extend(new_moov);
+    written.extend_from_slice(&data[moov.body.end..]);
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An m4a file with the `moov` atom before the audio data, with one chunk of audio.
+    fn mp4_file() -> Vec<u8> {
+        let mut stco = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stco.extend_from_slice(&0u32.to_be_bytes());
+        let stbl = atom_bytes(b"stbl", &atom_bytes(b"stco", &stco));
+        let trak = atom_bytes(b"trak", &atom_bytes(b"mdia", &atom_bytes(b"minf", &stbl)));
+        let mut moov_body = atom_bytes(b"mvhd", &[0; 20]);
+        moov_body.extend(trak);
+        let mut data = atom_bytes(b"ftyp", b"M4A \0\0\0\0");
+        data.extend(atom_bytes(b"moov", &moov_body));
+        let chunk_offset = data.len() as u32 + 8;
+        data.extend(atom_bytes(b"mdat", b"audio"));
+
+        // Point the chunk at the audio data
+        let stco_offset = data
+            .windows(4)
+            .position(|window| window == b"stco")
+            .unwrap();
+        data[stco_offset + 12..stco_offset + 16].copy_from_slice(&chunk_offset.to_be_bytes());
+        data
+    }
+
+    /// The body of the atom found by following `path` from the top of `data`.
+    fn find<'a>(data: &'a [u8], path: &[&AtomKind]) -> &'a [u8] {
+        let Some((kind, rest)) = path.split_first() else {
+            return data;
+        };
+        let atom = parse_atoms(data)
+            .unwrap()
+            .into_iter()
+            .find(|atom| &atom.kind == *kind)
+            .unwrap();
+        let body = &data[atom.body];
+        match *kind {
+            b"meta" => find(&body[4..], rest),
+            _ => find(body, rest),
+        }
+    }
+
+    fn chunk_offset(data: &[u8]) -> usize {
+        let stco = find(
+            data,
+            &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stco"],
+        );
+        u32::from_be_bytes(stco[8..12].try_into().unwrap()) as usize
+    }
+
+    #[test]
+    fn test_parse_atoms_malformed() {
+        // A 64 bit size that goes past the end of the data, and would overflow when added to the position
+        let mut data = atom_bytes(b"ftyp", b"M4A \0\0\0\0");
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(b"moov");
+        data.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert!(matches!(
+            parse_atoms(&data),
+            Err(TagWriteError::Malformed("MP4"))
+        ));
+    }
+
+    #[test]
+    fn test_write_mp4() {
+        let data = mp4_file();
+        let offset = chunk_offset(&data);
+        assert_eq!(&data[offset..offset + 5], b"audio");
+
+        let tags = Tags {
+            title: Some("Title".into()),
+            track_number: Some(3),
+            bpm: Some(120),
+            ..Default::default()
+        };
+        let written = write_mp4(&data, &tags).unwrap();
+        let ilst = find(&written, &[b"moov", b"udta", b"meta", b"ilst"]);
+        let values: Vec<(AtomKind, Option<String>)> = TagField::ALL
+            .into_iter()
+            .filter_map(|field| {
+                let item = parse_atoms(ilst)
+                    .unwrap()
+                    .into_iter()
+                    .find(|atom| &atom.kind == item_kind(field))?;
+                Some((*item_kind(field), item_value(field, &ilst[item.body])))
+            })
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                (*b"\xa9nam", Some("Title".into())),
+                (*b"trkn", Some("3".into())),
+                (*b"tmpo", Some("120".into())),
+            ]
+        );
+        // The audio chunk moved with the bigger moov atom
+        let offset = chunk_offset(&written);
+        assert_eq!(&written[offset..offset + 5], b"audio");
+
+        let tags = Tags {
+            title: None,
+            artist: Some("Artist".into()),
+            ..tags
+        };
+        let rewritten = write_mp4(&written, &tags).unwrap();
+        let ilst = find(&rewritten, &[b"moov", b"udta", b"meta", b"ilst"]);
+        let kinds: Vec<AtomKind> = parse_atoms(ilst)
+            .unwrap()
+            .into_iter()
+            .map(|atom| atom.kind)
+            .collect();
+        assert_eq!(kinds, vec![*b"trkn", *b"tmpo", *b"\xa9ART"]);
+        let offset = chunk_offset(&rewritten);
+        assert_eq!(&rewritten[offset..offset + 5], b"audio");
+    }
+}
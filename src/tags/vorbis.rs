@@ -0,0 +1,369 @@
+use std::io::Cursor;
+
+use ogg::{PacketReader, PacketWriteEndInfo, PacketWriter};
+
+use super::{TagField, Tags};
+use crate::errors::TagWriteError;
+
+/// The Vorbis comment field names of the tags, the first one is written.
+fn comment_keys(field: TagField) -> &'static [&'static str] {
+    match field {
+        TagField::Title => &["TITLE"],
+        TagField::Artist => &["ARTIST"],
+        TagField::Album => &["ALBUM"],
+        TagField::AlbumArtist => &["ALBUMARTIST", "ALBUM ARTIST"],
+        TagField::TrackNumber => &["TRACKNUMBER"],
+        TagField::DiscNumber => &["DISCNUMBER"],
+        TagField::Year => &["DATE", "YEAR"],
+        TagField::Genre => &["GENRE"],
+        TagField::Composer => &["COMPOSER"],
+        TagField::Comment => &["COMMENT"],
+        TagField::Bpm => &["BPM"],
+    }
+}
+
+/// A Vorbis comment block, used by flac and ogg files.
+#[derive(Clone, Debug, PartialEq)]
+struct VorbisComments {
+    vendor: String,
+    comments: Vec<(String, String)>,
+}
+
+impl VorbisComments {
+    /// Parse a comment block without the framing bit, returning it and the number of bytes read.
+    fn parse(data: &[u8]) -> Result<(Self, usize), TagWriteError> {
+        let invalid = || TagWriteError::Malformed("Vorbis comment");
+        let mut pos = 0;
+        let read_string = |pos: &mut usize| -> Result<String, TagWriteError> {
+            let len_bytes = data.get(*pos..*pos + 4).ok_or_else(invalid)?;
+            let len = u32::from_le_bytes(len_bytes.try_into().unwrap()) as usize;
+            let bytes = data.get(*pos + 4..*pos + 4 + len).ok_or_else(invalid)?;
+            *pos += 4 + len;
+            Ok(String::from_utf8_lossy(bytes).into_owned())
+        };
+        let vendor = read_string(&mut pos)?;
+        let count_bytes = data.get(pos..pos + 4).ok_or_else(invalid)?;
+        let count = u32::from_le_bytes(count_bytes.try_into().unwrap());
+        pos += 4;
+        let mut comments = Vec::new();
+        for _ in 0..count {
+            let comment = read_string(&mut pos)?;
+            let (key, value) = comment.split_once('=').unwrap_or((&comment, ""));
+            comments.push((key.to_string(), value.to_string()));
+        }
+        Ok((Self { vendor, comments }, pos))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let write_string = |bytes: &mut Vec<u8>, string: &str| {
+            bytes.extend_from_slice(&(string.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(string.as_bytes());
+        };
+        write_string(&mut bytes, &self.vendor);
+        bytes.extend_from_slice(&(self.comments.len() as u32).to_le_bytes());
+        for (key, value) in &self.comments {
+            write_string(&mut bytes, &format!("{key}={value}"));
+        }
+        bytes
+    }
+
+    /// Replace the comments of the fields that changed with the values in `tags`.
+    fn apply(&mut self, tags: &Tags) {
+        for field in TagField::ALL {
+            let keys = comment_keys(field);
+            let is_field = |key: &str| keys.iter().any(|k| k.eq_ignore_ascii_case(key));
+            let existing = self.comments.iter().find(|(key, _)| is_field(key));
+            if existing.is_some_and(|(_, value)| field.is_unchanged(value, tags)) {
+                continue;
+            }
+            let position = self.comments.iter().position(|(key, _)| is_field(key));
+            self.comments.retain(|(key, _)| !is_field(key));
+            if let Some(value) = field.get(tags) {
+                // Changed comments stay where they were
+                let position = position.unwrap_or(self.comments.len());
+                self.comments.insert(position, (keys[0].to_string(), value));
+            }
+        }
+    }
+}
+
+/// The size of the ID3v2 tag some flac files start with, 0 if there isn't one.
+fn id3_size(data: &[u8]) -> usize {
+    match data {
+        [b'I', b'D', b'3', _, _, flags, size @ ..] if size.len() >= 4 => {
+            let size = size[..4]
+                .iter()
+                .fold(0, |size, byte| (size << 7) | (*byte as usize & 0x7f));
+            let footer = if flags & 0x10 != 0 { 10 } else { 0 };
+            10 + size + footer
+        }
+        _ => 0,
+    }
+}
+
+const FLAC_STREAMINFO: u8 = 0;
+const FLAC_VORBIS_COMMENT: u8 = 4;
+
+/// Write `tags` to the Vorbis comment metadata block of a flac file, adding one if there isn't one.
+pub(super) fn write_flac(data: &[u8], tags: &Tags) -> Result<Vec<u8>, TagWriteError> {
+    let invalid = || TagWriteError::Malformed("flac");
+    let start = id3_size(data);
+    if data.get(start..start + 4) != Some(b"fLaC") {
+        return Err(invalid());
+    }
+    let mut pos = start + 4;
+    let mut blocks: Vec<(u8, &[u8])> = Vec::new();
+    loop {
+        let header = data.get(pos..pos + 4).ok_or_else(invalid)?;
+        let is_last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7f;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let block = data.get(pos + 4..pos + 4 + len).ok_or_else(invalid)?;
+        blocks.push((block_type, block));
+        pos += 4 + len;
+        if is_last {
+            break;
+        }
+    }
+    if blocks.first().map(|(block_type, _)| *block_type) != Some(FLAC_STREAMINFO) {
+        return Err(invalid());
+    }
+
+    let mut comments = match blocks.iter().find(|(t, _)| *t == FLAC_VORBIS_COMMENT) {
+        Some((_, block)) => VorbisComments::parse(block)?.0,
+        None => VorbisComments {
+            vendor: "amuseing".into(),
+            comments: Vec::new(),
+        },
+    };
+    comments.apply(tags);
+    let comment_block = comments.to_bytes();
+    if comment_block.len() >= 1 << 24 {
+        return Err(TagWriteError::Malformed("flac"));
+    }
+    match blocks.iter().position(|(t, _)| *t == FLAC_VORBIS_COMMENT) {
+        Some(i) => blocks[i].1 = &comment_block,
+        None => blocks.insert(1, (FLAC_VORBIS_COMMENT, &comment_block)),
+    }
+
+    let mut written = Vec::with_capacity(data.len() + comment_block.len());
+    written.extend_from_slice(&data[..start + 4]);
+    for (i, (block_type, block)) in blocks.iter().enumerate() {
+        let last_flag = if i == blocks.len() - 1 { 0x80 } else { 0 };
+        written.push(last_flag | block_type);
+        written.extend_from_slice(&(block.len() as u32).to_be_bytes()[1..]);
+        written.extend_from_slice(block);
+    }
+    written.extend_from_slice(&data[pos..]);
+    Ok(written)
+}
+
+const VORBIS_COMMENT_HEADER: &[u8] = b"\x03vorbis";
+const OPUS_TAGS_HEADER: &[u8] = b"OpusTags";
+
+/// Write `tags` to the comment header of the first Vorbis or Opus stream in an ogg file.
+///
+/// The packets are written to new pages, keeping where the original pages ended and their granule positions.
+pub(super) fn write_ogg(data: &[u8], tags: &Tags) -> Result<Vec<u8>, TagWriteError> {
+    let mut reader = PacketReader::new(Cursor::new(data));
+    let mut writer = PacketWriter::new(Vec::with_capacity(data.len()));
+    let mut comment_serial = None;
+    let mut written_comments = false;
+    while let Some(mut packet) = reader.read_packet()? {
+        let serial = packet.stream_serial();
+        if packet.first_in_stream() && comment_serial.is_none() {
+            comment_serial = Some(serial);
+        } else if !written_comments && comment_serial == Some(serial) {
+            packet.data = ogg_comment_packet(&packet.data, tags)?;
+            written_comments = true;
+        }
+        let end_info = if packet.last_in_stream() {
+            PacketWriteEndInfo::EndStream
+        } else if packet.last_in_page() {
+            PacketWriteEndInfo::EndPage
+        } else {
+            PacketWriteEndInfo::NormalPacket
+        };
+        let absgp = packet.absgp_page();
+        writer.write_packet(packet.data.into_boxed_slice(), serial, end_info, absgp)?;
+    }
+    if !written_comments {
+        return Err(TagWriteError::Malformed("ogg"));
+    }
+    Ok(writer.into_inner())
+}
+
+/// Write `tags` to a Vorbis comment header or Opus tags packet.
+fn ogg_comment_packet(packet: &[u8], tags: &Tags) -> Result<Vec<u8>, TagWriteError> {
+    let (header, framing_bit) = if packet.starts_with(VORBIS_COMMENT_HEADER) {
+        (VORBIS_COMMENT_HEADER, true)
+    } else if packet.starts_with(OPUS_TAGS_HEADER) {
+        (OPUS_TAGS_HEADER, false)
+    } else {
+        return Err(TagWriteError::Malformed("Vorbis or Opus"));
+    };
+    let (mut comments, len) = VorbisComments::parse(&packet[header.len()..])?;
+    comments.apply(tags);
+    let mut written = header.to_vec();
+    written.extend(comments.to_bytes());
+    if framing_bit {
+        written.push(1);
+    } else {
+        // Opus keeps binary data after the comments
+        written.extend_from_slice(&packet[header.len() + len..]);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comments(data: &[u8]) -> Vec<(String, String)> {
+        VorbisComments::parse(data).unwrap().0.comments
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut vorbis = VorbisComments {
+            vendor: "vendor".into(),
+            comments: vec![
+                ("tracknumber".into(), "3/12".into()),
+                ("TITLE".into(), "Old".into()),
+                ("CUSTOM".into(), "kept".into()),
+            ],
+        };
+        let tags = Tags {
+            title: Some("New".into()),
+            track_number: Some(3),
+            artist: Some("Artist".into()),
+            ..Default::default()
+        };
+        vorbis.apply(&tags);
+        let bytes = vorbis.to_bytes();
+        assert_eq!(
+            comments(&bytes),
+            vec![
+                ("tracknumber".into(), "3/12".into()),
+                ("TITLE".into(), "New".into()),
+                ("CUSTOM".into(), "kept".into()),
+                ("ARTIST".into(), "Artist".into()),
+            ]
+        );
+    }
+
+    /// A flac file with only a stream info block, followed by the audio frames.
+    fn flac_file() -> Vec<u8> {
+        let mut data = b"fLaC".to_vec();
+        data.push(0x80 | FLAC_STREAMINFO);
+        data.extend_from_slice(&34u32.to_be_bytes()[1..]);
+        data.extend_from_slice(&[0; 34]);
+        data.extend_from_slice(b"frames");
+        data
+    }
+
+    /// The types of the metadata blocks in a flac file, and the comments in its Vorbis comment block.
+    fn flac_comments(data: &[u8]) -> (Vec<u8>, Vec<(String, String)>) {
+        let mut pos = 4;
+        let mut types = Vec::new();
+        let mut found = Vec::new();
+        loop {
+            let len = u32::from_be_bytes([0, data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+            let block_type = data[pos] & 0x7f;
+            types.push(block_type);
+            if block_type == FLAC_VORBIS_COMMENT {
+                found = comments(&data[pos + 4..pos + 4 + len]);
+            }
+            if data[pos] & 0x80 != 0 {
+                break;
+            }
+            pos += 4 + len;
+        }
+        (types, found)
+    }
+
+    #[test]
+    fn test_write_flac() {
+        let tags = Tags {
+            title: Some("Title".into()),
+            year: Some(2004),
+            ..Default::default()
+        };
+        let written = write_flac(&flac_file(), &tags).unwrap();
+        assert_eq!(
+            flac_comments(&written),
+            (
+                vec![FLAC_STREAMINFO, FLAC_VORBIS_COMMENT],
+                vec![
+                    ("TITLE".into(), "Title".into()),
+                    ("DATE".into(), "2004".into())
+                ]
+            )
+        );
+        assert!(written.ends_with(b"frames"));
+
+        // Writing again replaces the comment block
+        let tags = Tags {
+            title: Some("Other".into()),
+            ..tags
+        };
+        let rewritten = write_flac(&written, &tags).unwrap();
+        assert_eq!(
+            flac_comments(&rewritten).1,
+            vec![
+                ("TITLE".into(), "Other".into()),
+                ("DATE".into(), "2004".into())
+            ]
+        );
+        assert!(rewritten.ends_with(b"frames"));
+        assert!(write_flac(b"not flac", &tags).is_err());
+    }
+
+    #[test]
+    fn test_write_ogg() {
+        let mut writer = PacketWriter::new(Vec::new());
+        let mut comment_header = VORBIS_COMMENT_HEADER.to_vec();
+        comment_header.extend(
+            VorbisComments {
+                vendor: "vendor".into(),
+                comments: vec![("TITLE".into(), "Old".into())],
+            }
+            .to_bytes(),
+        );
+        comment_header.push(1);
+        let packets: [(&[u8], PacketWriteEndInfo, u64); 4] = [
+            (b"\x01vorbis identification", PacketWriteEndInfo::EndPage, 0),
+            (&comment_header, PacketWriteEndInfo::NormalPacket, 0),
+            (b"\x05vorbis setup", PacketWriteEndInfo::EndPage, 0),
+            (b"audio", PacketWriteEndInfo::EndStream, 1000),
+        ];
+        for (data, end_info, absgp) in packets {
+            writer
+                .write_packet(data.to_vec().into_boxed_slice(), 7, end_info, absgp)
+                .unwrap();
+        }
+        let data = writer.into_inner();
+
+        let tags = Tags {
+            title: Some("New title".into()),
+            ..Default::default()
+        };
+        let written = write_ogg(&data, &tags).unwrap();
+        let mut reader = PacketReader::new(Cursor::new(&written));
+        let mut read = Vec::new();
+        while let Some(packet) = reader.read_packet().unwrap() {
+            read.push(packet);
+        }
+        assert_eq!(read.len(), 4);
+        let comment_packet = &read[1].data;
+        assert_eq!(
+            comments(&comment_packet[VORBIS_COMMENT_HEADER.len()..]),
+            vec![("TITLE".into(), "New title".into())]
+        );
+        assert_eq!(comment_packet.last(), Some(&1));
+        assert_eq!(read[3].data, b"audio");
+        assert_eq!(read[3].absgp_page(), 1000);
+        assert!(read[3].last_in_stream());
+    }
+}
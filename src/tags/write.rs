@@ -0,0 +1,168 @@
+use std::{
+    fs,
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+};
+
+use id3::{frame::Comment, TagLike, Version};
+
+use super::{mp4, vorbis, TagField, Tags};
+use crate::errors::TagWriteError;
+
+/// Write `tags` to the file at `path`, replacing the tags it had.
+///
+/// ID3v2 tags are written to mp3 and aac files, Vorbis comments to flac and ogg files and MP4 atoms to m4a files.
+/// Tags and pictures that aren't part of [`Tags`] are kept.
+///
+/// The file is written to a temporary file next to it first, which then replaces it,
+/// so the song isn't left half written if writing fails.
+pub fn write_tags(path: &Path, tags: &Tags) -> Result<(), TagWriteError> {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let data = fs::read(path)?;
+    let data = match extension.as_str() {
+        "mp3" | "aac" => write_id3(data, tags)?,
+        "flac" => vorbis::write_flac(&data, tags)?,
+        "ogg" | "oga" | "opus" => vorbis::write_ogg(&data, tags)?,
+        "m4a" | "m4b" | "mp4" => mp4::write_mp4(&data, tags)?,
+        _ => return Err(TagWriteError::UnsupportedFormat(extension)),
+    };
+    replace_file(path, &data)
+}
+
+/// Replace the contents of the file at `path` with `data`, through a temporary file that's renamed to it.
+fn replace_file(path: &Path, data: &[u8]) -> Result<(), TagWriteError> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::other("path without a file name"))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(".amuseing-tmp");
+    let temp_path: PathBuf = path.with_file_name(temp_name);
+    let written = (|| {
+        let mut temp = fs::File::create(&temp_path)?;
+        temp.write_all(data)?;
+        temp.sync_all()?;
+        temp.set_permissions(fs::metadata(path)?.permissions())?;
+        fs::rename(&temp_path, path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    Ok(written?)
+}
+
+/// The ID3v2 frame ids of the fields, in version 2.4.
+fn id3_frame(field: TagField) -> &'static str {
+    match field {
+        TagField::Title => "TIT2",
+        TagField::Artist => "TPE1",
+        TagField::Album => "TALB",
+        TagField::AlbumArtist => "TPE2",
+        TagField::TrackNumber => "TRCK",
+        TagField::DiscNumber => "TPOS",
+        TagField::Year => "TDRC",
+        TagField::Genre => "TCON",
+        TagField::Composer => "TCOM",
+        TagField::Comment => "COMM",
+        TagField::Bpm => "TBPM",
+    }
+}
+
+/// Write `tags` to the ID3v2 tag at the start of `data`, adding one if there isn't one.
+fn write_id3(data: Vec<u8>, tags: &Tags) -> Result<Vec<u8>, TagWriteError> {
+    let mut file = Cursor::new(data);
+    let mut tag = id3::no_tag_ok(id3::Tag::read_from2(&mut file))?.unwrap_or_default();
+    for field in TagField::ALL {
+        let id = id3_frame(field);
+        let value = field.get(tags);
+        if field == TagField::Comment {
+            let existing = tag.comments().next().map(|comment| comment.text.clone());
+            if existing.is_some_and(|existing| field.is_unchanged(&existing, tags)) {
+                continue;
+            }
+            tag.remove_comment(None, None);
+            if let Some(text) = value {
+                tag.add_frame(Comment {
+                    lang: "eng".into(),
+                    description: String::new(),
+                    text,
+                });
+            }
+            continue;
+        }
+        // Version 2.3 tags keep the year in TYER, which is replaced by TDRC
+        let existing = tag
+            .get(id)
+            .or_else(|| (field == TagField::Year).then(|| tag.get("TYER")).flatten())
+            .and_then(|frame| frame.content().text())
+            .map(String::from);
+        if existing.is_some_and(|existing| field.is_unchanged(&existing, tags)) {
+            continue;
+        }
+        tag.remove(id);
+        if field == TagField::Year {
+            tag.remove("TYER");
+        }
+        if let Some(value) = value {
+            tag.set_text(id, value);
+        }
+    }
+    file.set_position(0);
+    tag.write_to_file(&mut file, Version::Id3v24)?;
+    Ok(file.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_id3() {
+        let mut old = id3::Tag::new();
+        old.set_text("TRCK", "3/12");
+        old.set_text("TIT2", "Old title");
+        old.add_frame(id3::frame::ExtendedText {
+            description: "key".into(),
+            value: "kept".into(),
+        });
+        let mut data = Vec::new();
+        old.write_to(&mut data, Version::Id3v23).unwrap();
+        data.extend_from_slice(b"audio frames");
+
+        let tags = Tags {
+            title: Some("New title".into()),
+            track_number: Some(3),
+            comment: Some("Comment".into()),
+            ..Default::default()
+        };
+        let written = write_id3(data, &tags).unwrap();
+        assert!(written.ends_with(b"audio frames"));
+        let tag = id3::Tag::read_from2(Cursor::new(&written)).unwrap();
+        assert_eq!(tag.title(), Some("New title"));
+        // Unchanged tags keep their original text
+        assert_eq!(
+            tag.get("TRCK").and_then(|frame| frame.content().text()),
+            Some("3/12")
+        );
+        assert_eq!(tag.comments().next().unwrap().text, "Comment");
+        assert!(tag.get("TXXX").is_some());
+        assert_eq!(tag.artist(), None);
+    }
+
+    #[test]
+    fn test_replace_file() {
+        let dir = std::env::temp_dir().join("amuseing-test-replace-file");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("song.mp3");
+        fs::write(&path, b"old").unwrap();
+        replace_file(&path, b"new").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        // Only the song is left, the temporary file was renamed to it
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
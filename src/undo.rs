@@ -0,0 +1,149 @@
+use std::collections::VecDeque;
+
+/// A bounded stack of previous states, used to undo and redo edits.
+///
+/// Push the state from before an edit with [`push`], and get it back with [`undo`].
+/// Undone states can be restored with [`redo`] until the next [`push`].
+///
+/// When more than `depth` states are stored, the oldest one is dropped.
+///
+/// [`push`]: Self::push
+/// [`undo`]: Self::undo
+/// [`redo`]: Self::redo
+#[derive(Clone, Debug)]
+pub struct UndoStack<T> {
+    undo: VecDeque<T>,
+    redo: Vec<T>,
+    depth: usize,
+}
+
+impl<T> UndoStack<T> {
+    /// Create a new empty stack storing at most `depth` states.
+    pub fn new(depth: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            depth,
+        }
+    }
+
+    /// The maximum number of states that can be undone.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Set the maximum number of states, dropping the oldest ones if there are too many.
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        while self.undo.len() > depth {
+            self.undo.pop_front();
+        }
+        self.redo.truncate(depth);
+    }
+
+    /// Record the state from before an edit. This clears the redo stack.
+    pub fn push(&mut self, state: T) {
+        self.redo.clear();
+        self.push_undo(state);
+    }
+
+    /// Store a state that can be undone, dropping the oldest ones if there are more than `depth`.
+    fn push_undo(&mut self, state: T) {
+        if self.depth == 0 {
+            return;
+        }
+        while self.undo.len() >= self.depth {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(state);
+    }
+
+    /// Return the state from before the last edit, storing `current` so it can be restored with [`redo`].
+    ///
+    /// Returns None if there's nothing to undo, `current` is dropped in that case.
+    ///
+    /// [`redo`]: Self::redo
+    pub fn undo(&mut self, current: T) -> Option<T> {
+        let previous = self.undo.pop_back()?;
+        self.redo.push(current);
+        Some(previous)
+    }
+
+    /// Return the last undone state, storing `current` so it can be undone again.
+    ///
+    /// Returns None if there's nothing to redo, `current` is dropped in that case.
+    pub fn redo(&mut self, current: T) -> Option<T> {
+        let next = self.redo.pop()?;
+        self.push_undo(current);
+        Some(next)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Remove all stored states.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_undo_redo() {
+        let mut stack = UndoStack::new(10);
+        stack.push(1);
+        stack.push(2);
+        assert_eq!(stack.undo(3), Some(2));
+        assert_eq!(stack.undo(2), Some(1));
+        assert_eq!(stack.undo(1), None);
+        assert_eq!(stack.redo(1), Some(2));
+        assert_eq!(stack.redo(2), Some(3));
+        assert_eq!(stack.redo(3), None);
+    }
+
+    #[test]
+    fn test_push_clears_redo() {
+        let mut stack = UndoStack::new(10);
+        stack.push(1);
+        assert_eq!(stack.undo(2), Some(1));
+        stack.push(1);
+        assert!(!stack.can_redo());
+    }
+
+    #[test]
+    fn test_depth() {
+        let mut stack = UndoStack::new(2);
+        stack.push(1);
+        stack.push(2);
+        stack.push(3);
+        assert_eq!(stack.undo(4), Some(3));
+        assert_eq!(stack.undo(3), Some(2));
+        assert_eq!(stack.undo(2), None);
+        stack.set_depth(0);
+        assert!(!stack.can_redo());
+        stack.push(1);
+        assert!(!stack.can_undo());
+    }
+
+    #[test]
+    fn test_redo_depth() {
+        let mut stack = UndoStack::new(3);
+        stack.push(1);
+        stack.push(2);
+        stack.push(3);
+        assert_eq!(stack.undo(4), Some(3));
+        stack.set_depth(1);
+        assert_eq!(stack.redo(3), Some(4));
+        assert_eq!(stack.undo(4), Some(3));
+        assert_eq!(stack.undo(3), None);
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use notify_debouncer_mini::{
+    new_debouncer,
+    notify::{self, RecommendedWatcher, RecursiveMode},
+    DebounceEventResult, Debouncer,
+};
+use tracing::{debug, error};
+
+use crate::{
+    cue,
+    library::Library,
+    playback::{is_supported, Song},
+    scanner::{wildcard_match, ScanEvent, Scanner},
+};
+
+/// How long a file has to stay unchanged before it's read, so a file that's still being copied is only read once.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Sent by a [`LibraryWatcher`] when songs in the watched directories change.
+#[derive(Debug)]
+pub enum WatchEvent {
+    /// A song was added or its file changed.
+    Changed(Box<Song>),
+    /// A file or directory was removed, songs at or under the path don't exist anymore.
+    Removed(PathBuf),
+    /// A changed file couldn't be read.
+    Error { path: PathBuf, error: String },
+}
+
+/// Watches directories for added, changed and removed songs, using inotify on Linux.
+///
+/// Files are read on the watcher's thread, so the receiver only gets [`Song`]s that are ready to be added to a [`Library`].
+/// Watching stops when the watcher is dropped.
+pub struct LibraryWatcher {
+    debouncer: Debouncer<RecommendedWatcher>,
+    /// The canonical paths of the watched directories, ignore patterns only apply to paths inside them.
+    roots: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+impl LibraryWatcher {
+    /// Create a watcher that waits `debounce` after the last change to a file before reading it.
+    ///
+    /// Files and directories with a name matching one of the `ignore` patterns are skipped, see [`Scanner::ignore`].
+    /// Album art of the songs read is cached in `artwork_dir`, see [`Song::from_path`].
+    pub fn new(
+        debounce: Duration,
+        ignore: Vec<String>,
+        artwork_dir: Option<PathBuf>,
+    ) -> notify::Result<(Self, Receiver<WatchEvent>)> {
+        let (event_tx, event_rx) = mpsc::channel();
+        let roots = Arc::new(Mutex::new(Vec::new()));
+        let watched_roots = roots.clone();
+        let debouncer = new_debouncer(debounce, move |result: DebounceEventResult| match result {
+            Ok(events) => {
+                for event in events {
+                    if !is_ignored(&ignore, &watched_roots.lock().unwrap(), &event.path) {
+                        handle_change(event.path, &ignore, artwork_dir.as_deref(), &event_tx);
+                    }
+                }
+            }
+            Err(e) => error!("Error watching songs: {e}"),
+        })?;
+        Ok((Self { debouncer, roots }, event_rx))
+    }
+
+    /// Start watching the directory at `path` and its subdirectories.
+    ///
+    /// The path is canonicalized first, so the changed paths match the library's.
+    pub fn watch(&mut self, path: &Path) -> notify::Result<()> {
+        let path = path.canonicalize()?;
+        debug!("Watching '{}'", path.display());
+        self.debouncer
+            .watcher()
+            .watch(&path, RecursiveMode::Recursive)?;
+        self.roots.lock().unwrap().push(path);
+        Ok(())
+    }
+
+    /// Stop watching the directory at `path`.
+    pub fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+        // The directory might not exist anymore
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.debouncer.watcher().unwatch(&path)?;
+        self.roots.lock().unwrap().retain(|root| *root != path);
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for LibraryWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LibraryWatcher").finish_non_exhaustive()
+    }
+}
+
+/// Check if any component of `path` inside the watched root it's in matches one of the `ignore` patterns,
+/// so the directories above the root, like a hidden home directory, don't matter.
+///
+/// Only the file name is checked if `path` isn't in one of the `roots`.
+fn is_ignored(ignore: &[String], roots: &[PathBuf], path: &Path) -> bool {
+    let relative = roots
+        .iter()
+        .filter_map(|root| path.strip_prefix(root).ok())
+        .min_by_key(|relative| relative.iter().count())
+        .or_else(|| path.file_name().map(Path::new))
+        .unwrap_or(path);
+    relative
+        .iter()
+        .filter_map(|name| name.to_str())
+        .any(|name| ignore.iter().any(|pattern| wildcard_match(pattern, name)))
+}
+
+/// Turn a debounced change to `path` into [`WatchEvent`]s.
+///
+/// A changed cue sheet, or an audio file split by one, sends the sheet's tracks.
+fn handle_change(
+    path: PathBuf,
+    ignore: &[String],
+    artwork_dir: Option<&Path>,
+    events: &Sender<WatchEvent>,
+) {
+    if !path.exists() {
+        let _ = events.send(WatchEvent::Removed(path));
+    } else if path.is_dir() {
+        // A directory that was moved or copied in, its files don't get their own events
+        let scanner = ignore
+            .iter()
+            .fold(Scanner::new([path]).threads(1), |scanner, pattern| {
+                scanner.ignore(pattern.clone())
+            });
+        let mut cache = Library::default();
+        cache.set_artwork_dir(artwork_dir.map(Path::to_path_buf));
+        let (_, scan_events) = scanner.start(cache);
+        for event in scan_events {
+            let event = match event {
+                ScanEvent::Song(song) => WatchEvent::Changed(song),
+                ScanEvent::Error { path, error } => WatchEvent::Error { path, error },
+                _ => continue,
+            };
+            let _ = events.send(event);
+        }
+    } else if let Some(sheet) = cue::is_cue_sheet(&path)
+        .then(|| path.clone())
+        .or_else(|| cue::sheet_referencing(&path).filter(|_| is_supported(&path)))
+    {
+        match cue::read_songs(&sheet, artwork_dir) {
+            Ok(songs) => {
+                for song in songs {
+                    let _ = events.send(WatchEvent::Changed(Box::new(song)));
+                }
+            }
+            Err(e) => {
+                let _ = events.send(WatchEvent::Error {
+                    path: sheet,
+                    error: e.to_string(),
+                });
+            }
+        }
+    } else if is_supported(&path) {
+        let event = match Song::from_file(path.clone(), artwork_dir) {
+            Ok(song) => WatchEvent::Changed(Box::new(song)),
+            Err(e) => WatchEvent::Error {
+                path,
+                error: e.to_string(),
+            },
+        };
+        let _ = events.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_handle_change() {
+        let dir = std::env::temp_dir().join("amuseing-test-watcher");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("album")).unwrap();
+        fs::write(dir.join("album/1.mp3"), b"not audio").unwrap();
+        fs::write(dir.join("notes.txt"), b"").unwrap();
+        let (tx, rx) = mpsc::channel();
+
+        handle_change(dir.join("notes.txt"), &[], None, &tx);
+        handle_change(dir.join("gone.mp3"), &[], None, &tx);
+        handle_change(dir.join("album"), &[], None, &tx);
+        drop(tx);
+        let events: Vec<WatchEvent> = rx.iter().collect();
+        assert!(matches!(&events[..], [
+            WatchEvent::Removed(removed),
+            WatchEvent::Error { path, .. },
+        ] if *removed == dir.join("gone.mp3") && path.ends_with("album/1.mp3")));
+        let ignore = [".*".to_string()];
+        let roots = [PathBuf::from("/home/user/.music")];
+        assert!(is_ignored(
+            &ignore,
+            &roots,
+            Path::new("/home/user/.music/.trash/1.mp3")
+        ));
+        assert!(!is_ignored(
+            &ignore,
+            &roots,
+            Path::new("/home/user/.music/album/1.mp3")
+        ));
+        assert!(is_ignored(&ignore, &[], Path::new("/music/.1.mp3")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}